@@ -0,0 +1,144 @@
+use crate::{
+    ast::{AsmInstr, AsmOperand},
+    instructions::{Cond, Instr, Value},
+    register_allocator::NUM_REGISTERS,
+    target::{Shifts, Target},
+    types::IntType,
+};
+
+/// Number of the register called `name`, `r0` up to the last allocatable register.
+pub fn register(name: &str) -> Option<u8> {
+    let number = name.strip_prefix('r')?;
+
+    match number.parse::<u8>() {
+        Ok(n) if (n as usize) < NUM_REGISTERS && (n == 0 || !number.starts_with('0')) => Some(n),
+        _ => None,
+    }
+}
+
+/// Translates one line of an `asm` block into the instruction it names, checking that the
+/// target implements it.
+///
+/// Registers are written `rN`, `ld` and `st` take the address first and read an IO port when
+/// it is a number. Branches are not available, control flow stays with the compiler.
+pub fn assemble(instr: &AsmInstr, target: &Target) -> Result<Instr, String> {
+    let mnemonic = instr.mnemonic.as_str();
+
+    let reg = |i: usize| match &instr.operands[i] {
+        AsmOperand::Register(name) => {
+            register(name).map(Value::Reg).ok_or_else(|| format!("unknown register `{}`", name))
+        }
+        AsmOperand::Immediate(_) => Err(format!("operand {} of `{}` must be a register", i + 1, mnemonic)),
+    };
+
+    let imm = |i: usize| match &instr.operands[i] {
+        AsmOperand::Immediate(n) => {
+            let signed = IntType { signed: true, bits: target.word_bits };
+            let unsigned = IntType { signed: false, bits: target.word_bits };
+
+            match signed.contains(*n) || unsigned.contains(*n) {
+                true => Ok(*n as i32),
+                false => Err(format!("{} does not fit the {}-bit machine word", n, target.word_bits)),
+            }
+        }
+        AsmOperand::Register(_) => Err(format!("operand {} of `{}` must be a number", i + 1, mnemonic)),
+    };
+
+    let address = |i: usize| match &instr.operands[i] {
+        AsmOperand::Immediate(_) => imm(i).map(Value::Const),
+        AsmOperand::Register(_) => reg(i),
+    };
+
+    let arity = match mnemonic {
+        "nop" | "clc" => 0,
+        "mov" | "ldi" | "ld" | "st" | "not" => 2,
+        _ => 3,
+    };
+
+    let requires = |available: bool| match available {
+        true => Ok(()),
+        false => Err(format!("`{}` is not available on target `{}`", mnemonic, target.name)),
+    };
+
+    let binary = |make: fn(Value, Value, Value) -> Instr| -> Result<Instr, String> { Ok(make(reg(0)?, reg(1)?, reg(2)?)) };
+
+    let known = matches!(
+        mnemonic,
+        "nop" | "clc" | "mov" | "ldi" | "ld" | "st" | "not" | "addi" | "add" | "sub" | "adc" | "sbb" | "mul" | "div"
+            | "mod" | "divu" | "modu" | "and" | "or" | "xor" | "shl" | "shr" | "shru"
+    ) || mnemonic.starts_with("cmp.");
+
+    if !known {
+        return Err(format!("unknown instruction `{}`", mnemonic));
+    }
+
+    if instr.operands.len() != arity {
+        return Err(format!("`{}` takes {} operands, found {}", mnemonic, arity, instr.operands.len()));
+    }
+
+    match mnemonic {
+        "nop" => Ok(Instr::Nop),
+        "clc" => requires(target.carry).map(|_| Instr::ClearCarry),
+        "mov" => Ok(Instr::Move { dst: reg(0)?, src: reg(1)? }),
+        "ldi" => Ok(Instr::Immediate { dst: reg(0)?, value: Value::Const(imm(1)?) }),
+        "ld" => Ok(Instr::Load { dst: reg(0)?, src: address(1)? }),
+        "st" => Ok(Instr::Store { dst: address(0)?, src: reg(1)? }),
+        "addi" => Ok(Instr::AddImmediate { dst: reg(0)?, lhs: reg(1)?, imm: imm(2)? }),
+        "not" => {
+            requires(target.has_logic("not"))?;
+            Ok(Instr::Not { dst: reg(0)?, src: reg(1)? })
+        }
+
+        "add" => binary(|dst, lhs, rhs| Instr::Add { dst, lhs, rhs }),
+        "sub" => binary(|dst, lhs, rhs| Instr::Sub { dst, lhs, rhs }),
+        "adc" => {
+            requires(target.carry)?;
+            binary(|dst, lhs, rhs| Instr::AddCarry { dst, lhs, rhs })
+        }
+        "sbb" => {
+            requires(target.carry)?;
+            binary(|dst, lhs, rhs| Instr::SubBorrow { dst, lhs, rhs })
+        }
+        "mul" => {
+            requires(target.multiply)?;
+            binary(|dst, lhs, rhs| Instr::Mul { dst, lhs, rhs })
+        }
+        "div" | "mod" | "divu" | "modu" => {
+            requires(target.divide)?;
+            binary(match mnemonic {
+                "div" => |dst, lhs, rhs| Instr::Div { dst, lhs, rhs },
+                "mod" => |dst, lhs, rhs| Instr::Mod { dst, lhs, rhs },
+                "divu" => |dst, lhs, rhs| Instr::DivU { dst, lhs, rhs },
+                _ => |dst, lhs, rhs| Instr::ModU { dst, lhs, rhs },
+            })
+        }
+        "and" | "or" | "xor" => {
+            requires(target.has_logic(mnemonic))?;
+            binary(match mnemonic {
+                "and" => |dst, lhs, rhs| Instr::And { dst, lhs, rhs },
+                "or" => |dst, lhs, rhs| Instr::Or { dst, lhs, rhs },
+                _ => |dst, lhs, rhs| Instr::Xor { dst, lhs, rhs },
+            })
+        }
+        // Shifts by a register amount, which targets with single-bit shifts lack.
+        "shl" | "shr" | "shru" => {
+            requires(target.shifts == Shifts::Any)?;
+            binary(match mnemonic {
+                "shl" => |dst, lhs, rhs| Instr::Shl { dst, lhs, rhs },
+                "shr" => |dst, lhs, rhs| Instr::Shr { dst, lhs, rhs },
+                _ => |dst, lhs, rhs| Instr::ShrU { dst, lhs, rhs },
+            })
+        }
+
+        _ => {
+            let name = &mnemonic["cmp.".len()..];
+            let cond = Cond::ALL
+                .into_iter()
+                .find(|cond| cond.name() == name)
+                .ok_or_else(|| format!("unknown comparison `{}`", name))?;
+
+            requires(target.supports(cond))?;
+            Ok(Instr::Cmp { cond, dst: reg(0)?, lhs: reg(1)?, rhs: reg(2)? })
+        }
+    }
+}
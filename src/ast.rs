@@ -1,8 +1,9 @@
-use crate::token::TokenType;
+use crate::{token::TokenType, types::{IntType, Type}};
 
 #[derive(Debug)]
 pub enum Expr {
-    Number(i32),
+    Number(i64),
+    Bool(bool),
     Variable(String),
     Binary {
         left: Box<ExprNode>,
@@ -13,26 +14,165 @@ pub enum Expr {
         operator: TokenType,
         operand: Box<ExprNode>,
     },
+    Call {
+        callee: String,
+        args: Vec<ExprNode>,
+    },
+    Index {
+        array: Box<ExprNode>,
+        index: Box<ExprNode>,
+    },
+    /// `Point { x: 1, y: 2 }`, only allowed as the value of a declaration or assignment.
+    StructLiteral {
+        name: String,
+        fields: Vec<(ExprNode, ExprNode)>,
+    },
+    /// `points[i].x`. Fields of struct variables are plain names like `p.x`.
+    Field {
+        base: Box<ExprNode>,
+        field: String,
+    },
+    /// `&name`, `&array[i]` or `&array[i].field`, the RAM address of a variable or element.
+    AddressOf {
+        target: Box<ExprNode>,
+    },
+    /// `*pointer`, the word stored at the address `pointer` holds.
+    Deref {
+        pointer: Box<ExprNode>,
+    },
 }
 
 #[derive(Debug)]
 pub enum Stmt {
     Declare {
+        target: ExprNode,
+        /// Type annotation, the type is inferred from `value` without one.
+        ty: Option<Type>,
+        value: ExprNode,
+    },
+    /// `import name;`, making the `pub` items of `name.tch` available as `name.item`.
+    Import {
+        module: ExprNode,
+    },
+    /// `const NAME = expr;`, evaluated at compile time.
+    Const {
         target: ExprNode,
         value: ExprNode,
     },
+    /// `port name: type @ address;`, binding a name to a memory-mapped IO address.
+    Port {
+        target: ExprNode,
+        ty: Option<IntType>,
+        address: ExprNode,
+    },
+    /// `struct Name { a, b }`, a group of machine words stored together.
+    Struct {
+        name: ExprNode,
+        fields: Vec<ExprNode>,
+    },
+    /// `enum Name { A, B = 5, C }`, constants named `Name.A` and so on. Variants without a
+    /// value take the one after the previous variant's, starting at 0.
+    Enum {
+        name: ExprNode,
+        variants: Vec<(ExprNode, Option<ExprNode>)>,
+    },
+    /// `match value { A, B => { .. } _ => { .. } }`, running the first arm matching `value`.
+    Match {
+        value: ExprNode,
+        arms: Vec<ArmNode>,
+    },
+    /// `let name[length];`, `let name[length] = [...];` or `let name = [...];`,
+    /// holding structs with `let name[length]: Struct;`. A string literal gives the values
+    /// as its characters followed by a 0. With `const` instead of `let` the array is a
+    /// read-only table of constants.
+    DeclareArray {
+        target: ExprNode,
+        length: Option<ExprNode>,
+        element: Option<ExprNode>,
+        values: Option<Vec<ExprNode>>,
+        constant: bool,
+    },
     Assign {
         target: ExprNode,
         value: ExprNode,
     },
+    /// `target op= value`, with `target++` and `target--` meaning `+= 1` and `-= 1`.
+    /// `operator` is the plain binary operator, the target is evaluated only once.
+    CompoundAssign {
+        target: ExprNode,
+        operator: TokenType,
+        value: ExprNode,
+    },
     If {
         condition: ExprNode,
         body: Vec<StmtNode>,
+        /// `else if` chains are stored as a single nested `If` in here.
+        else_body: Option<Vec<StmtNode>>,
     },
     While {
+        /// `name: while ..`, letting nested loops `break name;` and `continue name;`.
+        label: Option<ExprNode>,
         condition: ExprNode,
         body: Vec<StmtNode>,
     },
+    /// `for var in start..end { }`, counting `var` up by one from `start` to just below `end`,
+    /// or up to and including it with `..=`. `end` is evaluated once, before the first iteration.
+    For {
+        label: Option<ExprNode>,
+        var: ExprNode,
+        start: ExprNode,
+        end: ExprNode,
+        inclusive: bool,
+        body: Vec<StmtNode>,
+    },
+    /// Leaves the innermost loop, or the one with the given label.
+    Break {
+        label: Option<ExprNode>,
+    },
+    /// Starts the next iteration of the innermost loop, or the one with the given label.
+    Continue {
+        label: Option<ExprNode>,
+    },
+    Function {
+        name: ExprNode,
+        params: Vec<ExprNode>,
+        body: Vec<StmtNode>,
+    },
+    Return {
+        value: Option<ExprNode>,
+    },
+    Expression {
+        expr: ExprNode,
+    },
+    /// `asm in(r1: x) out(r0: y) clobber(r2) { ... }`, copied into the program as written.
+    /// Registers are named like variables, `r0`, `r1` and so on.
+    Asm {
+        inputs: Vec<(ExprNode, ExprNode)>,
+        outputs: Vec<(ExprNode, ExprNode)>,
+        clobbers: Vec<ExprNode>,
+        body: Vec<AsmNode>,
+    },
+}
+
+/// An arm of a `match`, taken when the value equals one of the constant `patterns`.
+/// The `_` arm has no patterns and matches every value.
+#[derive(Debug)]
+pub struct MatchArm {
+    pub patterns: Option<Vec<ExprNode>>,
+    pub body: Vec<StmtNode>,
+}
+
+/// An instruction of an `asm` block such as `add r0, r1, r2;`.
+#[derive(Debug)]
+pub struct AsmInstr {
+    pub mnemonic: String,
+    pub operands: Vec<AsmOperand>,
+}
+
+#[derive(Debug)]
+pub enum AsmOperand {
+    Register(String),
+    Immediate(i64),
 }
 
 #[derive(Debug)]
@@ -43,4 +183,6 @@ pub struct AstNode<T> {
 }
 
 pub type ExprNode = AstNode<Expr>;
-pub type StmtNode = AstNode<Stmt>;
\ No newline at end of file
+pub type StmtNode = AstNode<Stmt>;
+pub type AsmNode = AstNode<AsmInstr>;
+pub type ArmNode = AstNode<MatchArm>;
\ No newline at end of file
@@ -0,0 +1,239 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    instructions::{Instr, Label, Value},
+    layout::Layout,
+    symbols::{SymbolKind, SymbolTable},
+    target::Target,
+};
+
+/// Writes the finished program as assembly text, and a map file listing where every
+/// array, addressed variable, constant table, memory home and port was placed.
+pub struct Emitter<'a> {
+    layout: &'a Layout,
+    symbols: &'a SymbolTable,
+    target: &'a Target,
+    /// Label of everything placed in memory, keyed by symbol id.
+    names: HashMap<u32, String>,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(layout: &'a Layout, symbols: &'a SymbolTable, target: &'a Target) -> Self {
+        let ids: Vec<u32> = layout.arrays().into_iter().chain(layout.tables()).map(|(id, _, _)| id).collect();
+
+        // Names are only unique within their scope, repeated ones get the symbol id appended.
+        let names = ids
+            .iter()
+            .map(|&id| {
+                let name = symbols.name_of(id);
+                match ids.iter().filter(|&&other| symbols.name_of(other) == name).count() {
+                    1 => (id, name.to_string()),
+                    _ => (id, format!("{}_{}", name, id)),
+                }
+            })
+            .collect();
+
+        Emitter { layout, symbols, target, names }
+    }
+
+    /// The program as assembly: the tables in ROM, the cells in RAM and then the code.
+    pub fn assembly(&self, instrs: &[Instr]) -> String {
+        let mut out = String::new();
+
+        if !self.layout.rom.is_empty() {
+            out.push_str("    .rodata\n");
+            for (id, address, length) in self.layout.tables() {
+                let words = &self.layout.rom[address as usize..address as usize + length as usize];
+                let words: Vec<String> = words.iter().map(|&word| self.target.unsigned(word).to_string()).collect();
+
+                writeln!(out, "{}:  ; address {}", self.names[&id], address).unwrap();
+                writeln!(out, "    .word {}", words.join(", ")).unwrap();
+            }
+        }
+
+        // Everything in RAM goes to the address the layout gave it, around the ports.
+        let mut cells: Vec<(i32, String, u32, &str)> = self
+            .layout
+            .arrays()
+            .into_iter()
+            .map(|(id, address, length)| (address, self.names[&id].clone(), length, self.note(id)))
+            .chain(self.layout.homes().into_iter().map(|(home, address)| (address, self.operand(&home), 1, "")))
+            .collect();
+        cells.sort_by_key(|&(address, ..)| address);
+
+        out.push_str("    .data\n");
+        let mut next = None;
+        for (address, name, length, note) in cells {
+            if next != Some(address) {
+                writeln!(out, "    .org {}", address).unwrap();
+            }
+            writeln!(out, "{}:{}", name, note).unwrap();
+            writeln!(out, "    .space {}", length).unwrap();
+            next = Some(address + length as i32);
+        }
+
+        out.push_str("    .text\n");
+        for instr in instrs {
+            match instr {
+                Instr::Label(label) => writeln!(out, "{}:", Self::label(label)).unwrap(),
+                Instr::Asm { body, .. } => {
+                    for instr in body {
+                        writeln!(out, "    {}", self.instr(instr)).unwrap();
+                    }
+                }
+                _ => writeln!(out, "    {}", self.instr(instr)).unwrap(),
+            }
+        }
+
+        out
+    }
+
+    /// Where everything was placed, ROM first, each segment ordered by address.
+    pub fn map(&self) -> String {
+        let mut out = String::new();
+
+        if !self.layout.rom.is_empty() {
+            writeln!(out, "ROM, {} words", self.layout.rom.len()).unwrap();
+            for (id, address, length) in self.layout.tables() {
+                writeln!(out, "  0x{:04x}  {:>5}  {:<16} const table", address, length, self.names[&id]).unwrap();
+            }
+        }
+
+        let mut entries: Vec<(i32, u32, String, String)> = self
+            .layout
+            .arrays()
+            .into_iter()
+            .map(|(id, address, length)| {
+                let kind = match self.symbols.kind_of(id) {
+                    SymbolKind::Array { .. } if self.symbols.tables.contains_key(&id) => "const table, copied at startup",
+                    SymbolKind::Array { .. } => "array",
+                    _ => "variable",
+                };
+                (address, length, self.names[&id].clone(), kind.to_string())
+            })
+            .collect();
+
+        for (home, address) in self.layout.homes() {
+            let kind = match home {
+                Value::Var(id) => self.symbols.get(id).map(|symbol| format!("memory home of `{}`", symbol.name)),
+                _ => None,
+            };
+            let kind = kind.unwrap_or_else(|| "memory home of a temporary".to_string());
+            entries.push((address, 1, self.operand(&home), kind));
+        }
+
+        for symbol in self.symbols.scopes.iter().flat_map(|scope| scope.symbols.values()) {
+            if let SymbolKind::Port { address } = symbol.kind {
+                entries.push((address, 1, symbol.name.clone(), "port".to_string()));
+            }
+        }
+        entries.sort();
+
+        writeln!(out, "RAM, {} words", self.layout.size).unwrap();
+        for (address, length, name, kind) in entries {
+            writeln!(out, "  0x{:04x}  {:>5}  {:<16} {}", address, length, name, kind).unwrap();
+        }
+
+        out
+    }
+
+    fn note(&self, id: u32) -> &'static str {
+        match self.symbols.tables.contains_key(&id) {
+            true => "  ; copied by the startup code",
+            false => "",
+        }
+    }
+
+    fn instr(&self, instr: &Instr) -> String {
+        let op = |value: &Value| self.operand(value);
+
+        match instr {
+            Instr::Immediate { dst, value } => format!("ldi {}, {}", op(dst), op(value)),
+            Instr::Move { dst, src } => format!("mov {}, {}", op(dst), op(src)),
+            Instr::Load { dst, src } => format!("ld {}, {}", op(dst), op(src)),
+            Instr::Store { dst, src } => format!("st {}, {}", op(dst), op(src)),
+            Instr::LoadRom { dst, src } => format!("ldr {}, {}", op(dst), op(src)),
+            Instr::AddImmediate { dst, lhs, imm } => format!("addi {}, {}, {}", op(dst), op(lhs), imm),
+            Instr::Not { dst, src } => format!("not {}, {}", op(dst), op(src)),
+            Instr::Cmp { cond, dst, lhs, rhs } => format!("cmp.{} {}, {}, {}", cond.name(), op(dst), op(lhs), op(rhs)),
+            Instr::Add { dst, lhs, rhs } => format!("add {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Sub { dst, lhs, rhs } => format!("sub {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::AddCarry { dst, lhs, rhs } => format!("adc {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::SubBorrow { dst, lhs, rhs } => format!("sbb {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Mul { dst, lhs, rhs } => format!("mul {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Div { dst, lhs, rhs } => format!("div {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Mod { dst, lhs, rhs } => format!("mod {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::DivU { dst, lhs, rhs } => format!("divu {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::ModU { dst, lhs, rhs } => format!("modu {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::And { dst, lhs, rhs } => format!("and {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Or { dst, lhs, rhs } => format!("or {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Xor { dst, lhs, rhs } => format!("xor {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Shl { dst, lhs, rhs } => format!("shl {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::Shr { dst, lhs, rhs } => format!("shr {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::ShrU { dst, lhs, rhs } => format!("shru {}, {}, {}", op(dst), op(lhs), op(rhs)),
+            Instr::ClearCarry => "clc".to_string(),
+            Instr::Jump(target) => format!("jmp {}", Self::label(target)),
+            Instr::JumpIfFalse { cond, target } => format!("jz {}, {}", op(cond), Self::label(target)),
+            Instr::JumpTable { index, targets } => {
+                let targets: Vec<String> = targets.iter().map(Self::label).collect();
+                format!("jt {}, {}", op(index), targets.join(", "))
+            }
+            Instr::Call(target) => format!("call {}", Self::label(target)),
+            Instr::Return => "ret".to_string(),
+            Instr::Halt => "halt".to_string(),
+            Instr::Trap => "trap".to_string(),
+            Instr::Nop => "nop".to_string(),
+            Instr::Label(label) => format!("{}:", Self::label(label)),
+            Instr::Asm { .. } => unreachable!("asm blocks are written line by line"),
+        }
+    }
+
+    /// Registers are written like in `asm` blocks, placed cells by their label.
+    fn operand(&self, value: &Value) -> String {
+        match value {
+            Value::Reg(reg) => format!("r{}", reg),
+            Value::Const(n) => n.to_string(),
+            Value::Ptr(id) => self.names[id].clone(),
+            Value::Var(id) => format!("v{}", id),
+            Value::Temp(id) => format!("t{}", id),
+        }
+    }
+
+    fn label(label: &Label) -> String {
+        format!("L{}", label.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn arrays_moved_past_a_port_keep_their_address() {
+        let run = run("port out @ 2;\nlet a[4];\na[3] = 7;\nout = a[3];\n", "").unwrap();
+        assert!(run.assembly.contains("    .data\n    .org 3\na:\n    .space 4\n"), "{}", run.assembly);
+        assert_eq!(run.port_writes, vec![7]);
+    }
+
+    #[test]
+    fn memory_homes_are_placed_and_mapped() {
+        let source = "port out @ 2;\nlet a[4];\n\
+            let x1 = 1; let x2 = 2; let x3 = 3; let x4 = 4; let x5 = 5; let x6 = 6; let x7 = 7; let x8 = 8; let x9 = 9;\n\
+            a[3] = x1 + x2;\nout = x1 + x2 + x3 + x4 + x5 + x6 + x7 + x8 + x9 + a[3];\n";
+        let run = run(source, "").unwrap();
+
+        let homes = run.map.lines().filter(|line| line.contains("memory home of")).count();
+        assert!(homes > 0, "{}", run.map);
+        assert!(run.map.contains(&format!("RAM, {} words", 7 + homes)), "{}", run.map);
+        assert!(run.map.contains("  0x0007      1  "), "{}", run.map);
+        assert!(run.assembly.contains("a:\n    .space 4\nv"), "{}", run.assembly);
+        assert_eq!(run.port_writes, vec![48]);
+    }
+
+    #[test]
+    fn memory_homes_of_routine_variables_are_mapped() {
+        let run = run("port out @ 200;\nlet a = 7;\nlet b = 9;\nout = a * b;\n", include_str!("../example.target")).unwrap();
+        assert!(run.map.contains("memory home of a temporary"), "{}", run.map);
+        assert_eq!(run.port_writes, vec![63]);
+    }
+}
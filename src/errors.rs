@@ -18,4 +18,9 @@ impl ErrorReporter {
             err.message, source.file_name, line, col
         );
     }
+
+    /// Prints an error that has no place in the source, like one found after lowering.
+    pub fn print_message(message: &str) {
+        eprintln!("error: {}", message);
+    }
 }
\ No newline at end of file
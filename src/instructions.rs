@@ -1,8 +1,8 @@
-
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Value {
     Temp(u32),
     Var(u32),
+    /// RAM address of the array with this symbol id, a constant once the layout is known.
     Ptr(u32),
     Const(i32),
     Reg(u8),
@@ -11,22 +11,271 @@ pub enum Value {
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Label(pub u32);
 
+/// Comparison performed by `Instr::Cmp`. The `U` variants compare unsigned.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LtU,
+    LeU,
+    GtU,
+    GeU,
+}
+
+impl Cond {
+    pub const ALL: [Cond; 10] = [
+        Cond::Eq, Cond::Ne,
+        Cond::Lt, Cond::Le, Cond::Gt, Cond::Ge,
+        Cond::LtU, Cond::LeU, Cond::GtU, Cond::GeU,
+    ];
+
+    /// The condition that holds for `(rhs, lhs)` whenever this one holds for `(lhs, rhs)`.
+    pub fn swapped(self) -> Cond {
+        match self {
+            Cond::Eq => Cond::Eq,
+            Cond::Ne => Cond::Ne,
+            Cond::Lt => Cond::Gt,
+            Cond::Le => Cond::Ge,
+            Cond::Gt => Cond::Lt,
+            Cond::Ge => Cond::Le,
+            Cond::LtU => Cond::GtU,
+            Cond::LeU => Cond::GeU,
+            Cond::GtU => Cond::LtU,
+            Cond::GeU => Cond::LeU,
+        }
+    }
+
+    /// The condition that holds exactly when this one does not.
+    pub fn negated(self) -> Cond {
+        match self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Lt => Cond::Ge,
+            Cond::Le => Cond::Gt,
+            Cond::Gt => Cond::Le,
+            Cond::Ge => Cond::Lt,
+            Cond::LtU => Cond::GeU,
+            Cond::LeU => Cond::GtU,
+            Cond::GtU => Cond::LeU,
+            Cond::GeU => Cond::LtU,
+        }
+    }
+
+    /// The same ordering with the other signedness, equality is unaffected.
+    pub fn resigned(self) -> Cond {
+        match self {
+            Cond::Lt => Cond::LtU,
+            Cond::Le => Cond::LeU,
+            Cond::Gt => Cond::GtU,
+            Cond::Ge => Cond::GeU,
+            Cond::LtU => Cond::Lt,
+            Cond::LeU => Cond::Le,
+            Cond::GtU => Cond::Gt,
+            Cond::GeU => Cond::Ge,
+            cond => cond,
+        }
+    }
+
+    /// Evaluates the comparison on two values of a `word_bits` wide machine word.
+    pub fn holds(self, lhs: i32, rhs: i32, word_bits: u32) -> bool {
+        let shift = 32 - word_bits;
+        let (sl, sr) = ((lhs << shift) >> shift, (rhs << shift) >> shift);
+        let (ul, ur) = ((lhs as u32) << shift >> shift, (rhs as u32) << shift >> shift);
+
+        match self {
+            Cond::Eq => ul == ur,
+            Cond::Ne => ul != ur,
+            Cond::Lt => sl < sr,
+            Cond::Le => sl <= sr,
+            Cond::Gt => sl > sr,
+            Cond::Ge => sl >= sr,
+            Cond::LtU => ul < ur,
+            Cond::LeU => ul <= ur,
+            Cond::GtU => ul > ur,
+            Cond::GeU => ul >= ur,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Lt => "lt",
+            Cond::Le => "le",
+            Cond::Gt => "gt",
+            Cond::Ge => "ge",
+            Cond::LtU => "ltu",
+            Cond::LeU => "leu",
+            Cond::GtU => "gtu",
+            Cond::GeU => "geu",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Instr {
     Immediate { dst: Value, value: Value },
     Move { dst: Value, src: Value },
+    /// Reads memory. A `Var` or `Temp` operand names that value's memory home, anything
+    /// else holds the RAM address to read. A `Const` address is a memory-mapped IO port.
     Load { dst: Value, src: Value },
+    /// Writes memory, addressed like `Load`.
     Store { dst: Value, src: Value },
+    /// Reads a word of a constant table from program memory, on targets with `rom_loads`.
+    /// The address is a `Ptr` or a register. Program memory never changes, so these are
+    /// no memory accesses to order against stores.
+    LoadRom { dst: Value, src: Value },
 
     Add { dst: Value, lhs: Value, rhs: Value },
     Sub { dst: Value, lhs: Value, rhs: Value },
+    /// `lhs + rhs + carry`, setting the carry flag to the carry out of the word.
+    AddCarry { dst: Value, lhs: Value, rhs: Value },
+    /// `lhs - rhs - carry`, setting the carry flag when the subtraction borrowed.
+    SubBorrow { dst: Value, lhs: Value, rhs: Value },
+    ClearCarry,
     AddImmediate { dst: Value, lhs: Value, imm: i32 },
     Mul { dst: Value, lhs: Value, rhs: Value },
+    /// Signed division, rounding towards zero.
+    Div { dst: Value, lhs: Value, rhs: Value },
+    /// Signed remainder, taking the sign of `lhs`.
+    Mod { dst: Value, lhs: Value, rhs: Value },
+    DivU { dst: Value, lhs: Value, rhs: Value },
+    ModU { dst: Value, lhs: Value, rhs: Value },
 
-    CmpGt { dst: Value, lhs: Value, rhs: Value },
+    And { dst: Value, lhs: Value, rhs: Value },
+    Or { dst: Value, lhs: Value, rhs: Value },
+    Xor { dst: Value, lhs: Value, rhs: Value },
+    Not { dst: Value, src: Value },
+    /// Shifts `lhs` left by `rhs` bits. The amount is read as an unsigned word, so a negative
+    /// one is a large amount, and amounts of a word or more shift every bit out.
+    Shl { dst: Value, lhs: Value, rhs: Value },
+    /// Arithmetic right shift, replicating the sign bit. The amount is unsigned like for
+    /// `Shl`, amounts of a word or more leave only copies of the sign bit.
+    Shr { dst: Value, lhs: Value, rhs: Value },
+    /// Logical right shift, shifting in zeros. The amount is unsigned like for `Shl`,
+    /// amounts of a word or more give 0.
+    ShrU { dst: Value, lhs: Value, rhs: Value },
+
+    Cmp { cond: Cond, dst: Value, lhs: Value, rhs: Value },
 
     Jump(Label),
     JumpIfFalse { cond: Value, target: Label },
+    /// Jumps to `targets[index]` through a table of addresses stored with the code.
+    /// `index` must be checked to be in range beforehand.
+    JumpTable { index: Value, targets: Vec<Label> },
+    Call(Label),
+    Return,
+    Halt,
+    /// Stops the program with an error, used by failed runtime checks.
+    Trap,
 
     Label(Label),
-}
\ No newline at end of file
+    Nop,
+
+    /// An `asm` block, whose `body` works on registers only. `inputs` are placed in their
+    /// registers before it runs and `outputs` taken from theirs afterwards. Every other
+    /// register the body writes is listed in `clobbers`.
+    Asm {
+        body: Vec<Instr>,
+        inputs: Vec<(u8, Value)>,
+        outputs: Vec<(u8, Value)>,
+        clobbers: Vec<u8>,
+    },
+}
+
+impl Instr {
+    /// The value written by this instruction, if any.
+    /// A `Store` writes memory rather than a value and therefore has none.
+    pub fn def(&self) -> Option<Value> {
+        match self {
+            Instr::Immediate { dst, .. }
+            | Instr::Move { dst, .. }
+            | Instr::Load { dst, .. }
+            | Instr::LoadRom { dst, .. }
+            | Instr::Add { dst, .. }
+            | Instr::Sub { dst, .. }
+            | Instr::AddCarry { dst, .. }
+            | Instr::SubBorrow { dst, .. }
+            | Instr::AddImmediate { dst, .. }
+            | Instr::Mul { dst, .. }
+            | Instr::Div { dst, .. }
+            | Instr::Mod { dst, .. }
+            | Instr::DivU { dst, .. }
+            | Instr::ModU { dst, .. }
+            | Instr::And { dst, .. }
+            | Instr::Or { dst, .. }
+            | Instr::Xor { dst, .. }
+            | Instr::Not { dst, .. }
+            | Instr::Shl { dst, .. }
+            | Instr::Shr { dst, .. }
+            | Instr::ShrU { dst, .. }
+            | Instr::Cmp { dst, .. } => Some(*dst),
+            _ => None,
+        }
+    }
+
+    /// The values read by this instruction.
+    pub fn uses(&self) -> Vec<Value> {
+        match self {
+            Instr::Move { src, .. } | Instr::Load { src, .. } | Instr::LoadRom { src, .. } | Instr::Not { src, .. } => vec![*src],
+            Instr::Store { dst, src } => vec![*src, *dst],
+            Instr::Add { lhs, rhs, .. }
+            | Instr::Sub { lhs, rhs, .. }
+            | Instr::AddCarry { lhs, rhs, .. }
+            | Instr::SubBorrow { lhs, rhs, .. }
+            | Instr::Mul { lhs, rhs, .. }
+            | Instr::Div { lhs, rhs, .. }
+            | Instr::Mod { lhs, rhs, .. }
+            | Instr::DivU { lhs, rhs, .. }
+            | Instr::ModU { lhs, rhs, .. }
+            | Instr::And { lhs, rhs, .. }
+            | Instr::Or { lhs, rhs, .. }
+            | Instr::Xor { lhs, rhs, .. }
+            | Instr::Shl { lhs, rhs, .. }
+            | Instr::Shr { lhs, rhs, .. }
+            | Instr::ShrU { lhs, rhs, .. }
+            | Instr::Cmp { lhs, rhs, .. } => vec![*lhs, *rhs],
+            Instr::AddImmediate { lhs, .. } => vec![*lhs],
+            Instr::JumpIfFalse { cond, .. } => vec![*cond],
+            Instr::JumpTable { index, .. } => vec![*index],
+            Instr::Asm { inputs, .. } => inputs.iter().map(|&(_, value)| value).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Every value written by this instruction, which is more than one for `Asm`.
+    pub fn defs(&self) -> Vec<Value> {
+        match self {
+            Instr::Asm { outputs, .. } => outputs.iter().map(|&(_, value)| value).collect(),
+            _ => self.def().into_iter().collect(),
+        }
+    }
+
+    /// Whether this instruction reads or writes the carry flag.
+    pub fn uses_carry(&self) -> bool {
+        matches!(self, Instr::AddCarry { .. } | Instr::SubBorrow { .. } | Instr::ClearCarry)
+    }
+
+    pub fn is_memory_access(&self) -> bool {
+        matches!(self, Instr::Load { .. } | Instr::Store { .. })
+    }
+
+    /// Whether this accesses an IO port. Those have side effects, so they are never
+    /// removed or moved across another memory access.
+    pub fn is_volatile(&self) -> bool {
+        matches!(self, Instr::Load { src: Value::Const(_), .. } | Instr::Store { dst: Value::Const(_), .. })
+    }
+
+    /// Whether this instruction transfers control, subject to the target's delay slots.
+    pub fn is_branch(&self) -> bool {
+        matches!(self, Instr::Jump(_) | Instr::JumpIfFalse { .. } | Instr::JumpTable { .. } | Instr::Call(_) | Instr::Return)
+    }
+
+    pub fn is_terminator(&self) -> bool {
+        self.is_branch() || matches!(self, Instr::Halt | Instr::Trap)
+    }
+}
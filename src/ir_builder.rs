@@ -1,20 +1,178 @@
-use crate::{ast::{Expr, Stmt, StmtNode}, instructions::{Instr, Label, Value}, symbols::SymbolTable, token::TokenType};
+use std::collections::{HashMap, HashSet};
+
+use crate::{asm, ast::{Expr, ExprNode, Stmt, StmtNode}, errors::CompileError, instructions::{Cond, Instr, Label, Value}, layout::Layout, modules::Module, symbols::{SymbolKind, SymbolTable}, target::Target, token::TokenType, types::IntType};
+
+/// A `for` loop in the generated code, kept for optimisations on its induction variable.
+/// The body starts at `head`, `latch` steps `counter` by one and branches back to `head`.
+pub struct CountedLoop {
+    /// The induction variable, changed by nothing but the latch.
+    pub counter: Value,
+    /// Bound the counter is compared against, fixed for the whole loop.
+    pub limit: Value,
+    pub inclusive: bool,
+    pub head: Label,
+    pub latch: Label,
+}
+
+/// How a `match` was compiled, kept for the optimisation report.
+pub struct MatchDispatch {
+    /// Position of the matched value.
+    pub position: usize,
+    pub source_id: usize,
+    /// Number of values the patterns match.
+    pub values: usize,
+    /// Entries a jump table from the smallest to the largest of them would need.
+    pub span: i64,
+    /// Estimated cycles to reach an arm through a chain of compares, on average.
+    pub chain_cycles: u32,
+    /// Estimated cycles to reach an arm through a jump table, if the target has indirect jumps.
+    pub table_cycles: Option<u32>,
+    pub jump_table: bool,
+    /// Why compares were chosen over a jump table, if they were.
+    pub rejection: Option<String>,
+}
 
 pub struct IrBuilder<'a> {
     instrs: Vec<Instr>,
     next_temp: u32,
     next_label: u32,
     symbols: &'a SymbolTable,
+    target: &'a Target,
+    /// Entry label and parameter ids of every function, keyed by the function's symbol id.
+    /// The function's own id doubles as the variable holding its return value.
+    functions: HashMap<u32, (Label, Vec<u32>)>,
+    function: Option<u32>,
+    /// Label, `continue` target and `break` target of every loop enclosing the current statement.
+    loops: Vec<(Option<String>, Label, Label)>,
+    pub counted_loops: Vec<CountedLoop>,
+    pub matches: Vec<MatchDispatch>,
+    pub layout: Layout,
+    /// Type of every typed variable and of the temporaries holding typed results,
+    /// which the legalizer needs to split multi-word values.
+    pub types: HashMap<Value, IntType>,
+    /// Whether indexes that aren't known at compile time are checked at runtime.
+    bounds_checks: bool,
+    /// Shared target of every failed bounds check, created on first use.
+    out_of_bounds: Option<Label>,
+    /// The first array that didn't fit in the address space, which `build` reports.
+    overflow: Option<CompileError>,
 }
 
 impl<'a> IrBuilder<'a> {
-    pub fn new(symbols: &'a SymbolTable) -> Self {
+    pub fn new(symbols: &'a SymbolTable, target: &'a Target, bounds_checks: bool) -> Self {
+        // Arrays are placed around the IO ports.
+        let mut layout = Layout::new(target.word_bits);
+        let mut overflow = None;
+        for symbol in symbols.scopes.iter().flat_map(|scope| scope.symbols.values()) {
+            if let SymbolKind::Port { address } = symbol.kind {
+                layout.reserve(address);
+            }
+        }
+
+        // Variables whose address is taken get a cell of their own, like one-element arrays.
+        let mut addressed: Vec<u32> = symbols.addressed.iter().copied().collect();
+        addressed.sort();
+        for id in addressed {
+            if let Err(err) = Self::place(&mut layout, symbols, target, id, 1) {
+                overflow.get_or_insert(err);
+            }
+        }
+
+        // Constant tables stay in ROM if the target can load from it. Otherwise they are
+        // placed like arrays, and `build` starts the program by copying them to RAM.
+        for (id, words) in Self::tables(symbols) {
+            match target.rom_loads {
+                true => {
+                    layout.place_rom(id, words);
+                }
+                false => {
+                    if let Err(err) = Self::place(&mut layout, symbols, target, id, words.len() as u32) {
+                        overflow.get_or_insert(err);
+                    }
+                }
+            }
+        }
+
         IrBuilder {
             instrs: Vec::new(),
             next_temp: symbols.scopes.iter().map(|s| s.symbols.len()).sum::<usize>() as u32 + 1,
             next_label: 0,
             symbols,
+            target,
+            functions: HashMap::new(),
+            function: None,
+            loops: Vec::new(),
+            counted_loops: Vec::new(),
+            matches: Vec::new(),
+            layout,
+            types: symbols.types.iter().map(|(&id, &ty)| (Value::Var(id), ty)).collect(),
+            bounds_checks,
+            out_of_bounds: None,
+            overflow,
+        }
+    }
+
+    /// Places `id` in RAM, failing at its declaration if it ends past the addressable range.
+    fn place(layout: &mut Layout, symbols: &SymbolTable, target: &Target, id: u32, length: u32) -> Result<(), CompileError> {
+        layout.place(id, length).map(|_| ()).map_err(|end| {
+            let (position, source_id) = symbols.declared_at(id);
+            Self::overflow(target, format!("`{}`", symbols.name_of(id)), end, position, source_id)
+        })
+    }
+
+    fn overflow(target: &Target, what: String, end: u64, position: usize, source_id: usize) -> CompileError {
+        CompileError { message: Self::overflow_message(target, what, end), position, source_id }
+    }
+
+    fn overflow_message(target: &Target, what: String, end: u64) -> String {
+        format!(
+            "{} doesn't fit in memory, it would end at address {} but {}-bit addresses stop at {}",
+            what,
+            end - 1,
+            target.word_bits,
+            (1u64 << target.word_bits) - 1
+        )
+    }
+
+    /// Gives the memory home of every value the allocator spilled a cell after the arrays.
+    /// Homes that don't fit are reported without a position, as the instructions don't carry
+    /// one and most spilled values are temporaries with nothing in the source to point at.
+    pub fn place_homes(&mut self, instrs: &[Instr]) -> Result<(), String> {
+        let mut placed = HashSet::new();
+        for instr in instrs {
+            let home = match instr {
+                Instr::Load { src: home @ (Value::Var(_) | Value::Temp(_)), .. } => *home,
+                Instr::Store { dst: home @ (Value::Var(_) | Value::Temp(_)), .. } => *home,
+                _ => continue,
+            };
+            if !placed.insert(home) {
+                continue;
+            }
+
+            self.layout.place_home(home).map_err(|end| {
+                let what = match home {
+                    Value::Var(id) => self.symbols.get(id).map(|symbol| format!("the memory home of `{}`", symbol.name)),
+                    _ => None,
+                };
+                let what = what.unwrap_or_else(|| "the memory home of a temporary".to_string());
+                Self::overflow_message(self.target, what, end)
+            })?;
         }
+
+        Ok(())
+    }
+
+    /// RAM cell of every variable whose address is taken, keyed by the variable. The
+    /// allocator keeps these in memory, as a store through a pointer may change them.
+    pub fn ram_homes(&self) -> HashMap<Value, Value> {
+        self.symbols.addressed.iter().map(|&id| (Value::Var(id), Value::Ptr(id))).collect()
+    }
+
+    /// Every constant table with its contents, ordered by symbol id.
+    fn tables(symbols: &SymbolTable) -> Vec<(u32, &Vec<i32>)> {
+        let mut tables: Vec<_> = symbols.tables.iter().map(|(&id, words)| (id, words)).collect();
+        tables.sort_by_key(|&(id, _)| id);
+        tables
     }
 
     fn emit(&mut self, instr: Instr) {
@@ -27,54 +185,121 @@ impl<'a> IrBuilder<'a> {
         temp
     }
 
+    /// A temporary for the result of `expr`, remembering its type if it has one.
+    fn new_temp_for(&mut self, expr: &ExprNode) -> Value {
+        let temp = self.new_temp();
+        if let Some(ty) = self.type_of(expr) {
+            self.types.insert(temp, ty);
+        }
+        temp
+    }
+
     fn new_label(&mut self) -> Label {
         let label = Label(self.next_label);
         self.next_label += 1;
         label
     }
 
-    fn lower_expr(&mut self, expr: &Expr, target: Option<Value>) -> Value {
-        match expr {
+    fn lower_expr(&mut self, expr: &ExprNode, target: Option<Value>) -> Value {
+        match &expr.node {
+            // The resolver made sure the literal fits, as signed or unsigned, so its bits are kept.
             Expr::Number(n) => {
                 if let Some(t) = target {
-                    self.emit(Instr::Move { dst: t, src: Value::Const(*n) });
+                    self.emit(Instr::Move { dst: t, src: Value::Const(*n as i32) });
                     t
                 } else {
-                    Value::Const(*n)
+                    Value::Const(*n as i32)
                 }
             },
-            Expr::Variable(name) => {
-                let id = self.symbols.id_of(name);
+            // `true` and `false` are stored as 1 and 0, like the results of comparisons.
+            Expr::Bool(b) => {
+                let value = Value::Const(*b as i32);
                 if let Some(t) = target {
-                    self.emit(Instr::Move { dst: t, src: Value::Var(id) });
+                    self.emit(Instr::Move { dst: t, src: value });
                     t
                 } else {
-                    Value::Var(id)
+                    value
                 }
             },
-            Expr::Binary { left, right, operator } => {
-                let lhs = self.lower_expr(&left.node, None);
-                let rhs = self.lower_expr(&right.node, None);
+            Expr::Variable(_) => {
+                let id = self.symbols.id_at(expr.position, expr.source_id);
+                let value = match self.symbols.kind_of(id) {
+                    SymbolKind::Constant { value } => Value::Const(value),
+                    SymbolKind::Port { address } => {
+                        let dst = match target {
+                            Some(t) => t,
+                            None => self.new_temp_for(expr),
+                        };
+
+                        self.emit(Instr::Load { dst, src: Value::Const(address) });
+                        return dst;
+                    }
+                    _ => Value::Var(id),
+                };
+
+                if let Some(t) = target {
+                    self.emit(Instr::Move { dst: t, src: value });
+                    t
+                } else {
+                    value
+                }
+            },
+            Expr::Binary { operator: TokenType::AND | TokenType::OR, .. } => {
                 let dst = match target {
                     Some(t) => t,
                     None => self.new_temp(),
                 };
+                let is_false = self.new_label();
+                let end = self.new_label();
 
-                self.emit(match operator {
-                    TokenType::PLUS => Instr::Add { dst, lhs, rhs },
-                    TokenType::MINUS => Instr::Sub { dst, lhs, rhs },
-                    TokenType::GT => Instr::CmpGt { dst, lhs, rhs },
-                    _ => unimplemented!(),
-                });
+                self.lower_branch(expr, false, is_false);
+                self.emit(Instr::Move { dst, src: Value::Const(1) });
+                self.emit(Instr::Jump(end));
+                self.emit(Instr::Label(is_false));
+                self.emit(Instr::Move { dst, src: Value::Const(0) });
+                self.emit(Instr::Label(end));
+
+                dst
+            },
+            Expr::Binary { left, right, operator } => {
+                let lhs = self.lower_expr(left, None);
+                let rhs = self.lower_expr(right, None);
+                let dst = match target {
+                    Some(t) => t,
+                    None => self.new_temp_for(expr),
+                };
+
+                if let Some(cond) = self.condition(operator, left, right) {
+                    self.emit(Instr::Cmp { cond, dst, lhs, rhs });
+                    return dst;
+                }
+
+                let instr = self.arithmetic(operator, dst, lhs, rhs, left, right);
+                self.emit(instr);
 
                 dst
             },
             Expr::Unary { operand, operator } => {
                 // Beispiel: Negation
-                let val = self.lower_expr(&operand.node, None);
+                let val = self.lower_expr(operand, None);
+
+                // Negated constants are folded at full width, as a temporary would only get a
+                // single word when they are used with a wider value.
+                if let (TokenType::MINUS | TokenType::TILDE, Value::Const(n)) = (operator, val) {
+                    let value = match operator {
+                        TokenType::MINUS => Value::Const(n.wrapping_neg()),
+                        _ => Value::Const(!n),
+                    };
+                    if let Some(t) = target {
+                        self.emit(Instr::Move { dst: t, src: value });
+                        return t;
+                    }
+                    return value;
+                }
+
                 let dst = match target {
                     Some(t) => t,
-                    None => self.new_temp(),
+                    None => self.new_temp_for(expr),
                 };
 
                 match operator {
@@ -86,61 +311,1045 @@ impl<'a> IrBuilder<'a> {
                             rhs: val,
                         });
                     }
+                    TokenType::TILDE => {
+                        self.emit(Instr::Not { dst, src: val });
+                    }
+                    TokenType::BANG => {
+                        self.emit(Instr::Cmp { cond: Cond::Eq, dst, lhs: val, rhs: Value::Const(0) });
+                    }
                     _ => (),
                 }
 
                 dst
             }
+            Expr::Index { array, index } => {
+                let address = self.element_address(array, index);
+                let dst = match target {
+                    Some(t) => t,
+                    None => self.new_temp(),
+                };
+
+                match self.layout.in_rom(self.symbols.id_at(array.position, array.source_id)) {
+                    true => self.emit(Instr::LoadRom { dst, src: address }),
+                    false => self.emit(Instr::Load { dst, src: address }),
+                }
+
+                dst
+            }
+            Expr::Field { .. } => {
+                let address = self.field_address(expr);
+                let dst = match target {
+                    Some(t) => t,
+                    None => self.new_temp(),
+                };
+
+                self.emit(Instr::Load { dst, src: address });
+
+                dst
+            }
+            Expr::AddressOf { target: operand } => {
+                let address = self.address_of(operand);
+
+                if let Some(t) = target {
+                    self.emit(Instr::Move { dst: t, src: address });
+                    t
+                } else {
+                    address
+                }
+            }
+            Expr::Deref { pointer } => {
+                let address = self.pointer_address(pointer);
+                let dst = match target {
+                    Some(t) => t,
+                    None => self.new_temp(),
+                };
+
+                self.emit(Instr::Load { dst, src: address });
+
+                dst
+            }
+            Expr::StructLiteral { .. } => unreachable!("struct literals are lowered by `lower_struct`"),
+            Expr::Call { args, .. } => {
+                let id = self.symbols.id_at(expr.position, expr.source_id);
+                let (label, params) = self.functions[&id].clone();
+
+                // Evaluate every argument before the first parameter is written, so nested
+                // calls to the same function can't clobber parameters already passed.
+                let values: Vec<Value> = args.iter().map(|arg| self.lower_expr(arg, None)).collect();
+
+                for (param, value) in params.into_iter().zip(values) {
+                    self.emit(Instr::Move { dst: Value::Var(param), src: value });
+                }
+
+                self.emit(Instr::Call(label));
+
+                let dst = match target {
+                    Some(t) => t,
+                    None => self.new_temp(),
+                };
+                self.emit(Instr::Move { dst, src: Value::Var(id) });
+
+                dst
+            }
+        }
+    }
+
+    /// The RAM address of a variable, an array or an element of one. Arrays and variables
+    /// whose address is taken are both placed in the layout under their own id.
+    fn address_of(&mut self, target: &ExprNode) -> Value {
+        match &target.node {
+            Expr::Variable(_) => Value::Ptr(self.symbols.id_at(target.position, target.source_id)),
+            Expr::Index { array, index } => self.element_address(array, index),
+            Expr::Field { .. } => self.field_address(target),
+            _ => unreachable!("the resolver only takes addresses of variables and elements"),
+        }
+    }
+
+    /// Evaluates a pointer to the RAM cell it refers to. Constant addresses are IO ports
+    /// to loads and stores, so a constant pointer is moved into a temporary first.
+    fn pointer_address(&mut self, pointer: &ExprNode) -> Value {
+        match self.lower_expr(pointer, None) {
+            Value::Const(n) => {
+                let address = self.new_temp();
+                self.emit(Instr::Move { dst: address, src: Value::Const(n) });
+                address
+            }
+            address => address,
+        }
+    }
+
+    /// Computes the RAM address of `array[index]`, checking the index first if enabled.
+    /// Elements of an array of structs take one cell per field.
+    fn element_address(&mut self, array: &ExprNode, index: &ExprNode) -> Value {
+        let id = self.symbols.id_at(array.position, array.source_id);
+        let SymbolKind::Array { length, element } = self.symbols.kind_of(id) else {
+            unreachable!("only arrays are indexed");
+        };
+        let index = self.lower_expr(index, None);
+
+        if self.bounds_checks && !matches!(index, Value::Const(_)) {
+            let in_bounds = self.new_temp();
+            let out_of_bounds = match self.out_of_bounds {
+                Some(label) => label,
+                None => {
+                    let label = self.new_label();
+                    self.out_of_bounds = Some(label);
+                    label
+                }
+            };
+
+            // Unsigned, so negative indexes are out of bounds as well.
+            self.emit(Instr::Cmp { cond: Cond::LtU, dst: in_bounds, lhs: index, rhs: Value::Const(length as i32) });
+            self.emit(Instr::JumpIfFalse { cond: in_bounds, target: out_of_bounds });
+        }
+
+        let stride = element.map_or(1, |element| self.symbols.structs[element].fields.len() as i32);
+        let offset = match index {
+            Value::Const(n) => Value::Const(n * stride),
+            _ if stride == 1 => index,
+            _ => {
+                let offset = self.new_temp();
+                self.emit(Instr::Mul { dst: offset, lhs: index, rhs: Value::Const(stride) });
+                offset
+            }
+        };
+
+        self.offset_address(Value::Ptr(id), offset)
+    }
+
+    /// Computes the RAM address of `array[index].field`.
+    fn field_address(&mut self, expr: &ExprNode) -> Value {
+        let Expr::Field { base, field } = &expr.node else {
+            unreachable!("only fields have field addresses");
+        };
+        let Expr::Index { array, index } = &base.node else {
+            unreachable!("the parser only takes fields of array elements");
+        };
+
+        let element = self.element_address(array, index);
+        let offset = self.field_offset(array, field);
+
+        self.offset_address(element, Value::Const(offset))
+    }
+
+    /// Position of `field` within the elements of `array`.
+    fn field_offset(&self, array: &ExprNode, field: &str) -> i32 {
+        let SymbolKind::Array { element: Some(element), .. } = self.symbols.kind_of(self.symbols.id_at(array.position, array.source_id)) else {
+            unreachable!("fields are only taken of arrays of structs");
+        };
+
+        self.symbols.structs[element]
+            .fields
+            .iter()
+            .position(|name| name == field)
+            .expect("fields are checked by the resolver") as i32
+    }
+
+    fn offset_address(&mut self, base: Value, offset: Value) -> Value {
+        match offset {
+            Value::Const(0) => base,
+            _ => {
+                let address = self.new_temp();
+                self.emit(Instr::Add { dst: address, lhs: base, rhs: offset });
+                address
+            }
+        }
+    }
+
+    /// Lowers a whole struct, returning the value of each of its fields in declaration order.
+    /// Elements of arrays are loaded, literal fields are evaluated in the order they are written.
+    fn lower_struct(&mut self, expr: &ExprNode) -> Vec<Value> {
+        match &expr.node {
+            Expr::StructLiteral { fields, .. } => {
+                let id = self.symbols.id_at(expr.position, expr.source_id);
+                let SymbolKind::Struct { index } = self.symbols.kind_of(id) else {
+                    unreachable!("literals are of structs");
+                };
+
+                let mut values: Vec<(usize, Value)> = fields
+                    .iter()
+                    .map(|(field, value)| {
+                        let Expr::Variable(name) = &field.node else {
+                            unreachable!("the parser only names fields");
+                        };
+                        let offset = self.symbols.structs[index].fields.iter().position(|field| field == name);
+                        (offset.expect("fields are checked by the resolver"), self.lower_expr(value, None))
+                    })
+                    .collect();
+
+                values.sort_by_key(|&(offset, _)| offset);
+                values.into_iter().map(|(_, value)| value).collect()
+            }
+            Expr::Variable(_) => {
+                let id = self.symbols.id_at(expr.position, expr.source_id);
+                self.symbols.records[&id].iter().map(|&field| Value::Var(field)).collect()
+            }
+            Expr::Index { array, index } => {
+                let element = self.element_address(array, index);
+                let fields = self.struct_fields(array);
+
+                (0..fields)
+                    .map(|offset| {
+                        let address = self.offset_address(element, Value::Const(offset as i32));
+                        let value = self.new_temp();
+                        self.emit(Instr::Load { dst: value, src: address });
+                        value
+                    })
+                    .collect()
+            }
+            _ => unreachable!("only structs are lowered as structs"),
+        }
+    }
+
+    /// Number of fields of the structs held by `array`.
+    fn struct_fields(&self, array: &ExprNode) -> usize {
+        match self.symbols.kind_of(self.symbols.id_at(array.position, array.source_id)) {
+            SymbolKind::Array { element: Some(element), .. } => self.symbols.structs[element].fields.len(),
+            _ => unreachable!("only arrays of structs hold structs"),
+        }
+    }
+
+    /// Stores the fields of a struct into consecutive cells starting at `address`.
+    fn store_struct(&mut self, address: Value, values: Vec<Value>) {
+        for (offset, value) in values.into_iter().enumerate() {
+            let field = self.offset_address(address, Value::Const(offset as i32));
+            self.emit(Instr::Store { dst: field, src: value });
+        }
+    }
+
+    fn comparison(operator: &TokenType) -> Option<Cond> {
+        match operator {
+            TokenType::EQ => Some(Cond::Eq),
+            TokenType::NEQ => Some(Cond::Ne),
+            TokenType::LT => Some(Cond::Lt),
+            TokenType::LTE => Some(Cond::Le),
+            TokenType::GT => Some(Cond::Gt),
+            TokenType::GTE => Some(Cond::Ge),
+            _ => None,
+        }
+    }
+
+    /// The comparison `operator` performs on these operands, unsigned if either of them is.
+    fn condition(&self, operator: &TokenType, left: &ExprNode, right: &ExprNode) -> Option<Cond> {
+        let cond = Self::comparison(operator)?;
+
+        match self.is_unsigned(left) || self.is_unsigned(right) {
+            true => Some(cond.resigned()),
+            false => Some(cond),
+        }
+    }
+
+    /// The type of `expr`, or `None` for a plain signed word. Literals are untyped,
+    /// arithmetic takes the wider of its operands and is unsigned if either of them is.
+    fn type_of(&self, expr: &ExprNode) -> Option<IntType> {
+        match &expr.node {
+            Expr::Variable(_) => {
+                let id = self.symbols.id_at(expr.position, expr.source_id);
+                self.symbols.types.get(&id).copied()
+            }
+            Expr::Binary { left, right, operator } => match operator {
+                TokenType::SHL | TokenType::SHR => self.type_of(left),
+                _ if Self::comparison(operator).is_some() => None,
+                TokenType::AND | TokenType::OR => None,
+                _ => {
+                    let word = IntType { signed: true, bits: self.target.word_bits };
+                    match (self.type_of(left), self.type_of(right)) {
+                        (None, None) => None,
+                        (l, r) => {
+                            let (l, r) = (l.unwrap_or(word), r.unwrap_or(word));
+                            Some(IntType { signed: l.signed && r.signed, bits: l.bits.max(r.bits) })
+                        }
+                    }
+                }
+            },
+            Expr::Unary { operator: TokenType::MINUS | TokenType::TILDE, operand } => self.type_of(operand),
+            _ => None,
+        }
+    }
+
+    fn is_unsigned(&self, expr: &ExprNode) -> bool {
+        self.type_of(expr).is_some_and(|ty| !ty.signed)
+    }
+
+    /// The instruction for an arithmetic or bitwise `operator`, signed unless an operand is unsigned.
+    fn arithmetic(&self, operator: &TokenType, dst: Value, lhs: Value, rhs: Value, left: &ExprNode, right: &ExprNode) -> Instr {
+        let unsigned = self.is_unsigned(left) || self.is_unsigned(right);
+
+        match operator {
+            TokenType::PLUS => Instr::Add { dst, lhs, rhs },
+            TokenType::MINUS => Instr::Sub { dst, lhs, rhs },
+            TokenType::ASTERISK => Instr::Mul { dst, lhs, rhs },
+            TokenType::SLASH if unsigned => Instr::DivU { dst, lhs, rhs },
+            TokenType::SLASH => Instr::Div { dst, lhs, rhs },
+            TokenType::PERCENT if unsigned => Instr::ModU { dst, lhs, rhs },
+            TokenType::PERCENT => Instr::Mod { dst, lhs, rhs },
+            TokenType::AMPERSAND => Instr::And { dst, lhs, rhs },
+            TokenType::PIPE => Instr::Or { dst, lhs, rhs },
+            TokenType::CARET => Instr::Xor { dst, lhs, rhs },
+            TokenType::SHL => Instr::Shl { dst, lhs, rhs },
+            TokenType::SHR if self.is_unsigned(left) => Instr::ShrU { dst, lhs, rhs },
+            TokenType::SHR => Instr::Shr { dst, lhs, rhs },
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Wraps a variable of a type narrower than the machine word around to that type.
+    fn wrap(&mut self, id: u32) {
+        let Some(ty) = self.symbols.types.get(&id).copied() else {
+            return;
+        };
+
+        // Multi-word types are whole words, which wrap by themselves.
+        if ty.bits >= self.target.word_bits {
+            return;
+        }
+
+        let var = Value::Var(id);
+        let unused = self.target.word_bits - ty.bits;
+
+        if ty.signed {
+            self.emit(Instr::Shl { dst: var, lhs: var, rhs: Value::Const(unused as i32) });
+            self.emit(Instr::Shr { dst: var, lhs: var, rhs: Value::Const(unused as i32) });
+        } else {
+            let mask = ((1u32 << ty.bits) - 1) as i32;
+            self.emit(Instr::And { dst: var, lhs: var, rhs: Value::Const(mask) });
+        }
+    }
+
+    /// Jumps to `target` if `expr` evaluates to `when` and falls through otherwise.
+    /// `&&`, `||` and `!` become control flow, so right operands are only evaluated when needed.
+    fn lower_branch(&mut self, expr: &ExprNode, when: bool, target: Label) {
+        match &expr.node {
+            Expr::Unary { operator: TokenType::BANG, operand } => {
+                self.lower_branch(operand, !when, target);
+            },
+            Expr::Binary { left, right, operator: operator @ (TokenType::AND | TokenType::OR) } => {
+                // `a && b` is false as soon as `a` is, `a || b` true as soon as `a` is.
+                let decisive = *operator == TokenType::OR;
+
+                if when == decisive {
+                    self.lower_branch(left, when, target);
+                    self.lower_branch(right, when, target);
+                } else {
+                    let skip = self.new_label();
+                    self.lower_branch(left, decisive, skip);
+                    self.lower_branch(right, when, target);
+                    self.emit(Instr::Label(skip));
+                }
+            },
+            Expr::Binary { left, right, operator } if when && Self::comparison(operator).is_some() => {
+                // Only `JumpIfFalse` exists, so jumping on true tests the negated comparison.
+                let lhs = self.lower_expr(left, None);
+                let rhs = self.lower_expr(right, None);
+                let cond = self.new_temp();
+                let negated = self.condition(operator, left, right).unwrap().negated();
+
+                self.emit(Instr::Cmp { cond: negated, dst: cond, lhs, rhs });
+                self.emit(Instr::JumpIfFalse { cond, target });
+            },
+            _ => {
+                let value = self.lower_expr(expr, None);
+                let cond = match when {
+                    false => value,
+                    true => {
+                        let cond = self.new_temp();
+                        self.emit(Instr::Cmp { cond: Cond::Eq, dst: cond, lhs: value, rhs: Value::Const(0) });
+                        cond
+                    }
+                };
+
+                self.emit(Instr::JumpIfFalse { cond, target });
+            },
         }
     }
 
     fn lower_stmt(&mut self, stmt: &Stmt) {
         match stmt {
-            Stmt::Assign { target, value } => {
-                if let Expr::Variable(name) = &target.node {
-                    let var = self.symbols.id_of(&name);
+            Stmt::Assign { target, value } => match &target.node {
+                Expr::Variable(_) => {
+                    let var = self.symbols.id_at(target.position, target.source_id);
 
-                    self.lower_expr(&value.node, Some(Value::Var(var)));
-                } else {
-                    panic!("Invalid assignment target");
+                    // Fields read from the target itself are copied first, so `p = Point { x: p.y, y: p.x }` swaps them.
+                    if let Some(fields) = self.symbols.records.get(&var) {
+                        let mut values = self.lower_struct(value);
+                        for value in values.iter_mut() {
+                            if let Value::Var(id) = *value
+                                && fields.contains(&id)
+                            {
+                                let copy = self.new_temp();
+                                self.emit(Instr::Move { dst: copy, src: *value });
+                                *value = copy;
+                            }
+                        }
+
+                        for (&field, value) in fields.iter().zip(values) {
+                            self.emit(Instr::Move { dst: Value::Var(field), src: value });
+                        }
+                        return;
+                    }
+
+                    if let SymbolKind::Port { address } = self.symbols.kind_of(var) {
+                        let value = self.lower_expr(value, None);
+                        self.emit(Instr::Store { dst: Value::Const(address), src: value });
+                        return;
+                    }
+
+                    self.lower_expr(value, Some(Value::Var(var)));
+                    if !matches!(value.node, Expr::Number(_)) {
+                        self.wrap(var);
+                    }
+                }
+                Expr::Index { array, index } => {
+                    let address = self.element_address(array, index);
+
+                    if let SymbolKind::Array { element: Some(_), .. } = self.symbols.kind_of(self.symbols.id_at(array.position, array.source_id)) {
+                        let values = self.lower_struct(value);
+                        self.store_struct(address, values);
+                        return;
+                    }
+
+                    let value = self.lower_expr(value, None);
+
+                    self.emit(Instr::Store { dst: address, src: value });
+                }
+                Expr::Field { .. } => {
+                    let address = self.field_address(target);
+                    let value = self.lower_expr(value, None);
+
+                    self.emit(Instr::Store { dst: address, src: value });
+                }
+                Expr::Deref { pointer } => {
+                    let address = self.pointer_address(pointer);
+                    let value = self.lower_expr(value, None);
+
+                    self.emit(Instr::Store { dst: address, src: value });
+                }
+                _ => panic!("Invalid assignment target"),
+            },
+            // The target is read and written in place, ports, array elements and pointers through a temporary.
+            Stmt::CompoundAssign { target, operator, value } => {
+                let (current, address) = match &target.node {
+                    Expr::Variable(_) => {
+                        let var = self.symbols.id_at(target.position, target.source_id);
+                        match self.symbols.kind_of(var) {
+                            SymbolKind::Port { address } => (self.new_temp_for(target), Some(Value::Const(address))),
+                            _ => (Value::Var(var), None),
+                        }
+                    }
+                    Expr::Index { array, index } => (self.new_temp(), Some(self.element_address(array, index))),
+                    Expr::Field { .. } => (self.new_temp(), Some(self.field_address(target))),
+                    Expr::Deref { pointer } => (self.new_temp(), Some(self.pointer_address(pointer))),
+                    _ => panic!("Invalid assignment target"),
+                };
+
+                if let Some(address) = address {
+                    self.emit(Instr::Load { dst: current, src: address });
+                }
+
+                let rhs = self.lower_expr(value, None);
+                let wide = self.types.get(&current).is_some_and(|ty| ty.bits > self.target.word_bits);
+
+                match (operator, rhs) {
+                    (TokenType::PLUS, Value::Const(n)) if !wide => {
+                        self.emit(Instr::AddImmediate { dst: current, lhs: current, imm: n });
+                    }
+                    (TokenType::MINUS, Value::Const(n)) if !wide => {
+                        self.emit(Instr::AddImmediate { dst: current, lhs: current, imm: n.wrapping_neg() });
+                    }
+                    _ => {
+                        let instr = self.arithmetic(operator, current, current, rhs, target, value);
+                        self.emit(instr);
+                    }
+                }
+
+                match (address, current) {
+                    (Some(address), _) => self.emit(Instr::Store { dst: address, src: current }),
+                    (None, Value::Var(var)) => self.wrap(var),
+                    _ => {}
+                }
+            },
+            Stmt::DeclareArray { target, values, .. } => {
+                let id = self.symbols.id_at(target.position, target.source_id);
+                // Constant tables were placed, and copied by the startup code if needed, up front.
+                if self.symbols.tables.contains_key(&id) {
+                    return;
+                }
+
+                let SymbolKind::Array { length, element } = self.symbols.kind_of(id) else {
+                    unreachable!("arrays are declared as arrays");
+                };
+
+                // The fields of each struct element are stored next to each other.
+                let stride = element.map_or(1, |element| self.symbols.structs[element].fields.len());
+                if let Err(err) = Self::place(&mut self.layout, self.symbols, self.target, id, length * stride as u32) {
+                    self.overflow.get_or_insert(err);
+                }
+
+                for (i, value) in values.iter().flatten().enumerate() {
+                    match element {
+                        Some(_) => {
+                            let values = self.lower_struct(value);
+                            let address = self.offset_address(Value::Ptr(id), Value::Const((i * stride) as i32));
+                            self.store_struct(address, values);
+                        }
+                        None => {
+                            let value = self.lower_expr(value, None);
+                            let address = self.offset_address(Value::Ptr(id), Value::Const(i as i32));
+                            self.emit(Instr::Store { dst: address, src: value });
+                        }
+                    }
                 }
             },
-            Stmt::Declare { target, value } => {
-                if let Expr::Variable(name) = &target.node {
-                    let var = self.symbols.id_of(&name);
+            Stmt::Declare { target, value, .. } => {
+                if let Expr::Variable(_) = &target.node {
+                    let var = self.symbols.id_at(target.position, target.source_id);
+
+                    if let Some(fields) = self.symbols.records.get(&var) {
+                        let values = self.lower_struct(value);
+                        for (&field, value) in fields.iter().zip(values) {
+                            self.emit(Instr::Move { dst: Value::Var(field), src: value });
+                        }
+                        return;
+                    }
 
-                    self.lower_expr(&value.node, Some(Value::Var(var)));
+                    self.lower_expr(value, Some(Value::Var(var)));
+                    if !matches!(value.node, Expr::Number(_)) {
+                        self.wrap(var);
+                    }
                 } else {
                     panic!("Invalid declaration target");
                 }
             },
-            Stmt::While { condition, body } => {
+            Stmt::While { label, condition, body } => {
                 let start = self.new_label();
                 let end = self.new_label();
 
                 self.emit(Instr::Label(start));
+                self.lower_branch(condition, false, end);
 
-                let cond = self.lower_expr(&condition.node, None);
-                self.emit(Instr::JumpIfFalse {
-                    cond,
-                    target: end,
-                });
-
+                self.loops.push((Self::label_name(label), start, end));
                 for s in body {
                     self.lower_stmt(&s.node);
                 }
+                self.loops.pop();
 
                 self.emit(Instr::Jump(start));
                 self.emit(Instr::Label(end));
             },
-            _ => unimplemented!(),
+            // The test is repeated at the latch, so every iteration takes a single branch.
+            // Inclusive loops stop after the iteration for `end`, without ever stepping past it.
+            Stmt::For { label, var, start, end, inclusive, body } => {
+                let counter = Value::Var(self.symbols.id_at(var.position, var.source_id));
+                self.lower_expr(start, Some(counter));
+
+                let limit = match self.lower_expr(end, None) {
+                    Value::Var(id) => {
+                        let temp = self.new_temp();
+                        self.emit(Instr::Move { dst: temp, src: Value::Var(id) });
+                        temp
+                    }
+                    limit => limit,
+                };
+
+                let is_unsigned_const = |value: Value| matches!(value, Value::Const(c) if self.target.signed(c) != c);
+                let unsigned = self.is_unsigned(start) || self.is_unsigned(end) || is_unsigned_const(limit);
+                let cond = match (*inclusive, unsigned) {
+                    (false, false) => Cond::Lt,
+                    (false, true) => Cond::LtU,
+                    (true, false) => Cond::Le,
+                    (true, true) => Cond::LeU,
+                };
+
+                let head = self.new_label();
+                let latch = self.new_label();
+                let exit = self.new_label();
+
+                let enter = self.new_temp();
+                self.emit(Instr::Cmp { cond, dst: enter, lhs: counter, rhs: limit });
+                self.emit(Instr::JumpIfFalse { cond: enter, target: exit });
+                self.emit(Instr::Label(head));
+
+                self.loops.push((Self::label_name(label), latch, exit));
+                for s in body {
+                    self.lower_stmt(&s.node);
+                }
+                self.loops.pop();
+
+                self.emit(Instr::Label(latch));
+
+                let done = self.new_temp();
+                if *inclusive {
+                    self.emit(Instr::Cmp { cond: Cond::Eq, dst: done, lhs: counter, rhs: limit });
+                    self.emit(Instr::Add { dst: counter, lhs: counter, rhs: Value::Const(1) });
+                } else {
+                    self.emit(Instr::Add { dst: counter, lhs: counter, rhs: Value::Const(1) });
+                    self.emit(Instr::Cmp { cond: cond.negated(), dst: done, lhs: counter, rhs: limit });
+                }
+                self.emit(Instr::JumpIfFalse { cond: done, target: head });
+                self.emit(Instr::Label(exit));
+
+                self.counted_loops.push(CountedLoop { counter, limit, inclusive: *inclusive, head, latch });
+            },
+            // Arms are placed in order after the dispatch, which jumps to the `_` arm or past the
+            // end for values no pattern matches.
+            Stmt::Match { value, arms } => {
+                let scrutinee = self.lower_expr(value, None);
+                let labels: Vec<Label> = arms.iter().map(|_| self.new_label()).collect();
+                let end = self.new_label();
+                let default = arms.iter().position(|arm| arm.node.patterns.is_none()).map_or(end, |i| labels[i]);
+
+                let cases: Vec<(i32, Label)> = arms
+                    .iter()
+                    .zip(&labels)
+                    .flat_map(|(arm, &label)| arm.node.patterns.iter().flatten().map(move |pattern| (pattern, label)))
+                    .map(|(pattern, label)| (self.symbols.patterns[&(pattern.source_id, pattern.position)], label))
+                    .collect();
+
+                let dispatch = self.plan_match(&cases, value);
+
+                if dispatch.jump_table {
+                    // Rebased to start at 0, so a single unsigned compare also rejects values below it.
+                    let min = cases.iter().map(|&(n, _)| n).min().expect("jump tables have cases");
+                    let index = match min {
+                        0 => scrutinee,
+                        _ => {
+                            let index = self.new_temp();
+                            self.emit(Instr::Sub { dst: index, lhs: scrutinee, rhs: Value::Const(min) });
+                            index
+                        }
+                    };
+
+                    let in_range = self.new_temp();
+                    self.emit(Instr::Cmp { cond: Cond::LtU, dst: in_range, lhs: index, rhs: Value::Const(dispatch.span as i32) });
+                    self.emit(Instr::JumpIfFalse { cond: in_range, target: default });
+
+                    let targets = (0..dispatch.span)
+                        .map(|offset| {
+                            let n = (min as i64 + offset) as i32;
+                            cases.iter().find(|&&(case, _)| case == n).map_or(default, |&(_, label)| label)
+                        })
+                        .collect();
+                    self.emit(Instr::JumpTable { index, targets });
+                } else {
+                    for &(n, label) in &cases {
+                        let differs = self.new_temp();
+                        self.emit(Instr::Cmp { cond: Cond::Ne, dst: differs, lhs: scrutinee, rhs: Value::Const(n) });
+                        self.emit(Instr::JumpIfFalse { cond: differs, target: label });
+                    }
+                    self.emit(Instr::Jump(default));
+                }
+
+                self.matches.push(dispatch);
+
+                for (i, (arm, label)) in arms.iter().zip(labels).enumerate() {
+                    self.emit(Instr::Label(label));
+                    for s in &arm.node.body {
+                        self.lower_stmt(&s.node);
+                    }
+
+                    if i + 1 < arms.len() {
+                        self.emit(Instr::Jump(end));
+                    }
+                }
+
+                self.emit(Instr::Label(end));
+            },
+            Stmt::Break { label } | Stmt::Continue { label } => {
+                let name = Self::label_name(label);
+                let &(_, next, end) = self
+                    .loops
+                    .iter()
+                    .rev()
+                    .find(|(label, _, _)| name.is_none() || *label == name)
+                    .expect("loop exits are checked by the resolver");
+
+                match stmt {
+                    Stmt::Break { .. } => self.emit(Instr::Jump(end)),
+                    _ => self.emit(Instr::Jump(next)),
+                }
+            },
+            Stmt::If { condition, body, else_body } => {
+                let else_label = self.new_label();
+
+                self.lower_branch(condition, false, else_label);
+
+                for s in body {
+                    self.lower_stmt(&s.node);
+                }
+
+                match else_body {
+                    Some(else_body) => {
+                        let end = self.new_label();
+
+                        self.emit(Instr::Jump(end));
+                        self.emit(Instr::Label(else_label));
+
+                        for s in else_body {
+                            self.lower_stmt(&s.node);
+                        }
+
+                        self.emit(Instr::Label(end));
+                    }
+                    None => self.emit(Instr::Label(else_label)),
+                }
+            },
+            Stmt::Return { value } => {
+                let function = self.function.expect("return outside of a function");
+
+                if let Some(value) = value {
+                    self.lower_expr(value, Some(Value::Var(function)));
+                }
+
+                self.emit(Instr::Return);
+            },
+            Stmt::Expression { expr } => {
+                self.lower_expr(expr, None);
+            },
+            Stmt::Asm { inputs, outputs, clobbers, body } => {
+                let register = |node: &ExprNode| match &node.node {
+                    Expr::Variable(name) => asm::register(name).expect("registers are checked by the resolver"),
+                    _ => unreachable!("the parser only binds names"),
+                };
+
+                let inputs = inputs.iter().map(|(reg, value)| (register(reg), self.lower_expr(value, None))).collect();
+                let outputs: Vec<(u8, u32)> = outputs
+                    .iter()
+                    .map(|(reg, var)| (register(reg), self.symbols.id_at(var.position, var.source_id)))
+                    .collect();
+                let body = body
+                    .iter()
+                    .map(|line| asm::assemble(&line.node, self.target).expect("asm is checked by the resolver"))
+                    .collect();
+
+                self.emit(Instr::Asm {
+                    body,
+                    inputs,
+                    outputs: outputs.iter().map(|&(reg, var)| (reg, Value::Var(var))).collect(),
+                    clobbers: clobbers.iter().map(register).collect(),
+                });
+
+                for (_, var) in outputs {
+                    self.wrap(var);
+                }
+            },
+            // Function bodies are placed after the main program by `build`, constants were
+            // already folded into their uses and structs and enums only shape the values using them.
+            Stmt::Function { .. }
+            | Stmt::Const { .. }
+            | Stmt::Port { .. }
+            | Stmt::Import { .. }
+            | Stmt::Struct { .. }
+            | Stmt::Enum { .. } => {},
         }
     }
 
-    pub fn build(&mut self, stmts: &Vec<StmtNode>) -> &Vec<Instr> {
-        for stmt in stmts {
+    /// Picks between a compare chain and a jump table for the `cases` of a `match` on `value`.
+    /// A table needs indirect jumps and has to be at least half full, and is only used when the
+    /// target's cost model makes it faster than comparing.
+    fn plan_match(&self, cases: &[(i32, Label)], value: &ExprNode) -> MatchDispatch {
+        let values = cases.len();
+        let min = cases.iter().map(|&(n, _)| n as i64).min().unwrap_or(0);
+        let span = cases.iter().map(|&(n, _)| n as i64 - min + 1).max().unwrap_or(0);
+
+        // On average half of the compares run before the matching one branches.
+        let compare = self.target.compare_branch_cycles(Cond::Ne);
+        let chain_cycles = (compare * (values as u32 + 1)).div_ceil(2);
+
+        let rebase = (min != 0) as u32 * (1 + self.target.result_latency);
+        let table_cycles = (self.target.indirect_jumps && values > 0)
+            .then(|| rebase + self.target.compare_branch_cycles(Cond::LtU) + self.target.branch_cycles());
+
+        let dense = span <= 2 * values as i64;
+        let rejection = match table_cycles {
+            None if !self.target.indirect_jumps => Some(format!("target `{}` has no indirect jumps", self.target.name)),
+            None => Some("no values to jump to".to_string()),
+            Some(_) if !dense => Some(format!("{} entries for {} values are too sparse", span, values)),
+            Some(table) if table >= chain_cycles => Some(format!("{} cycles", table)),
+            Some(_) => None,
+        };
+        let jump_table = rejection.is_none();
+
+        MatchDispatch { position: value.position, source_id: value.source_id, values, span, chain_cycles, table_cycles, jump_table, rejection }
+    }
+
+    fn label_name(label: &Option<ExprNode>) -> Option<String> {
+        label.as_ref().map(|label| match &label.node {
+            Expr::Variable(name) => name.clone(),
+            _ => unreachable!("the parser only labels with names"),
+        })
+    }
+
+    fn lower_function(&mut self, stmt: &Stmt) {
+        if let Stmt::Function { name, body, .. } = stmt {
+            let id = self.symbols.id_at(name.position, name.source_id);
+            let (label, _) = self.functions[&id];
+
+            self.function = Some(id);
+            self.emit(Instr::Label(label));
+
+            for s in body {
+                self.lower_stmt(&s.node);
+            }
+
+            if !matches!(self.instrs.last(), Some(Instr::Return)) {
+                self.emit(Instr::Return);
+            }
+
+            self.function = None;
+        }
+    }
+
+    /// Lowers the program. The top level code of every module runs in module order, so
+    /// imported modules are initialised before the modules using them.
+    pub fn build(&mut self, modules: &[Module]) -> Result<&Vec<Instr>, CompileError> {
+        let stmts: Vec<&StmtNode> = modules.iter().flat_map(|module| &module.stmts).collect();
+
+        for stmt in &stmts {
+            if let Stmt::Function { name, params, .. } = &stmt.node {
+                let label = self.new_label();
+                let params = params
+                    .iter()
+                    .map(|p| self.symbols.id_at(p.position, p.source_id))
+                    .collect();
+
+                self.functions.insert(self.symbols.id_at(name.position, name.source_id), (label, params));
+            }
+        }
+
+        if !self.target.rom_loads {
+            for (id, words) in Self::tables(self.symbols) {
+                for (i, &word) in words.iter().enumerate() {
+                    let address = self.offset_address(Value::Ptr(id), Value::Const(i as i32));
+                    self.emit(Instr::Store { dst: address, src: Value::Const(word) });
+                }
+            }
+        }
+
+        for stmt in &stmts {
             self.lower_stmt(&stmt.node);
         }
-        &self.instrs
+
+        self.emit(Instr::Halt);
+
+        for stmt in &stmts {
+            self.lower_function(&stmt.node);
+        }
+
+        if let Some(out_of_bounds) = self.out_of_bounds {
+            self.emit(Instr::Label(out_of_bounds));
+            self.emit(Instr::Trap);
+        }
+
+        match self.overflow.take() {
+            Some(err) => Err(err),
+            None => Ok(&self.instrs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        instructions::{Instr, Value},
+        testing::run,
+    };
+
+    const TARGET: &str = include_str!("../example.target");
+
+    const MATCHES: &str = "port out @ 200;\nlet x = 3;\n\
+        match x {\n0 => { out = 0; }\n1 => { out = 1; }\n2 => { out = 2; }\n3 => { out = 3; }\n4 => { out = 4; }\n_ => {}\n}\n\
+        match x {\n0 => { out = 0; }\n50 => { out = 50; }\n100 => { out = 100; }\n_ => {}\n}\n\
+        match x {\n3 => { out = 1; }\n_ => {}\n}\n";
+
+    #[test]
+    fn negated_literals_keep_every_word_of_wide_values() {
+        let source = "port out @ 200;\nlet a: i16 = -300;\nif a == -300 { out = 1; } else { out = 0; }\n\
+            let y: i16 = 1000;\nlet z: i16 = y + -300;\nif z == 700 { out = 1; } else { out = 0; }\n\
+            y += -300;\nif y == 700 { out = 1; } else { out = 0; }\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![1, 1, 1]);
+        }
+    }
+
+    #[test]
+    fn memory_homes_past_the_end_are_reported_without_a_position() {
+        let source = |length| format!("port out @ 255;\nlet big[{}];\nlet a = 7;\nlet b = 9;\nout = a * b;\n", length);
+        let err = run(&source(253), TARGET).err().unwrap();
+        assert_eq!(err, "the memory home of a temporary doesn't fit in memory, it would end at address 256 but 8-bit addresses stop at 255");
+        let err = run(&source(255), TARGET).err().unwrap();
+        assert_eq!(err, "the memory home of `a` doesn't fit in memory, it would end at address 256 but 8-bit addresses stop at 255");
+    }
+
+    #[test]
+    fn matches_record_why_they_got_no_jump_table() {
+        let run = run(MATCHES, &TARGET.replace("indirect_jumps = false", "indirect_jumps = true")).unwrap();
+        assert_eq!(
+            run.match_rejections,
+            vec![None, Some("101 entries for 3 values are too sparse".to_string()), Some("12 cycles".to_string())]
+        );
+        assert_eq!(run.port_writes, vec![3, 1]);
+    }
+
+    #[test]
+    fn matches_without_indirect_jumps_record_the_target() {
+        let run = run(MATCHES, TARGET).unwrap();
+        let rejection = Some("target `pipelined-8` has no indirect jumps".to_string());
+        assert_eq!(run.match_rejections, vec![rejection.clone(), rejection.clone(), rejection]);
+        assert_eq!(run.port_writes, vec![3, 1]);
+    }
+
+    #[test]
+    fn calls_pass_arguments_and_return_values() {
+        let source = "port out @ 200;\nfn add(a, b) { return a + b; }\nfn twice(x) { return add(x, x); }\n\
+            fn show(v) { out = v; }\nout = add(3, 4);\nout = twice(add(1, 2));\nshow(9);\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![7, 6, 9]);
+        }
+    }
+
+    #[test]
+    fn else_if_chains_take_the_first_true_branch() {
+        let source = "port out @ 200;\nfn classify(x) {\n\
+            if x < 0 { return 0; } else if x == 0 { return 1; } else if x < 10 { return 2; } else { return 3; }\n}\n\
+            out = classify(-5);\nout = classify(0);\nout = classify(4);\nout = classify(50);\n\
+            let y = 5;\nif y > 3 { out = 7; }\nif y > 9 { out = 8; }\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![0, 1, 2, 3, 7]);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn comparisons_respect_the_signedness_of_their_operands() {
+        let compare = |ty: &str, a: i32, b: i32| {
+            format!(
+                "port out @ 200;\nlet a: {0} = {1};\nlet b: {0} = {2};\nlet r = 0;\n\
+                if a < b {{ r += 1; }}\nif a <= b {{ r += 2; }}\nif a > b {{ r += 4; }}\n\
+                if a >= b {{ r += 8; }}\nif a == b {{ r += 16; }}\nif a != b {{ r += 32; }}\nout = r;\n",
+                ty, a, b
+            )
+        };
+        for target in ["", TARGET] {
+            for (source, expected) in [
+                (compare("i8", -1, 1), 35),
+                (compare("i8", 1, -1), 44),
+                (compare("i8", 3, 3), 26),
+                (compare("u8", 255, 1), 44),
+                (compare("u8", 1, 255), 35),
+            ] {
+                assert_eq!(run(&source, target).unwrap().port_writes, vec![expected], "{}", source);
+            }
+        }
+    }
+
+    #[test]
+    fn port_accesses_are_loads_and_stores_that_are_never_merged() {
+        let source = "port output: u8 @ 0xF0;\nport status @ 0xF1;\noutput = 5;\noutput = 5;\nstatus = 1;\n\
+            let a = status;\nlet b = status;\noutput = a + b;\n";
+        for target in ["", TARGET] {
+            let run = run(source, target).unwrap();
+            assert_eq!(run.port_writes, vec![5, 5, 1, 2]);
+            let loads = run.legalized.iter().filter(|instr| matches!(instr, Instr::Load { src: Value::Const(0xF1), .. })).count();
+            assert_eq!(loads, 2);
+        }
+    }
+
+    #[test]
+    fn break_and_continue_leave_the_loop_they_name() {
+        let source = "port out @ 200;\nlet i = 0;\nwhile i < 10 {\n    i += 1;\n    if i == 2 { continue; }\n    if i == 5 { break; }\n    out = i;\n}\n\
+            let total = 0;\nlet x = 0;\nouter: while x < 5 {\n    x += 1;\n    let y = 0;\n    while y < 5 {\n        y += 1;\n\
+            if y == 2 { continue outer; }\n        if x == 4 { break outer; }\n        total += 1;\n    }\n}\nout = total;\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![1, 3, 4, 3]);
+        }
+    }
+
+    #[test]
+    fn for_loops_count_over_their_range() {
+        let source = "port out @ 200;\nfor i in 0..3 { out = i; }\nfor i in 1..=3 { out = i * 10; }\n\
+            let n = 0;\nfor i in 5..5 { n += 1; }\nout = n;\nfor i in 0..4 { for j in 0..=i { n += 1; } }\nout = n;\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![0, 1, 2, 10, 20, 30, 0, 10]);
+        }
+
+        let err = run("for i in 0..3 { }\nlet x = i;\n", "").err().unwrap();
+        assert_eq!(err, "use of undeclared variable `i` at main.tch:2:9");
+    }
+
+    #[test]
+    fn compound_assignments_update_the_variable_in_place() {
+        let source = "port out @ 200;\nlet x = 5;\nx += 1; out = x;\nx -= 2; out = x;\nx *= 3; out = x;\nx &= 6; out = x;\n\
+            x |= 1; out = x;\nx <<= 2; out = x;\nx >>= 1; out = x;\nx ^= 3; out = x;\nx++; out = x;\nx--; x--; out = x;\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![6, 4, 12, 4, 5, 20, 10, 9, 10, 8]);
+        }
+
+        let run = run("port out @ 200;\nlet x = 5;\nx += 1;\nx++;\nout = x;\n", "").unwrap();
+        let increments = run.legalized.iter().filter(|instr| matches!(instr, Instr::AddImmediate { dst, lhs, imm: 1 } if dst == lhs));
+        assert_eq!(increments.count(), 2, "{:?}", run.legalized);
+        assert_eq!(run.legalized.len(), 5, "{:?}", run.legalized);
+    }
+
+    #[test]
+    fn struct_fields_and_arrays_of_structs() {
+        let source = "port out @ 200;\nstruct Point { x, y }\nlet p = Point { x: 1, y: 2 };\np.x += 1;\nout = p.x;\nout = p.y;\n\
+            let points[4]: Point;\npoints[0] = p;\npoints[1].y = points[0].x + 5;\npoints[2] = Point { x: 8, y: 9 };\n\
+            let i = 2;\nout = points[1].y;\nout = points[i].x + points[i].y;\nout = points[0].y;\n";
+        for target in ["", TARGET] {
+            let run = run(source, target).unwrap();
+            assert_eq!(run.port_writes, vec![2, 2, 7, 17, 2]);
+            assert!(run.map.contains("  0x0000      8  points"), "{}", run.map);
+        }
+    }
+
+    #[test]
+    fn pointers_reach_arrays_and_addressed_variables() {
+        let source = "port out @ 200;\nfn sum(buf, n) {\n    let s = 0;\n    for i in 0..n { s += *(buf + i); }\n    return s;\n}\n\
+            let data = [1, 2, 3];\nout = sum(&data, 3);\nlet q = &data[1];\n*(q + 1) = 10;\nout = sum(&data, 3);\n\
+            let x = 5;\nlet p = &x;\n*p += 1;\nout = x;\nx = 20;\nout = *p;\n";
+        for target in ["", TARGET] {
+            let run = run(source, target).unwrap();
+            assert_eq!(run.port_writes, vec![6, 13, 6, 20]);
+            assert!(run.map.contains("  0x0000      1  x                variable"), "{}", run.map);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use crate::instructions::Value;
+
+/// Places every array in RAM and constant tables in ROM if the target reads it. Scalar
+/// variables are kept in registers by the allocator, unless their address is taken, which
+/// places them like one-element arrays. Those it spills get a cell after everything else,
+/// their memory home.
+pub struct Layout {
+    /// Base address and length of each array, keyed by the array's symbol id.
+    arrays: HashMap<u32, (i32, u32)>,
+    /// Base address and length of each constant table in ROM, keyed likewise.
+    tables: HashMap<u32, (i32, u32)>,
+    /// Address of the memory home of every spilled variable and temporary.
+    homes: HashMap<Value, i32>,
+    /// Contents of the ROM data segment, the tables one after another.
+    pub rom: Vec<i32>,
+    /// Addresses of IO ports, which no array may overlap.
+    reserved: Vec<i32>,
+    pub size: u32,
+    /// Number of addresses the target's words can hold.
+    capacity: u64,
+}
+
+impl Layout {
+    pub fn new(word_bits: u32) -> Self {
+        Layout {
+            arrays: HashMap::new(),
+            tables: HashMap::new(),
+            homes: HashMap::new(),
+            rom: Vec::new(),
+            reserved: Vec::new(),
+            size: 0,
+            capacity: 1 << word_bits,
+        }
+    }
+
+    pub fn reserve(&mut self, address: i32) {
+        self.reserved.push(address);
+    }
+
+    /// Reserves `length` consecutive cells for the array `id` and returns its base address,
+    /// or the address it would end at if that is past the last one the target can address.
+    pub fn place(&mut self, id: u32, length: u32) -> Result<i32, u64> {
+        let address = self.allocate(length)?;
+        self.arrays.insert(id, (address, length));
+        Ok(address)
+    }
+
+    /// Reserves a cell for the memory home of a spilled value, like `place`.
+    pub fn place_home(&mut self, home: Value) -> Result<i32, u64> {
+        let address = self.allocate(1)?;
+        self.homes.insert(home, address);
+        Ok(address)
+    }
+
+    /// Finds room for `length` cells after everything placed so far, around the ports.
+    fn allocate(&mut self, length: u32) -> Result<i32, u64> {
+        let mut address = self.size as i32;
+        while let Some(&port) = self.reserved.iter().filter(|&&port| (address..address + length as i32).contains(&port)).max() {
+            address = port + 1;
+        }
+
+        let end = address as u64 + length as u64;
+        if end > self.capacity {
+            return Err(end);
+        }
+
+        self.size = (address as u32) + length;
+        Ok(address)
+    }
+
+    /// Appends the constant table `id` to ROM and returns its base address there.
+    pub fn place_rom(&mut self, id: u32, words: &[i32]) -> i32 {
+        let address = self.rom.len() as i32;
+        self.rom.extend_from_slice(words);
+        self.tables.insert(id, (address, words.len() as u32));
+        address
+    }
+
+    /// Base address of an array in RAM or of a table in ROM.
+    pub fn address_of(&self, id: u32) -> i32 {
+        self.arrays.get(&id).or(self.tables.get(&id)).expect("only placed arrays have addresses").0
+    }
+
+    pub fn home_of(&self, home: &Value) -> i32 {
+        *self.homes.get(home).expect("only spilled values have memory homes")
+    }
+
+    pub fn in_rom(&self, id: u32) -> bool {
+        self.tables.contains_key(&id)
+    }
+
+    /// Every placed array as `(id, address, length)`, ordered by address.
+    pub fn arrays(&self) -> Vec<(u32, i32, u32)> {
+        let mut arrays: Vec<_> = self.arrays.iter().map(|(&id, &(address, length))| (id, address, length)).collect();
+        arrays.sort_by_key(|&(_, address, _)| address);
+        arrays
+    }
+
+    /// Every memory home as `(value, address)`, ordered by address.
+    pub fn homes(&self) -> Vec<(Value, i32)> {
+        let mut homes: Vec<_> = self.homes.iter().map(|(&home, &address)| (home, address)).collect();
+        homes.sort_by_key(|&(_, address)| address);
+        homes
+    }
+
+    /// Every table in ROM as `(id, address, length)`, ordered by address.
+    pub fn tables(&self) -> Vec<(u32, i32, u32)> {
+        let mut tables: Vec<_> = self.tables.iter().map(|(&id, &(address, length))| (id, address, length)).collect();
+        tables.sort_by_key(|&(_, address, _)| address);
+        tables
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn arrays_past_the_address_space_are_rejected() {
+        let err = run("let a[200];\nlet b[100];\nb[99] = 5;\n", "").err().unwrap();
+        assert_eq!(err, "`b` doesn't fit in memory, it would end at address 299 but 8-bit addresses stop at 255 at main.tch:2:6");
+    }
+
+    #[test]
+    fn arrays_may_fill_the_address_space() {
+        let run = run("port out @ 0;\nlet a[200];\nlet b[55];\nb[54] = 5;\nout = b[54];\n", "").unwrap();
+        assert_eq!(run.port_writes, vec![5]);
+    }
+}
@@ -1,26 +1,67 @@
-use crate::instructions::{Instr, Value};
+use std::collections::HashMap;
+
+use crate::{instructions::{Cond, Instr, Label, Value}, target::{Shifts, Target}, types::IntType};
+
+/// Helper routines appended to the program for operations the target has no instruction for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Routine {
+    /// `(a, b) -> (a * b)` by shifting and adding.
+    Mul,
+    /// `(n, d) -> (n / d, n % d)` on unsigned operands by shifting and subtracting.
+    UDivMod,
+    /// `(n, d) -> (n / d, n % d)` on signed operands, built on `UDivMod`.
+    SDivMod,
+    /// `(a, b) -> (a & b)` one bit at a time, for targets with neither `and` nor `or`.
+    And,
+    /// `(x, n) -> (x >> n)` arithmetic, for targets without shifts.
+    Shr,
+    /// `(x, n) -> (x >> n)` logical, for targets without shifts.
+    ShrU,
+}
+
+/// Entry label and the statically allocated parameter and result variables of a routine.
+#[derive(Clone)]
+struct Frame {
+    label: Label,
+    params: Vec<Value>,
+    results: Vec<Value>,
+}
 
 pub struct Legalizer<'a> {
     instrs: &'a Vec<Instr>,
+    target: &'a Target,
+    /// Declared type of typed variables and temporaries, everything else is a signed word.
+    types: &'a HashMap<Value, IntType>,
+    /// Word-sized pieces of every value wider than the machine word, lowest first.
+    /// The lowest piece is the value itself.
+    pieces: HashMap<Value, Vec<Value>>,
     next_temp: u32,
+    next_var: u32,
+    next_label: u32,
+    routines: Vec<(Routine, Frame)>,
 }
 
 impl<'a> Legalizer<'a> {
-    pub fn new(instrs: &'a Vec<Instr>) -> Self {
-        Self { instrs, next_temp: instrs.iter().filter_map(|instr| {
+    pub fn new(instrs: &'a Vec<Instr>, types: &'a HashMap<Value, IntType>, target: &'a Target) -> Self {
+        Self { instrs, target, types, pieces: HashMap::new(), next_temp: instrs.iter().filter_map(|instr| {
+            match instr.def() {
+                Some(Value::Temp(id)) => Some(id),
+                _ => None,
+            }
+        }).max().unwrap_or(0) + 1,
+        next_var: instrs.iter().flat_map(|instr| instr.defs().into_iter().chain(instr.uses())).filter_map(|value| {
+            match value {
+                Value::Var(id) => Some(id),
+                _ => None,
+            }
+        }).max().unwrap_or(0) + 1,
+        next_label: instrs.iter().filter_map(|instr| {
             match instr {
-                Instr::Immediate { dst, .. } | Instr::Move { dst, .. } | Instr::Load { dst, .. } | Instr::Store { dst, .. } |
-                Instr::Add { dst, .. } | Instr::Sub { dst, .. } | Instr::AddImmediate { dst, .. } | Instr::Mul { dst, .. } |
-                Instr::CmpGt { dst, .. } => {
-                    if let Value::Temp(id) = dst {
-                        Some(*id)
-                    } else {
-                        None
-                    }
-                }
+                Instr::Label(Label(id)) => Some(*id),
                 _ => None,
             }
-        }).max().unwrap_or(0) + 1 }
+        }).max().unwrap_or(0) + 1,
+        routines: Vec::new() }
     }
 
     fn get_next_temp(&mut self) -> Value {
@@ -29,69 +70,1062 @@ impl<'a> Legalizer<'a> {
         temp
     }
 
+    fn get_next_var(&mut self) -> Value {
+        let var = Value::Var(self.next_var);
+        self.next_var += 1;
+        var
+    }
+
+    fn get_next_label(&mut self) -> Label {
+        let label = Label(self.next_label);
+        self.next_label += 1;
+        label
+    }
+
     pub fn legalize(&mut self) -> Vec<Instr> {
         let mut legalized_instrs = Vec::new();
 
         for instr in self.instrs.iter() {
-            match instr {
-                Instr::Move { dst, src } => match (dst, src) {
-                    (Value::Var(_), Value::Const(_)) => {
-                        legalized_instrs.push(Instr::Immediate {
-                            dst: dst.clone(),
-                            value: src.clone(),
+            for instr in self.split_wide(instr) {
+                self.legalize_instr(&mut legalized_instrs, &instr);
+            }
+        }
+
+        self.emit_routines(&mut legalized_instrs);
+
+        legalized_instrs
+    }
+
+    /// The pieces of every multi-word value, which the allocator spills together.
+    pub fn units(&self) -> Vec<Vec<Value>> {
+        self.pieces.values().cloned().collect()
+    }
+
+    fn piece_count(&self, value: &Value) -> usize {
+        match (value, self.types.get(value)) {
+            (Value::Var(_) | Value::Temp(_), Some(ty)) => ty.bits.div_ceil(self.target.word_bits) as usize,
+            _ => 1,
+        }
+    }
+
+    /// Piece `i` of `value`. Narrower values are extended with copies of `fill`.
+    fn piece(&mut self, value: Value, i: usize, fill: Value) -> Value {
+        if let Value::Const(c) = value {
+            let shift = (i as u32 * self.target.word_bits).min(31);
+            return Value::Const(self.target.signed(c >> shift));
+        }
+
+        let count = self.piece_count(&value);
+        if i >= count {
+            return fill;
+        }
+        if i == 0 {
+            return value;
+        }
+
+        if !self.pieces.contains_key(&value) {
+            let mut pieces = vec![value];
+            for _ in 1..count {
+                pieces.push(match value {
+                    Value::Var(_) => self.get_next_var(),
+                    _ => self.get_next_temp(),
+                });
+            }
+            self.pieces.insert(value, pieces);
+        }
+
+        self.pieces[&value][i]
+    }
+
+    /// The value the upper pieces of a word-sized `value` take when it is widened.
+    fn fill(&mut self, out: &mut Vec<Instr>, value: Value) -> Value {
+        let signed = self.types.get(&value).is_none_or(|ty| ty.signed);
+
+        match value {
+            Value::Var(_) | Value::Temp(_) if signed && self.piece_count(&value) == 1 => {
+                let negative = self.get_next_temp();
+                let fill = self.get_next_temp();
+                out.push(Instr::Cmp { cond: Cond::Lt, dst: negative, lhs: value, rhs: Value::Const(0) });
+                out.push(Instr::Sub { dst: fill, lhs: Value::Const(0), rhs: negative });
+                fill
+            }
+            _ => Value::Const(0),
+        }
+    }
+
+    /// Rewrites an instruction on values wider than the machine word into instructions
+    /// on their pieces. Everything else is returned unchanged.
+    fn split_wide(&mut self, instr: &Instr) -> Vec<Instr> {
+        let mut out = Vec::new();
+
+        let wide = instr.def().into_iter().chain(instr.uses()).any(|value| self.piece_count(&value) > 1);
+        if !wide {
+            return vec![instr.clone()];
+        }
+
+        match instr {
+            Instr::Immediate { dst, value: src } | Instr::Move { dst, src } => {
+                let fill = self.fill(&mut out, *src);
+
+                for i in 0..self.piece_count(dst) {
+                    let (dst, src) = (self.piece(*dst, i, fill), self.piece(*src, i, fill));
+                    out.push(Instr::Move { dst, src });
+                }
+            }
+            Instr::Add { dst, lhs, rhs } | Instr::Sub { dst, lhs, rhs } => {
+                let is_add = matches!(instr, Instr::Add { .. });
+                let count = self.piece_count(dst);
+                let (lhs_fill, rhs_fill) = (self.fill(&mut out, *lhs), self.fill(&mut out, *rhs));
+                let operands: Vec<_> = (0..count)
+                    .map(|i| (self.piece(*dst, i, Value::Const(0)), self.piece(*lhs, i, lhs_fill), self.piece(*rhs, i, rhs_fill)))
+                    .collect();
+
+                if self.target.carry {
+                    out.push(Instr::ClearCarry);
+                    for (dst, lhs, rhs) in operands {
+                        out.push(match is_add {
+                            true => Instr::AddCarry { dst, lhs, rhs },
+                            false => Instr::SubBorrow { dst, lhs, rhs },
                         });
                     }
-                    _ => {
-                        legalized_instrs.push(instr.clone());
+                } else {
+                    self.emulate_carry_chain(&mut out, is_add, &operands);
+                }
+            }
+            Instr::And { dst, lhs, rhs } | Instr::Or { dst, lhs, rhs } | Instr::Xor { dst, lhs, rhs } => {
+                let (lhs_fill, rhs_fill) = (self.fill(&mut out, *lhs), self.fill(&mut out, *rhs));
+
+                for i in 0..self.piece_count(dst) {
+                    let (dst, lhs, rhs) = (self.piece(*dst, i, Value::Const(0)), self.piece(*lhs, i, lhs_fill), self.piece(*rhs, i, rhs_fill));
+                    out.push(match instr {
+                        Instr::And { .. } => Instr::And { dst, lhs, rhs },
+                        Instr::Or { .. } => Instr::Or { dst, lhs, rhs },
+                        _ => Instr::Xor { dst, lhs, rhs },
+                    });
+                }
+            }
+            Instr::Not { dst, src } => {
+                let fill = self.fill(&mut out, *src);
+
+                for i in 0..self.piece_count(dst) {
+                    let (dst, src) = (self.piece(*dst, i, Value::Const(0)), self.piece(*src, i, fill));
+                    out.push(Instr::Not { dst, src });
+                }
+            }
+            Instr::Cmp { cond, dst, lhs, rhs } => {
+                self.split_cmp(&mut out, *cond, *dst, *lhs, *rhs);
+                for i in 1..self.piece_count(dst) {
+                    let dst = self.piece(*dst, i, Value::Const(0));
+                    out.push(Instr::Move { dst, src: Value::Const(0) });
+                }
+            }
+            Instr::JumpIfFalse { cond, target } => {
+                let nonzero = self.get_next_temp();
+                self.split_cmp(&mut out, Cond::Ne, nonzero, *cond, Value::Const(0));
+                out.push(Instr::JumpIfFalse { cond: nonzero, target: *target });
+            }
+            // Everything else only reads a wide value through its lowest piece, which
+            // truncates it to the machine word, and computes a single word that is then
+            // extended into a wide destination.
+            _ => {
+                out.push(instr.clone());
+
+                if let Some(dst) = instr.def()
+                    && self.piece_count(&dst) > 1
+                {
+                    let fill = match instr {
+                        Instr::DivU { .. } | Instr::ModU { .. } | Instr::ShrU { .. } => Value::Const(0),
+                        _ => {
+                            let negative = self.get_next_temp();
+                            let fill = self.get_next_temp();
+                            out.push(Instr::Cmp { cond: Cond::Lt, dst: negative, lhs: dst, rhs: Value::Const(0) });
+                            out.push(Instr::Sub { dst: fill, lhs: Value::Const(0), rhs: negative });
+                            fill
+                        }
+                    };
+
+                    for i in 1..self.piece_count(&dst) {
+                        let piece = self.piece(dst, i, fill);
+                        out.push(Instr::Move { dst: piece, src: fill });
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Adds or subtracts piece by piece, computing the carry (or borrow) of every piece
+    /// with unsigned compares. Results go through temporaries in case `dst` is an operand.
+    fn emulate_carry_chain(&mut self, out: &mut Vec<Instr>, is_add: bool, operands: &[(Value, Value, Value)]) {
+        let mut carry: Option<Value> = None;
+        let mut results = Vec::new();
+
+        for (i, &(_, lhs, rhs)) in operands.iter().enumerate() {
+            let partial = self.get_next_temp();
+            out.push(match is_add {
+                true => Instr::Add { dst: partial, lhs, rhs },
+                false => Instr::Sub { dst: partial, lhs, rhs },
+            });
+
+            let result = match carry {
+                Some(carry) => {
+                    let result = self.get_next_temp();
+                    out.push(match is_add {
+                        true => Instr::Add { dst: result, lhs: partial, rhs: carry },
+                        false => Instr::Sub { dst: result, lhs: partial, rhs: carry },
+                    });
+                    result
+                }
+                None => partial,
+            };
+
+            if i + 1 < operands.len() {
+                // A carry leaves the piece when the result wrapped below an operand,
+                // a borrow when more was subtracted than there was.
+                let first = self.get_next_temp();
+                match is_add {
+                    true => out.push(Instr::Cmp { cond: Cond::LtU, dst: first, lhs: partial, rhs: lhs }),
+                    false => out.push(Instr::Cmp { cond: Cond::LtU, dst: first, lhs, rhs }),
+                }
+
+                carry = Some(match carry {
+                    Some(carry) => {
+                        let second = self.get_next_temp();
+                        let next = self.get_next_temp();
+                        match is_add {
+                            true => out.push(Instr::Cmp { cond: Cond::LtU, dst: second, lhs: result, rhs: partial }),
+                            false => out.push(Instr::Cmp { cond: Cond::LtU, dst: second, lhs: partial, rhs: carry }),
+                        }
+                        out.push(Instr::Add { dst: next, lhs: first, rhs: second });
+                        next
                     }
-                },
-                Instr::Add { dst, lhs, rhs } => match (lhs, rhs) {
+                    None => first,
+                });
+            }
+
+            results.push(result);
+        }
+
+        for (&(dst, ..), result) in operands.iter().zip(results) {
+            out.push(Instr::Move { dst, src: result });
+        }
+    }
+
+    /// Compares multi-word values. Equality needs every piece to match, an ordering is
+    /// decided by the highest piece that differs, of which only the top one is signed.
+    fn split_cmp(&mut self, out: &mut Vec<Instr>, cond: Cond, dst: Value, lhs: Value, rhs: Value) {
+        let count = self.piece_count(&lhs).max(self.piece_count(&rhs));
+        let (lhs_fill, rhs_fill) = (self.fill(out, lhs), self.fill(out, rhs));
+        let pieces: Vec<_> = (0..count).map(|i| (self.piece(lhs, i, lhs_fill), self.piece(rhs, i, rhs_fill))).collect();
+
+        match cond {
+            Cond::Eq | Cond::Ne => {
+                let mut equal = None;
+
+                for (lhs, rhs) in pieces {
+                    let piece_equal = self.get_next_temp();
+                    out.push(Instr::Cmp { cond: Cond::Eq, dst: piece_equal, lhs, rhs });
+
+                    equal = Some(match equal {
+                        Some(equal) => {
+                            let both = self.get_next_temp();
+                            out.push(Instr::And { dst: both, lhs: equal, rhs: piece_equal });
+                            both
+                        }
+                        None => piece_equal,
+                    });
+                }
+
+                match cond {
+                    Cond::Eq => out.push(Instr::Move { dst, src: equal.unwrap() }),
+                    _ => out.push(Instr::Sub { dst, lhs: Value::Const(1), rhs: equal.unwrap() }),
+                }
+            }
+            _ => {
+                // Only `<` and `<=` are built, the others compare with swapped operands.
+                let (cond, pieces) = match cond {
+                    Cond::Gt | Cond::Ge | Cond::GtU | Cond::GeU => (cond.swapped(), pieces.into_iter().map(|(l, r)| (r, l)).collect()),
+                    _ => (cond, pieces),
+                };
+                let signed = matches!(cond, Cond::Lt | Cond::Le);
+                let lowest = if matches!(cond, Cond::Le | Cond::LeU) { Cond::LeU } else { Cond::LtU };
+
+                let mut result = self.get_next_temp();
+                out.push(Instr::Cmp { cond: lowest, dst: result, lhs: pieces[0].0, rhs: pieces[0].1 });
+
+                for (i, &(lhs, rhs)) in pieces.iter().enumerate().skip(1) {
+                    let less = self.get_next_temp();
+                    let equal = self.get_next_temp();
+                    let tied = self.get_next_temp();
+                    let next = self.get_next_temp();
+                    let piece_cond = if signed && i + 1 == count { Cond::Lt } else { Cond::LtU };
+
+                    out.push(Instr::Cmp { cond: piece_cond, dst: less, lhs, rhs });
+                    out.push(Instr::Cmp { cond: Cond::Eq, dst: equal, lhs, rhs });
+                    out.push(Instr::And { dst: tied, lhs: equal, rhs: result });
+                    out.push(Instr::Add { dst: next, lhs: less, rhs: tied });
+                    result = next;
+                }
+
+                out.push(Instr::Move { dst, src: result });
+            }
+        }
+    }
+
+    fn legalize_instr(&mut self, out: &mut Vec<Instr>, instr: &Instr) {
+        match instr {
+            Instr::Move { dst, src } => match (dst, src) {
+                (Value::Var(_), Value::Const(c)) => {
+                    out.push(Instr::Immediate {
+                        dst: dst.clone(),
+                        value: self.word(*c),
+                    });
+                }
+                _ => {
+                    out.push(instr.clone());
+                }
+            },
+            Instr::Add { dst, lhs, rhs } => match (lhs, rhs) {
+                (Value::Const(l), Value::Const(r)) => {
+                    out.push(Instr::Immediate {
+                        dst: dst.clone(),
+                        value: self.word(l.wrapping_add(*r)),
+                    });
+                }
+                (val, Value::Const(c)) | (Value::Const(c), val) => {
+                    out.push(Instr::AddImmediate {
+                        dst: dst.clone(),
+                        lhs: val.clone(),
+                        imm: *c,
+                    });
+                }
+                _ => {
+                    out.push(instr.clone());
+                }
+            },
+            Instr::Sub { dst, lhs, rhs } => {
+                match (lhs, rhs) {
                     (Value::Const(l), Value::Const(r)) => {
-                        legalized_instrs.push(Instr::Immediate {
+                        out.push(Instr::Immediate {
                             dst: dst.clone(),
-                            value: Value::Const(l + r),
+                            value: self.word(l.wrapping_sub(*r)),
                         });
                     }
-                    (val, Value::Const(c)) | (Value::Const(c), val) => {
-                        legalized_instrs.push(Instr::AddImmediate {
-                            dst: dst.clone(),
-                            lhs: val.clone(),
-                            imm: *c,
-                        });
+                    (val, Value::Const(c)) => {
+                        // out.push(Instr::AddImmediate {
+                        //     dst: dst.clone(),
+                        //     lhs: val.clone(),
+                        //     imm: -(*c),
+                        // });
+                        let temp = self.get_next_temp();
+                        out.push(Instr::Immediate { dst: temp, value: Value::Const(*c) });
+                        out.push(Instr::Sub { dst: dst.clone(), lhs: val.clone(), rhs: temp });
+                    }
+                    (Value::Const(c), val) => {
+                        let temp = self.get_next_temp();
+                        out.push(Instr::Immediate { dst: temp, value: Value::Const(*c) });
+                        out.push(Instr::Sub { dst: dst.clone(), lhs: temp, rhs: val.clone() });
                     }
                     _ => {
-                        legalized_instrs.push(instr.clone());
-                    }
-                },
-                Instr::Sub { dst, lhs, rhs } => {
-                    match (lhs, rhs) {
-                        (Value::Const(l), Value::Const(r)) => {
-                            legalized_instrs.push(Instr::Immediate {
-                                dst: dst.clone(),
-                                value: Value::Const(l - r),
-                            });
-                        }
-                        (val, Value::Const(c)) | (Value::Const(c), val) => {
-                            // legalized_instrs.push(Instr::AddImmediate {
-                            //     dst: dst.clone(),
-                            //     lhs: val.clone(),
-                            //     imm: -(*c),
-                            // });
-                            let temp = self.get_next_temp();
-                            legalized_instrs.push(Instr::Immediate { dst: temp, value: Value::Const(*c) });
-                            legalized_instrs.push(Instr::Sub { dst: dst.clone(), lhs: val.clone(), rhs: temp });
-                        }
-                        _ => {
-                            legalized_instrs.push(instr.clone());
-                        }
+                        out.push(instr.clone());
                     }
                 }
+            }
+            Instr::Cmp { cond, dst, lhs, rhs } => match (lhs, rhs) {
+                (Value::Const(l), Value::Const(r)) => {
+                    out.push(Instr::Immediate {
+                        dst: *dst,
+                        value: Value::Const(cond.holds(*l, *r, self.target.word_bits) as i32),
+                    });
+                }
+                _ => {
+                    self.legalize_cmp(out, *cond, *dst, *lhs, *rhs);
+                }
+            },
+            Instr::Mul { dst, lhs, rhs } => match (lhs, rhs) {
+                (Value::Const(l), Value::Const(r)) => {
+                    out.push(Instr::Immediate {
+                        dst: *dst,
+                        value: self.word(l.wrapping_mul(*r)),
+                    });
+                }
+                (val, Value::Const(c)) | (Value::Const(c), val) => {
+                    self.multiply_by_constant(out, *dst, *val, *c);
+                }
+                _ if self.target.multiply => {
+                    out.push(instr.clone());
+                }
                 _ => {
-                    legalized_instrs.push(instr.clone());
+                    self.call_routine(out, Routine::Mul, &[*lhs, *rhs], &[Some(*dst)]);
+                }
+            },
+            Instr::Div { dst, lhs, rhs } | Instr::Mod { dst, lhs, rhs } => {
+                let is_div = matches!(instr, Instr::Div { .. });
+
+                match (lhs, rhs) {
+                    (Value::Const(l), Value::Const(r)) if self.target.signed(*r) != 0 => {
+                        let (l, r) = (self.target.signed(*l), self.target.signed(*r));
+                        let value = if is_div { l.wrapping_div(r) } else { l.wrapping_rem(r) };
+                        out.push(Instr::Immediate { dst: *dst, value: self.word(value) });
+                    }
+                    (_, Value::Const(1 | -1)) if !is_div => {
+                        out.push(Instr::Immediate { dst: *dst, value: Value::Const(0) });
+                    }
+                    (val, Value::Const(1)) => {
+                        out.push(Instr::Move { dst: *dst, src: *val });
+                    }
+                    (val, Value::Const(-1)) => {
+                        out.push(Instr::Sub { dst: *dst, lhs: Value::Const(0), rhs: *val });
+                    }
+                    (val, Value::Const(c)) if Self::power_of_two(self.target.signed(*c).unsigned_abs()).is_some() => {
+                        self.divide_by_power_of_two(out, is_div, *dst, *val, self.target.signed(*c));
+                    }
+                    _ if self.target.divide => {
+                        out.push(instr.clone());
+                    }
+                    _ => {
+                        let results = if is_div { [Some(*dst), None] } else { [None, Some(*dst)] };
+                        self.call_routine(out, Routine::SDivMod, &[*lhs, *rhs], &results);
+                    }
+                }
+            }
+            Instr::DivU { dst, lhs, rhs } | Instr::ModU { dst, lhs, rhs } => {
+                let is_div = matches!(instr, Instr::DivU { .. });
+
+                match (lhs, rhs) {
+                    (Value::Const(l), Value::Const(r)) if self.target.unsigned(*r) != 0 => {
+                        let (l, r) = (self.target.unsigned(*l), self.target.unsigned(*r));
+                        let value = if is_div { l / r } else { l % r };
+                        out.push(Instr::Immediate { dst: *dst, value: self.word(value as i32) });
+                    }
+                    (_, Value::Const(1)) if !is_div => {
+                        out.push(Instr::Immediate { dst: *dst, value: Value::Const(0) });
+                    }
+                    (val, Value::Const(1)) => {
+                        out.push(Instr::Move { dst: *dst, src: *val });
+                    }
+                    (val, Value::Const(c)) if Self::power_of_two(self.target.unsigned(*c)).is_some() => {
+                        let c = self.target.unsigned(*c);
+                        let instr = match is_div {
+                            true => Instr::ShrU { dst: *dst, lhs: *val, rhs: Value::Const(c.trailing_zeros() as i32) },
+                            false => Instr::And { dst: *dst, lhs: *val, rhs: self.word(c as i32 - 1) },
+                        };
+                        self.legalize_instr(out, &instr);
+                    }
+                    _ if self.target.divide => {
+                        out.push(instr.clone());
+                    }
+                    _ => {
+                        let results = if is_div { [Some(*dst), None] } else { [None, Some(*dst)] };
+                        self.call_routine(out, Routine::UDivMod, &[*lhs, *rhs], &results);
+                    }
+                }
+            }
+            Instr::And { dst, lhs, rhs } | Instr::Or { dst, lhs, rhs } | Instr::Xor { dst, lhs, rhs } => {
+                match (lhs, rhs) {
+                    (Value::Const(l), Value::Const(r)) => {
+                        let value = match instr {
+                            Instr::And { .. } => l & r,
+                            Instr::Or { .. } => l | r,
+                            _ => l ^ r,
+                        };
+                        out.push(Instr::Immediate { dst: *dst, value: self.word(value) });
+                    }
+                    _ => match instr {
+                        Instr::And { .. } => self.legalize_and(out, *dst, *lhs, *rhs),
+                        Instr::Or { .. } => self.legalize_or(out, *dst, *lhs, *rhs),
+                        _ => self.legalize_xor(out, *dst, *lhs, *rhs),
+                    },
                 }
             }
+            Instr::Not { dst, src } => match src {
+                Value::Const(c) => {
+                    out.push(Instr::Immediate { dst: *dst, value: self.word(!c) });
+                }
+                _ => self.legalize_not(out, *dst, *src),
+            },
+            Instr::Shl { dst, lhs, rhs } | Instr::Shr { dst, lhs, rhs } | Instr::ShrU { dst, lhs, rhs } => {
+                match (lhs, rhs) {
+                    (Value::Const(l), Value::Const(r)) => {
+                        let value = match instr {
+                            Instr::Shl { .. } => self.target.shl(*l, *r),
+                            Instr::Shr { .. } => self.target.shr(*l, *r),
+                            _ => self.target.shr_u(*l, *r),
+                        };
+                        out.push(Instr::Immediate { dst: *dst, value: self.word(value) });
+                    }
+                    (val, Value::Const(c)) if self.target.unsigned(*c) == 0 => {
+                        out.push(Instr::Move { dst: *dst, src: *val });
+                    }
+                    _ if self.target.shifts == Shifts::Any => {
+                        out.push(instr.clone());
+                    }
+                    _ => self.legalize_shift(out, instr),
+                }
+            }
+            _ => {
+                out.push(instr.clone());
+            }
         }
+    }
 
-        legalized_instrs
+    /// A folded constant, wrapped around to the machine word.
+    fn word(&self, value: i32) -> Value {
+        Value::Const(self.target.signed(value))
+    }
+
+    /// Multiplies by a constant through doubling and adding, one step per bit of the constant.
+    fn multiply_by_constant(&mut self, out: &mut Vec<Instr>, dst: Value, val: Value, c: i32) {
+        match c {
+            0 => out.push(Instr::Immediate { dst, value: Value::Const(0) }),
+            1 => out.push(Instr::Move { dst, src: val }),
+            -1 => out.push(Instr::Sub { dst, lhs: Value::Const(0), rhs: val }),
+            _ => {
+                let magnitude = c.unsigned_abs();
+                let bits = 32 - magnitude.leading_zeros();
+
+                let mut steps = Vec::new();
+                let mut acc = val;
+                for bit in (0..bits - 1).rev() {
+                    let doubled = self.get_next_temp();
+                    steps.push(Instr::Add { dst: doubled, lhs: acc, rhs: acc });
+                    acc = doubled;
+
+                    if magnitude >> bit & 1 == 1 {
+                        let added = self.get_next_temp();
+                        steps.push(Instr::Add { dst: added, lhs: acc, rhs: val });
+                        acc = added;
+                    }
+                }
+
+                if c < 0 {
+                    steps.push(Instr::Sub { dst, lhs: Value::Const(0), rhs: acc });
+                } else if let Some(Instr::Add { dst: last, .. }) = steps.last_mut() {
+                    *last = dst;
+                }
+
+                out.extend(steps);
+            }
+        }
+    }
+
+    /// The exponent of `magnitude` if it is a power of two above 1.
+    fn power_of_two(magnitude: u32) -> Option<u32> {
+        (magnitude > 1 && magnitude.is_power_of_two()).then(|| magnitude.trailing_zeros())
+    }
+
+    /// Signed division or remainder by `±2^k`. An arithmetic shift rounds towards negative
+    /// infinity, so negative dividends are first biased by `2^k - 1` to round towards zero.
+    fn divide_by_power_of_two(&mut self, out: &mut Vec<Instr>, is_div: bool, dst: Value, val: Value, c: i32) {
+        let k = Self::power_of_two(c.unsigned_abs()).unwrap();
+        let mask = self.word(((1u32 << k) - 1) as i32);
+        let (negative, sign, bias, biased) = (self.get_next_temp(), self.get_next_temp(), self.get_next_temp(), self.get_next_temp());
+
+        let mut steps = vec![
+            Instr::Cmp { cond: Cond::Lt, dst: negative, lhs: val, rhs: Value::Const(0) },
+            Instr::Sub { dst: sign, lhs: Value::Const(0), rhs: negative },
+            Instr::And { dst: bias, lhs: sign, rhs: mask },
+            Instr::Add { dst: biased, lhs: val, rhs: bias },
+        ];
+
+        if is_div && c > 0 {
+            steps.push(Instr::Shr { dst, lhs: biased, rhs: Value::Const(k as i32) });
+        } else if is_div {
+            let quotient = self.get_next_temp();
+            steps.push(Instr::Shr { dst: quotient, lhs: biased, rhs: Value::Const(k as i32) });
+            steps.push(Instr::Sub { dst, lhs: Value::Const(0), rhs: quotient });
+        } else {
+            // The remainder takes the sign of the dividend whatever the divisor's sign.
+            let rounded = self.get_next_temp();
+            steps.push(Instr::And { dst: rounded, lhs: biased, rhs: self.word(!((1u32 << k) - 1) as i32) });
+            steps.push(Instr::Sub { dst, lhs: val, rhs: rounded });
+        }
+
+        for step in steps {
+            self.legalize_instr(out, &step);
+        }
+    }
+
+    fn legalize_and(&mut self, out: &mut Vec<Instr>, dst: Value, lhs: Value, rhs: Value) {
+        if self.target.has_logic("and") {
+            out.push(Instr::And { dst, lhs, rhs });
+        } else if self.target.has_logic("or") {
+            // a & b == ~(~a | ~b)
+            let (not_lhs, not_rhs, either) = (self.get_next_temp(), self.get_next_temp(), self.get_next_temp());
+            self.legalize_not(out, not_lhs, lhs);
+            self.legalize_not(out, not_rhs, rhs);
+            out.push(Instr::Or { dst: either, lhs: not_lhs, rhs: not_rhs });
+            self.legalize_not(out, dst, either);
+        } else {
+            self.call_routine(out, Routine::And, &[lhs, rhs], &[Some(dst)]);
+        }
+    }
+
+    fn legalize_or(&mut self, out: &mut Vec<Instr>, dst: Value, lhs: Value, rhs: Value) {
+        if self.target.has_logic("or") {
+            out.push(Instr::Or { dst, lhs, rhs });
+            return;
+        }
+
+        // a | b == (a + b) - (a & b), the bits set in both are the ones counted twice.
+        let (both, sum) = (self.get_next_temp(), self.get_next_temp());
+        self.legalize_and(out, both, lhs, rhs);
+        out.push(Instr::Add { dst: sum, lhs, rhs });
+        out.push(Instr::Sub { dst, lhs: sum, rhs: both });
+    }
+
+    fn legalize_xor(&mut self, out: &mut Vec<Instr>, dst: Value, lhs: Value, rhs: Value) {
+        if self.target.has_logic("xor") {
+            out.push(Instr::Xor { dst, lhs, rhs });
+            return;
+        }
+
+        // a ^ b == (a | b) - (a & b)
+        let (either, both) = (self.get_next_temp(), self.get_next_temp());
+        self.legalize_or(out, either, lhs, rhs);
+        self.legalize_and(out, both, lhs, rhs);
+        out.push(Instr::Sub { dst, lhs: either, rhs: both });
+    }
+
+    fn legalize_not(&mut self, out: &mut Vec<Instr>, dst: Value, src: Value) {
+        if self.target.has_logic("not") {
+            out.push(Instr::Not { dst, src });
+        } else {
+            // ~a == -1 - a
+            out.push(Instr::Sub { dst, lhs: Value::Const(-1), rhs: src });
+        }
+    }
+
+    /// Rewrites a shift for targets that shift by a single bit or not at all.
+    fn legalize_shift(&mut self, out: &mut Vec<Instr>, instr: &Instr) {
+        let (dst, lhs, rhs) = match instr {
+            Instr::Shl { dst, lhs, rhs } | Instr::Shr { dst, lhs, rhs } | Instr::ShrU { dst, lhs, rhs } => (*dst, *lhs, *rhs),
+            _ => unreachable!(),
+        };
+
+        let single = self.target.shifts == Shifts::Single;
+        let step = |dst: Value, src: Value| match instr {
+            Instr::Shl { .. } if single => Instr::Shl { dst, lhs: src, rhs: Value::Const(1) },
+            Instr::Shl { .. } => Instr::Add { dst, lhs: src, rhs: src },
+            Instr::Shr { .. } => Instr::Shr { dst, lhs: src, rhs: Value::Const(1) },
+            _ => Instr::ShrU { dst, lhs: src, rhs: Value::Const(1) },
+        };
+
+        if !single && !matches!(instr, Instr::Shl { .. }) {
+            let routine = if matches!(instr, Instr::Shr { .. }) { Routine::Shr } else { Routine::ShrU };
+            self.call_routine(out, routine, &[lhs, rhs], &[Some(dst)]);
+            return;
+        }
+
+        match rhs {
+            Value::Const(amount) => {
+                // A word's worth of steps already shifts every bit out.
+                let steps = self.target.unsigned(amount).min(self.target.word_bits);
+                let mut acc = lhs;
+
+                for i in 0..steps {
+                    let next = if i + 1 == steps { dst } else { self.get_next_temp() };
+                    out.push(step(next, acc));
+                    acc = next;
+                }
+            }
+            _ => {
+                let shifted = self.get_next_var();
+                out.push(Instr::Move { dst: shifted, src: lhs });
+
+                let count = self.clamp_shift_amount(out, rhs);
+                self.emit_counted_loop(out, count, |_, out| out.push(step(shifted, shifted)));
+
+                out.push(Instr::Move { dst, src: shifted });
+            }
+        }
+    }
+
+    // Runtime routines
+
+    fn frame(&mut self, routine: Routine) -> Frame {
+        if let Some((_, frame)) = self.routines.iter().find(|(r, _)| *r == routine) {
+            return frame.clone();
+        }
+
+        let frame = Frame {
+            label: self.get_next_label(),
+            params: vec![self.get_next_var(), self.get_next_var()],
+            results: match routine {
+                Routine::Mul | Routine::And | Routine::Shr | Routine::ShrU => vec![self.get_next_var()],
+                Routine::UDivMod | Routine::SDivMod => vec![self.get_next_var(), self.get_next_var()],
+            },
+        };
+
+        self.routines.push((routine, frame.clone()));
+        frame
+    }
+
+    /// Passes `args` to `routine` and copies each requested result into its destination.
+    fn call_routine(&mut self, out: &mut Vec<Instr>, routine: Routine, args: &[Value], results: &[Option<Value>]) {
+        let frame = self.frame(routine);
+
+        for (param, arg) in frame.params.iter().zip(args) {
+            out.push(Instr::Move { dst: *param, src: *arg });
+        }
+
+        out.push(Instr::Call(frame.label));
+
+        for (result, dst) in frame.results.iter().zip(results) {
+            if let Some(dst) = dst {
+                out.push(Instr::Move { dst: *dst, src: *result });
+            }
+        }
+    }
+
+    /// Appends the body of every routine that was called. Routines may call each
+    /// other, so this runs until no new routine gets pulled in.
+    fn emit_routines(&mut self, out: &mut Vec<Instr>) {
+        let mut emitted = 0;
+
+        while emitted < self.routines.len() {
+            let (routine, frame) = self.routines[emitted].clone();
+            emitted += 1;
+
+            out.push(Instr::Label(frame.label));
+
+            match routine {
+                Routine::Mul => self.emit_mul(out, &frame),
+                Routine::UDivMod => self.emit_udivmod(out, &frame),
+                Routine::SDivMod => self.emit_sdivmod(out, &frame),
+                Routine::And => self.emit_and(out, &frame),
+                Routine::Shr | Routine::ShrU => self.emit_shift_right(out, &frame, routine == Routine::Shr),
+            }
+
+            out.push(Instr::Return);
+        }
+    }
+
+    /// Emits `while counter > 0 { body; counter -= 1; }` for `word_bits` iterations.
+    fn emit_bit_loop(&mut self, out: &mut Vec<Instr>, body: impl FnOnce(&mut Self, &mut Vec<Instr>)) {
+        self.emit_counted_loop(out, Value::Const(self.target.word_bits as i32), body);
+    }
+
+    /// Emits `counter = count; while counter > 0 { body; counter -= 1; }`.
+    fn emit_counted_loop(&mut self, out: &mut Vec<Instr>, count: Value, body: impl FnOnce(&mut Self, &mut Vec<Instr>)) {
+        let counter = self.get_next_var();
+        let start = self.get_next_label();
+        let end = self.get_next_label();
+
+        match count {
+            Value::Const(_) => out.push(Instr::Immediate { dst: counter, value: count }),
+            _ => out.push(Instr::Move { dst: counter, src: count }),
+        }
+        out.push(Instr::Label(start));
+
+        let more = self.get_next_temp();
+        self.legalize_cmp(out, Cond::Gt, more, counter, Value::Const(0));
+        out.push(Instr::JumpIfFalse { cond: more, target: end });
+
+        body(self, out);
+
+        out.push(Instr::AddImmediate { dst: counter, lhs: counter, imm: -1 });
+        out.push(Instr::Jump(start));
+        out.push(Instr::Label(end));
+    }
+
+    /// A copy of the shift amount `amount` read as unsigned and capped at `word_bits`, which
+    /// already shifts every bit out. Counting down from it needs no more than a word's steps.
+    fn clamp_shift_amount(&mut self, out: &mut Vec<Instr>, amount: Value) -> Value {
+        let word_bits = Value::Const(self.target.word_bits as i32);
+        let (clamped, wide, in_range) = (self.get_next_var(), self.get_next_temp(), self.get_next_label());
+
+        out.push(Instr::Move { dst: clamped, src: amount });
+        self.legalize_cmp(out, Cond::GtU, wide, amount, word_bits);
+        out.push(Instr::JumpIfFalse { cond: wide, target: in_range });
+        out.push(Instr::Immediate { dst: clamped, value: word_bits });
+        out.push(Instr::Label(in_range));
+
+        clamped
+    }
+
+    /// Sets `dst` to 1 if the top bit of the machine word in `value` is set, 0 otherwise.
+    fn emit_top_bit(&mut self, out: &mut Vec<Instr>, dst: Value, value: Value) {
+        if self.target.shifts == Shifts::Any {
+            let amount = Value::Const(self.target.word_bits as i32 - 1);
+            out.push(Instr::ShrU { dst, lhs: value, rhs: amount });
+            return;
+        }
+
+        let sign_bit = 1i32.wrapping_shl(self.target.word_bits - 1);
+        self.legalize_cmp(out, Cond::GeU, dst, value, Value::Const(sign_bit));
+    }
+
+    fn emit_mul(&mut self, out: &mut Vec<Instr>, frame: &Frame) {
+        let (a, b, product) = (frame.params[0], frame.params[1], frame.results[0]);
+
+        // Walks `b` from its top bit down: product = 2 * product + (bit ? a : 0).
+        out.push(Instr::Immediate { dst: product, value: Value::Const(0) });
+
+        self.emit_bit_loop(out, |this, out| {
+            let skip = this.get_next_label();
+            let bit = this.get_next_temp();
+
+            out.push(Instr::Add { dst: product, lhs: product, rhs: product });
+            this.emit_top_bit(out, bit, b);
+            out.push(Instr::JumpIfFalse { cond: bit, target: skip });
+            out.push(Instr::Add { dst: product, lhs: product, rhs: a });
+            out.push(Instr::Label(skip));
+            out.push(Instr::Add { dst: b, lhs: b, rhs: b });
+        });
+    }
+
+    fn emit_udivmod(&mut self, out: &mut Vec<Instr>, frame: &Frame) {
+        let (n, d) = (frame.params[0], frame.params[1]);
+        let (quotient, remainder) = (frame.results[0], frame.results[1]);
+
+        // Restoring division: shift the next bit of `n` into the remainder and subtract `d`
+        // whenever it fits. A remainder that carried out of the word always fits.
+        out.push(Instr::Immediate { dst: quotient, value: Value::Const(0) });
+        out.push(Instr::Immediate { dst: remainder, value: Value::Const(0) });
+
+        self.emit_bit_loop(out, |this, out| {
+            let skip = this.get_next_label();
+            let carry = this.get_next_temp();
+            let bit = this.get_next_temp();
+            let fits = this.get_next_temp();
+            let take = this.get_next_temp();
+
+            this.emit_top_bit(out, carry, remainder);
+            this.emit_top_bit(out, bit, n);
+            out.push(Instr::Add { dst: remainder, lhs: remainder, rhs: remainder });
+            out.push(Instr::Add { dst: remainder, lhs: remainder, rhs: bit });
+            out.push(Instr::Add { dst: n, lhs: n, rhs: n });
+            out.push(Instr::Add { dst: quotient, lhs: quotient, rhs: quotient });
+
+            this.legalize_cmp(out, Cond::GeU, fits, remainder, d);
+            out.push(Instr::Add { dst: take, lhs: carry, rhs: fits });
+            out.push(Instr::JumpIfFalse { cond: take, target: skip });
+            out.push(Instr::Sub { dst: remainder, lhs: remainder, rhs: d });
+            out.push(Instr::AddImmediate { dst: quotient, lhs: quotient, imm: 1 });
+            out.push(Instr::Label(skip));
+        });
+    }
+
+    fn emit_sdivmod(&mut self, out: &mut Vec<Instr>, frame: &Frame) {
+        let (n, d) = (frame.params[0], frame.params[1]);
+        let (quotient, remainder) = (frame.results[0], frame.results[1]);
+
+        // Divide the magnitudes, then the quotient is negative if exactly one operand
+        // was and the remainder takes the sign of the dividend.
+        let n_negative = self.get_next_var();
+        let d_negative = self.get_next_var();
+        self.legalize_cmp(out, Cond::Lt, n_negative, n, Value::Const(0));
+        self.legalize_cmp(out, Cond::Lt, d_negative, d, Value::Const(0));
+
+        self.emit_negate_if(out, n_negative, n);
+        self.emit_negate_if(out, d_negative, d);
+
+        self.call_routine(out, Routine::UDivMod, &[n, d], &[Some(quotient), Some(remainder)]);
+
+        let signs_differ = self.get_next_temp();
+        self.legalize_cmp(out, Cond::Ne, signs_differ, n_negative, d_negative);
+        self.emit_negate_if(out, signs_differ, quotient);
+        self.emit_negate_if(out, n_negative, remainder);
+    }
+
+    fn emit_and(&mut self, out: &mut Vec<Instr>, frame: &Frame) {
+        let (a, b, result) = (frame.params[0], frame.params[1], frame.results[0]);
+
+        // Walks both operands from the top bit down: result = 2 * result + (bit_a & bit_b).
+        out.push(Instr::Immediate { dst: result, value: Value::Const(0) });
+
+        self.emit_bit_loop(out, |this, out| {
+            let bit_a = this.get_next_temp();
+            let bit_b = this.get_next_temp();
+            let sum = this.get_next_temp();
+            let both = this.get_next_temp();
+
+            this.emit_top_bit(out, bit_a, a);
+            this.emit_top_bit(out, bit_b, b);
+            out.push(Instr::Add { dst: sum, lhs: bit_a, rhs: bit_b });
+            this.legalize_cmp(out, Cond::Gt, both, sum, Value::Const(1));
+            out.push(Instr::Add { dst: result, lhs: result, rhs: result });
+            out.push(Instr::Add { dst: result, lhs: result, rhs: both });
+            out.push(Instr::Add { dst: a, lhs: a, rhs: a });
+            out.push(Instr::Add { dst: b, lhs: b, rhs: b });
+        });
+    }
+
+    fn emit_shift_right(&mut self, out: &mut Vec<Instr>, frame: &Frame, arithmetic: bool) {
+        let (x, amount, result) = (frame.params[0], frame.params[1], frame.results[0]);
+        let unchanged = self.get_next_label();
+
+        let amount = self.clamp_shift_amount(out, amount);
+
+        // Shifting right by `n` keeps the top `word_bits - n` bits, so those are walked
+        // into a result that starts out as all copies of the bit shifted in.
+        let positive = self.get_next_temp();
+        self.legalize_cmp(out, Cond::Gt, positive, amount, Value::Const(0));
+        out.push(Instr::JumpIfFalse { cond: positive, target: unchanged });
+
+        out.push(Instr::Immediate { dst: result, value: Value::Const(0) });
+        if arithmetic {
+            let negative = self.get_next_temp();
+            let done = self.get_next_label();
+            self.legalize_cmp(out, Cond::Lt, negative, x, Value::Const(0));
+            out.push(Instr::JumpIfFalse { cond: negative, target: done });
+            out.push(Instr::Immediate { dst: result, value: Value::Const(-1) });
+            out.push(Instr::Label(done));
+        }
+
+        let kept = self.get_next_var();
+        out.push(Instr::Sub { dst: kept, lhs: Value::Const(self.target.word_bits as i32), rhs: amount });
+
+        self.emit_counted_loop(out, kept, |this, out| {
+            let bit = this.get_next_temp();
+
+            this.emit_top_bit(out, bit, x);
+            out.push(Instr::Add { dst: result, lhs: result, rhs: result });
+            out.push(Instr::Add { dst: result, lhs: result, rhs: bit });
+            out.push(Instr::Add { dst: x, lhs: x, rhs: x });
+        });
+        out.push(Instr::Return);
+
+        out.push(Instr::Label(unchanged));
+        out.push(Instr::Move { dst: result, src: x });
+    }
+
+    fn emit_negate_if(&mut self, out: &mut Vec<Instr>, cond: Value, value: Value) {
+        let skip = self.get_next_label();
+
+        out.push(Instr::JumpIfFalse { cond, target: skip });
+        out.push(Instr::Sub { dst: value, lhs: Value::Const(0), rhs: value });
+        out.push(Instr::Label(skip));
+    }
+
+    /// Rewrites a comparison in terms of the ones the target implements.
+    fn legalize_cmp(&mut self, out: &mut Vec<Instr>, cond: Cond, dst: Value, lhs: Value, rhs: Value) {
+        if self.target.supports(cond) {
+            out.push(Instr::Cmp { cond, dst, lhs, rhs });
+            return;
+        }
+
+        if self.target.supports(cond.swapped()) {
+            out.push(Instr::Cmp { cond: cond.swapped(), dst, lhs: rhs, rhs: lhs });
+            return;
+        }
+
+        let negated = cond.negated();
+        if self.target.supports(negated) || self.target.supports(negated.swapped()) {
+            let temp = self.get_next_temp();
+            self.legalize_cmp(out, negated, temp, lhs, rhs);
+            out.push(Instr::Sub { dst, lhs: Value::Const(1), rhs: temp });
+            return;
+        }
+
+        match cond {
+            Cond::Eq | Cond::Ne => {
+                // Equal exactly when neither operand is greater than the other.
+                let greater = self.get_next_temp();
+                let less = self.get_next_temp();
+                self.legalize_cmp(out, Cond::Gt, greater, lhs, rhs);
+                self.legalize_cmp(out, Cond::Gt, less, rhs, lhs);
+
+                if cond == Cond::Eq {
+                    let either = self.get_next_temp();
+                    out.push(Instr::Add { dst: either, lhs: greater, rhs: less });
+                    out.push(Instr::Sub { dst, lhs: Value::Const(1), rhs: either });
+                } else {
+                    out.push(Instr::Add { dst, lhs: greater, rhs: less });
+                }
+            }
+            _ => {
+                // Flipping the sign bit of both operands maps signed order onto unsigned order and back.
+                let lhs = self.flip_sign_bit(out, lhs);
+                let rhs = self.flip_sign_bit(out, rhs);
+                self.legalize_cmp(out, cond.resigned(), dst, lhs, rhs);
+            }
+        }
+    }
+
+    fn flip_sign_bit(&mut self, out: &mut Vec<Instr>, value: Value) -> Value {
+        let sign_bit = 1i32.wrapping_shl(self.target.word_bits - 1);
+
+        match value {
+            Value::Const(c) => Value::Const(c.wrapping_add(sign_bit)),
+            _ => {
+                let temp = self.get_next_temp();
+                out.push(Instr::AddImmediate { dst: temp, lhs: value, imm: sign_bit });
+                temp
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{instructions::Instr, testing::run};
+
+    const TARGET: &str = include_str!("../example.target");
+
+    #[test]
+    fn unsigned_division_by_powers_of_two_is_shifts_and_masks() {
+        let source = "port out @ 200;\nlet y: u8 = 0;\nlet done = false;\nwhile !done {\n\
+            out = y / 4;\nout = y % 16;\nout = y / 128;\ndone = y == 255;\ny = y + 1;\n}\n";
+        let run = run(source, TARGET).unwrap();
+
+        assert!(!run.legalized.iter().any(|instr| matches!(instr, Instr::Call(_))));
+        let expected: Vec<i32> = (0..256).flat_map(|y| [y / 4, y % 16, y / 128]).collect();
+        assert_eq!(run.port_writes, expected);
+    }
+
+    #[test]
+    fn signed_division_by_powers_of_two_rounds_towards_zero() {
+        let source = "port out @ 200;\nconst D = -8;\nconst MIN = -128;\nlet x = -128;\nlet done = false;\nwhile !done {\n\
+            out = x / 4;\nout = x % 4;\nout = x / D;\nout = x % D;\nout = x / MIN;\nout = x % MIN;\ndone = x == 127;\nx = x + 1;\n}\n";
+        let run = run(source, TARGET).unwrap();
+
+        assert!(!run.legalized.iter().any(|instr| matches!(instr, Instr::Call(_))));
+        let expected: Vec<i32> = (-128..128i32)
+            .flat_map(|x| {
+                let x = x as i8;
+                [x / 4, x % 4, x / -8, x % -8, x.wrapping_div(-128), x.wrapping_rem(-128)].map(i32::from)
+            })
+            .collect();
+        assert_eq!(run.port_writes, expected);
+    }
+
+    #[test]
+    fn shift_amounts_are_unsigned_and_clamped_to_the_word() {
+        let source = "port out @ 200;\nlet a = 5;\nlet b = -5;\nlet s: u8 = 200;\nlet t: u8 = 3;\nlet u: u8 = 200;\n\
+            out = a << s;\nout = a >> s;\nout = b >> s;\nout = u >> s;\nout = a << t;\nout = b >> t;\nout = u >> t;\n";
+
+        // Shifts in hardware, one bit at a time and through runtime routines.
+        for target in ["", TARGET, &TARGET.replace("shifts = single", "shifts = none")] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![0, 0, -1, 0, 40, -1, 25]);
+        }
+    }
+
+    #[test]
+    fn sized_types_wrap_around() {
+        let source = "port out @ 200;\nlet a: u8 = 250;\na = a + 10;\nout = a;\nlet b: i8 = 127;\nb = b + 1;\nout = b;\n\
+            let c: u16 = 65535;\nc = c + 2;\nif c == 1 { out = 1; } else { out = 0; }\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![4, -128, 1]);
+        }
+
+        let err = run("let x = 300;\n", "").err().unwrap();
+        assert_eq!(err, "`300` does not fit in the 8-bit machine word at main.tch:1:9");
+    }
+
+    #[test]
+    fn wide_values_carry_and_borrow_between_words() {
+        let source = "port out @ 200;\nlet a: u16 = 200;\nlet b: u16 = 100;\n\
+            let s: u16 = a + b;\nif s == 300 { out = 1; } else { out = 0; }\n\
+            let d: u16 = b - a;\nif d == 65436 { out = 1; } else { out = 0; }\n\
+            if a < s { out = 1; } else { out = 0; }\n\
+            let x: i16 = -1000;\nlet y: i16 = 1000;\nif x < y { out = 1; } else { out = 0; }\n\
+            let z: i16 = x - y;\nif z == -2000 { out = 1; } else { out = 0; }\n\
+            let n: u16 = 0;\nwhile n < 300 { n += 1; }\nif n == 300 { out = 1; } else { out = 0; }\n";
+        for target in ["", TARGET] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![1; 6]);
+        }
     }
 }
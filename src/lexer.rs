@@ -1,5 +1,8 @@
+use std::path::Path;
+
 use crate::{
     errors::CompileError,
+    pbm,
     source_map::SourceMap,
     token::{Keyword, Token, TokenType, TokenValue},
 };
@@ -19,10 +22,10 @@ pub struct LexerContext {
 
 impl<'a> Lexer<'a> {
     // Logical
-    pub fn new(source_map: &'a mut SourceMap) -> Self {
+    pub fn new(source_map: &'a mut SourceMap, source_id: usize) -> Self {
         Lexer {
             contexts: vec![LexerContext {
-                source_id: 0,
+                source_id,
                 pos: 0,
             }],
             source_map,
@@ -103,10 +106,17 @@ impl<'a> Lexer<'a> {
                 self.skip_line_comment();
                 self.get_token()
             }
-            b'+' => self.advance_and(TokenType::PLUS),
-            b'-' => self.advance_and(TokenType::MINUS),
-            b'*' => self.advance_and(TokenType::ASTERISK),
-            b'/' => self.advance_and(TokenType::SLASH),
+            b'+' if matches!(self.peek(), Some(b'+')) => self.double(TokenType::INCREMENT),
+            b'-' if matches!(self.peek(), Some(b'-')) => self.double(TokenType::DECREMENT),
+            b'+' => self.operator(TokenType::PLUS, TokenType::PLUSASSIGN),
+            b'-' => self.operator(TokenType::MINUS, TokenType::MINUSASSIGN),
+            b'*' => self.operator(TokenType::ASTERISK, TokenType::ASTERISKASSIGN),
+            b'/' => self.operator(TokenType::SLASH, TokenType::SLASHASSIGN),
+            b'%' => self.operator(TokenType::PERCENT, TokenType::PERCENTASSIGN),
+            b'&' => self.ampersand(),
+            b'|' => self.pipe(),
+            b'^' => self.operator(TokenType::CARET, TokenType::CARETASSIGN),
+            b'~' => self.advance_and(TokenType::TILDE),
             b'=' => self.equals(),
             b'<' => self.less_than(),
             b'>' => self.greater_than(),
@@ -117,9 +127,14 @@ impl<'a> Lexer<'a> {
             b'[' => self.advance_and(TokenType::LBRACKET),
             b']' => self.advance_and(TokenType::RBRACKET),
             b',' => self.advance_and(TokenType::COMMA),
+            b':' => self.advance_and(TokenType::COLON),
             b';' => self.advance_and(TokenType::SEMICOLON),
+            b'@' => self.advance_and(TokenType::AT),
+            b'.' => self.dot(),
             b'!' => self.bang(),
             b'0'..=b'9' => self.number(),
+            b'\'' => self.character(),
+            b'"' => self.string(),
             b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.literal(),
             _ => Err(CompileError {
                 message: format!("unexpected character '{}'", c as char),
@@ -148,19 +163,59 @@ impl<'a> Lexer<'a> {
 
     // Operators
     fn bang(&mut self) -> LexResult<Token> {
-        let c = self.current().unwrap();
         if matches!(self.peek(), Some(b'=')) {
             self.advance();
             self.advance_and(TokenType::NEQ)
-        } else if matches!(self.peek_n(7), Some(b"include")) {
+        } else if self.directive("include") {
             self.advance(); // skip '!'
             self.include()
+        } else if self.directive("embed") {
+            self.embed()
         } else {
-            panic!(
-                "Lexing error: Unexpected character '{}' at {}",
-                c as char,
-                self.pos()
+            self.advance_and(TokenType::BANG)
+        }
+    }
+
+    /// Whether the `!` here starts the directive `name`, not the negation of an identifier
+    /// that merely begins with it, like `!included`.
+    fn directive(&self, name: &str) -> bool {
+        self.peek_n(name.len()) == Some(name.as_bytes())
+            && !matches!(
+                self.src().get(self.pos() + 1 + name.len()),
+                Some(b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_')
             )
+    }
+
+    /// An operator that is followed by `=` in its compound assignment form.
+    fn operator(&mut self, plain: TokenType, assign: TokenType) -> LexResult<Token> {
+        if matches!(self.peek(), Some(b'=')) {
+            self.advance();
+            self.advance_and(assign)
+        } else {
+            self.advance_and(plain)
+        }
+    }
+
+    fn double(&mut self, token: TokenType) -> LexResult<Token> {
+        self.advance();
+        self.advance_and(token)
+    }
+
+    fn ampersand(&mut self) -> LexResult<Token> {
+        if matches!(self.peek(), Some(b'&')) {
+            self.advance();
+            self.advance_and(TokenType::AND)
+        } else {
+            self.operator(TokenType::AMPERSAND, TokenType::AMPERSANDASSIGN)
+        }
+    }
+
+    fn pipe(&mut self) -> LexResult<Token> {
+        if matches!(self.peek(), Some(b'|')) {
+            self.advance();
+            self.advance_and(TokenType::OR)
+        } else {
+            self.operator(TokenType::PIPE, TokenType::PIPEASSIGN)
         }
     }
 
@@ -171,10 +226,11 @@ impl<'a> Lexer<'a> {
 
         self.skip_whitespace();
         let file_name = self.string_literal()?;
+        let path = self.directive_path(&file_name);
 
         let new_id = self
             .source_map
-            .add_from_file(&file_name)
+            .add_from_file(&path)
             .map_err(|e| CompileError {
                 message: format!("Failed to include file '{}': {}", file_name, e),
                 position: self.pos(),
@@ -189,19 +245,79 @@ impl<'a> Lexer<'a> {
         self.get_token()
     }
 
+    /// Path of the file a directive names, relative to the directory of the file containing
+    /// the directive, like imported modules.
+    fn directive_path(&self, file_name: &str) -> String {
+        let current = &self.source_map.files[self.contexts[self.context_id].source_id].file_name;
+        let directory = Path::new(current).parent().unwrap_or(Path::new(""));
+        directory.join(file_name).to_string_lossy().into_owned()
+    }
+
+    /// Reads the file named by `!embed "file"` into the token's value. PBM images give their
+    /// pixel rows, any other file its bytes.
+    fn embed(&mut self) -> LexResult<Token> {
+        let start = self.pos();
+        let source_id = self.current_context().source_id;
+
+        for _ in 0.."!embed".len() {
+            self.advance();
+        }
+
+        self.skip_whitespace();
+        let file_name = self.string_literal()?;
+        let path = self.directive_path(&file_name);
+
+        let (embedded_id, bytes) = self.source_map.add_binary_file(&path).map_err(|e| CompileError {
+            message: format!("Failed to embed file '{}': {}", file_name, e),
+            position: start,
+            source_id,
+        })?;
+
+        let data = match file_name.to_ascii_lowercase().ends_with(".pbm") {
+            true => pbm::rows(&bytes, embedded_id)?,
+            false => bytes,
+        };
+
+        Ok(Token {
+            token_type: TokenType::EMBED,
+            value: TokenValue::Str(data),
+            position: start,
+            source_id,
+        })
+    }
+
     fn equals(&mut self) -> LexResult<Token> {
         if matches!(self.peek(), Some(b'=')) {
             self.advance();
             self.advance_and(TokenType::EQ)
+        } else if matches!(self.peek(), Some(b'>')) {
+            self.advance();
+            self.advance_and(TokenType::FATARROW)
         } else {
             self.advance_and(TokenType::ASSIGN)
         }
     }
 
+    fn dot(&mut self) -> LexResult<Token> {
+        if matches!(self.peek_n(2), Some(b".=")) {
+            self.advance();
+            self.advance();
+            self.advance_and(TokenType::DOTDOTEQ)
+        } else if matches!(self.peek(), Some(b'.')) {
+            self.advance();
+            self.advance_and(TokenType::DOTDOT)
+        } else {
+            self.advance_and(TokenType::DOT)
+        }
+    }
+
     fn less_than(&mut self) -> LexResult<Token> {
         if matches!(self.peek(), Some(b'=')) {
             self.advance();
             self.advance_and(TokenType::LTE)
+        } else if matches!(self.peek(), Some(b'<')) {
+            self.advance();
+            self.operator(TokenType::SHL, TokenType::SHLASSIGN)
         } else {
             self.advance_and(TokenType::LT)
         }
@@ -211,19 +327,51 @@ impl<'a> Lexer<'a> {
         if matches!(self.peek(), Some(b'=')) {
             self.advance();
             self.advance_and(TokenType::GTE)
+        } else if matches!(self.peek(), Some(b'>')) {
+            self.advance();
+            self.operator(TokenType::SHR, TokenType::SHRASSIGN)
         } else {
             self.advance_and(TokenType::GT)
         }
     }
 
+    /// Reads a decimal, `0x` hexadecimal or `0b` binary literal. Digits may be separated by `_`.
     fn number(&mut self) -> LexResult<Token> {
         let start = self.pos();
+        let source_id = self.current_context().source_id;
+
+        let (radix, name) = match (self.current(), self.peek()) {
+            (Some(b'0'), Some(b'x' | b'X')) => (16, "hexadecimal"),
+            (Some(b'0'), Some(b'b' | b'B')) => (2, "binary"),
+            _ => (10, "decimal"),
+        };
+
+        if radix != 10 {
+            self.advance();
+            self.advance();
+        }
 
-        while matches!(self.current(), Some(b'0'..=b'9')) {
+        let digits_start = self.pos();
+        while matches!(self.current(), Some(b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_')) {
             self.advance();
         }
 
-        let value = Self::parse_i32_ascii(&self.src()[start..self.pos()]);
+        let error = |message: String| CompileError { message, position: start, source_id };
+        let digits = &self.src()[digits_start..self.pos()];
+
+        if let Some(&invalid) = digits.iter().find(|&&b| b != b'_' && !(b as char).is_digit(radix)) {
+            return Err(error(format!("invalid digit `{}` in {} literal", invalid as char, name)));
+        }
+
+        if !digits.iter().any(|&b| b != b'_') {
+            return Err(error(format!("{} literal has no digits", name)));
+        }
+
+        // The resolver checks the value against the type it is used with.
+        let value = Self::parse_i64_ascii(digits, radix).ok_or_else(|| {
+            let literal = String::from_utf8_lossy(&self.src()[start..self.pos()]).into_owned();
+            error(format!("literal `{}` does not fit in a 64-bit integer", literal))
+        })?;
 
         Ok(Token {
             token_type: TokenType::NUMBER,
@@ -233,6 +381,40 @@ impl<'a> Lexer<'a> {
         })
     }
 
+    /// Reads a character literal like `'A'` or `'\n'` as the number of its ASCII code.
+    fn character(&mut self) -> LexResult<Token> {
+        let start = self.pos();
+        let source_id = self.current_context().source_id;
+        let error = |message: &str| CompileError { message: message.to_string(), position: start, source_id };
+
+        self.advance();
+
+        let value = match self.current() {
+            Some(b'\\') => {
+                self.advance();
+                Self::escape(self.current()).ok_or_else(|| error("unknown escape sequence in character literal"))?
+            }
+            Some(b'\'') => return Err(error("empty character literal")),
+            Some(c) if c.is_ascii() && !c.is_ascii_control() => c,
+            Some(c) if !c.is_ascii() => return Err(error("character literals must be ASCII")),
+            _ => return Err(error("unterminated character literal")),
+        };
+
+        self.advance();
+
+        if self.current() != Some(b'\'') {
+            return Err(error("unterminated character literal"));
+        }
+        self.advance();
+
+        Ok(Token {
+            token_type: TokenType::NUMBER,
+            value: TokenValue::Number(value as i64),
+            position: start,
+            source_id,
+        })
+    }
+
     fn literal(&mut self) -> LexResult<Token> {
         let start = self.pos();
 
@@ -247,8 +429,25 @@ impl<'a> Lexer<'a> {
 
         let (kind, value) = match identifier.as_str() {
             "let" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::LET)),
+            "const" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::CONST)),
+            "port" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::PORT)),
+            "import" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::IMPORT)),
+            "pub" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::PUB)),
+            "asm" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::ASM)),
+            "struct" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::STRUCT)),
+            "enum" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::ENUM)),
+            "match" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::MATCH)),
             "if" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::IF)),
+            "else" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::ELSE)),
             "while" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::WHILE)),
+            "true" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::TRUE)),
+            "false" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::FALSE)),
+            "for" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::FOR)),
+            "in" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::IN)),
+            "break" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::BREAK)),
+            "continue" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::CONTINUE)),
+            "fn" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::FN)),
+            "return" => (TokenType::KEYWORD, TokenValue::Keyword(Keyword::RETURN)),
             _ => (TokenType::IDENTIFIER, TokenValue::Identifier(identifier)),
         };
 
@@ -260,6 +459,47 @@ impl<'a> Lexer<'a> {
         })
     }
 
+    /// Reads a string literal like `"Score: \n"` as the ASCII codes of its characters.
+    fn string(&mut self) -> LexResult<Token> {
+        let start = self.pos();
+        let source_id = self.current_context().source_id;
+        let error = |message: &str| CompileError { message: message.to_string(), position: start, source_id };
+
+        let raw = self.string_literal()?;
+        let mut bytes = raw.bytes();
+        let mut value = Vec::new();
+
+        while let Some(c) = bytes.next() {
+            value.push(match c {
+                b'\\' => Self::escape(bytes.next()).ok_or_else(|| error("unknown escape sequence in string literal"))?,
+                c if !c.is_ascii() => return Err(error("string literals must be ASCII")),
+                c if c.is_ascii_control() => return Err(error("control characters in string literals must be escaped, like `\\n`")),
+                c => c,
+            });
+        }
+
+        Ok(Token {
+            token_type: TokenType::STRING,
+            value: TokenValue::Str(value),
+            position: start,
+            source_id,
+        })
+    }
+
+    /// The character an escape sequence like `\n` stands for, given the one after the backslash.
+    fn escape(c: Option<u8>) -> Option<u8> {
+        match c? {
+            b'n' => Some(b'\n'),
+            b'r' => Some(b'\r'),
+            b't' => Some(b'\t'),
+            b'0' => Some(0),
+            b'\\' => Some(b'\\'),
+            b'\'' => Some(b'\''),
+            b'"' => Some(b'"'),
+            _ => None,
+        }
+    }
+
     fn string_literal(&mut self) -> LexResult<String> {
         // 1. Sicherstellen, dass wir wirklich an einem " stehen
         if self.current() != Some(b'"') {
@@ -280,6 +520,11 @@ impl<'a> Lexer<'a> {
                 self.advance(); // Überspringe das schließende "
                 return Ok(value);
             }
+
+            // An escaped quote doesn't end the literal, the escape itself is kept.
+            if c == b'\\' {
+                self.advance();
+            }
             self.advance();
         }
 
@@ -309,13 +554,67 @@ impl<'a> Lexer<'a> {
     }
 
     // Utility
-    fn parse_i32_ascii(bytes: &[u8]) -> i32 {
-        let mut value: i32 = 0;
+    /// Parses validated digits, skipping `_` separators. `None` if the value overflows.
+    fn parse_i64_ascii(bytes: &[u8], radix: u32) -> Option<i64> {
+        let mut value: i64 = 0;
 
-        for &b in bytes {
-            value = value * 10 + (b - b'0') as i32;
+        for &b in bytes.iter().filter(|&&b| b != b'_') {
+            let digit = (b as char).to_digit(radix)? as i64;
+            value = value.checked_mul(radix as i64)?.checked_add(digit)?;
         }
 
-        value
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lexer;
+    use crate::{source::Source, source_map::SourceMap, testing::{run, run_files}, token::TokenType};
+
+    fn token_types(source: &str) -> Vec<TokenType> {
+        let mut source_map = SourceMap::new();
+        source_map.add(Source::new(source.to_string(), "main.tch".to_string()));
+        Lexer::new(&mut source_map, 0).read_all().unwrap().iter().map(|token| token.token_type).collect()
+    }
+
+    #[test]
+    fn negated_identifiers_starting_with_include_are_not_includes() {
+        assert_eq!(
+            token_types("if !included {}"),
+            vec![TokenType::KEYWORD, TokenType::BANG, TokenType::IDENTIFIER, TokenType::LBRACE, TokenType::RBRACE, TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn negated_identifiers_starting_with_embed_are_not_embeds() {
+        assert_eq!(
+            token_types("if !embedded {}"),
+            vec![TokenType::KEYWORD, TokenType::BANG, TokenType::IDENTIFIER, TokenType::LBRACE, TokenType::RBRACE, TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn directive_paths_are_relative_to_the_file_containing_them() {
+        let program = "!include \"lib/io.tch\"\n!embed \"level.bin\" as LEVEL;\nout = LEVEL[1];\nout = ONE;\n";
+        let io = "port out @ 0;\n!include \"one.tch\"\n";
+        let files = [("game/main.tch", program), ("game/level.bin", "AB"), ("game/lib/io.tch", io), ("game/lib/one.tch", "const ONE = 1;\n")];
+        assert_eq!(run_files(&files, "").unwrap().port_writes, vec![66, 1]);
+    }
+
+    #[test]
+    fn literals_may_use_every_bit_of_a_32_bit_word() {
+        let source = "port out @ 200;\nlet z: u32 = 0xFFFFFFFF;\nlet h: u32 = 0x80000000;\nlet m: i32 = -2147483648;\n\
+            if z == 4294967295 { out = 1; }\nif h == 2147483648 { out = 2; }\nif m < 0 && m == -2147483648 { out = 3; }\n";
+
+        for target in ["", "word_bits = 32\n"] {
+            assert_eq!(run(source, target).unwrap().port_writes, vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn literals_past_their_type_are_rejected_by_the_resolver() {
+        let err = run("let m: i32 = -2147483649;\n", "word_bits = 32\n").err().unwrap();
+        assert_eq!(err, "`-2147483649` does not fit in `i32`, which holds -2147483648 to 2147483647 at main.tch:1:14");
     }
 }
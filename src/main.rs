@@ -7,25 +7,39 @@ mod source;
 mod source_map;
 mod symbols;
 mod token;
+mod types;
+mod type_checker;
 mod instructions;
 mod ir_builder;
+mod layout;
+mod modules;
 mod legalizer;
 mod register_allocator;
+mod scheduler;
+mod simulator;
+mod target;
 mod emitter;
+mod asm;
+mod pbm;
+#[cfg(test)]
+mod testing;
 
 use lexer::Lexer;
 use parser::Parser;
 
-use std::env;
+use std::{env, fs};
 
 use crate::errors::ErrorReporter;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (flags, args): (Vec<String>, Vec<String>) = env::args().partition(|arg| arg.starts_with("--"));
     if args.len() < 2 {
         panic!("Please provide an input file name as argument.");
     }
 
+    let bounds_checks = flags.iter().any(|flag| flag == "--bounds-checks");
+    let map_file_name = flags.iter().find_map(|flag| flag.strip_prefix("--map="));
+
     let input_file_name = args.get(1).unwrap();
 
     println!("Running Torch compiler v0.1 ..");
@@ -38,9 +52,27 @@ fn main() {
 
     println!("Loaded source file `{}`.", input_file_name);
 
+    // Target
+
+    let target = match args.get(2) {
+        Some(target_file_name) => {
+            let target_id = source_map.add_from_file(target_file_name).unwrap();
+            match target::Target::from_source(&source_map, target_id) {
+                Err(err) => {
+                    ErrorReporter::print(&source_map, &err);
+                    return;
+                }
+                Ok(target) => target,
+            }
+        }
+        None => target::Target::default(),
+    };
+
+    println!("Compiling for target `{}`.", target.name);
+
     // Lexing
 
-    let mut lexer = Lexer::new(&mut source_map);
+    let mut lexer = Lexer::new(&mut source_map, 0);
     println!();
     println!("Tokenizing ..");
 
@@ -89,18 +121,42 @@ fn main() {
         println!("{:?}", stmt)
     }
 
+    // Modules
+
+    let root = modules::Module::new("main", 0, statements, parser.exports);
+
+    println!();
+    println!("Loading modules ..");
+
+    let modules = match modules::ModuleLoader::new(&mut source_map).load(root) {
+        Err(err) => {
+            ErrorReporter::print(&source_map, &err);
+            return;
+        }
+        Ok(modules) => modules,
+    };
+
+    println!("Done loading modules.");
+
+    println!();
+    println!("Modules:");
+    for module in &modules {
+        let file_name = &source_map.files[module.source_id].file_name;
+        println!(" {} from `{}`, exports {:?}", module.name, file_name, module.exports);
+    }
+
     // Resolving
 
     let mut symbol_table = symbols::SymbolTable::new();
 
-    let resolver = resolver::Resolver::new(&mut symbol_table);
+    let resolver = resolver::Resolver::new(&mut symbol_table, &target);
     println!();
     println!("Resolving ..");
 
-    resolver.resolve_program(&statements).unwrap_or_else(|err| {
+    if let Err(err) = resolver.resolve_program(&modules) {
         ErrorReporter::print(&source_map, &err);
         return;
-    });
+    }
 
     println!("Done resolving.");
 
@@ -108,7 +164,10 @@ fn main() {
     println!("Symbols:");
 
     for (i, scope) in symbol_table.scopes.iter().enumerate() {
-        println!(" Scope {}, parent {:?}:", i, scope.parent);
+        match symbol_table.modules.iter().find(|module| module.scope == i) {
+            Some(module) => println!(" Scope {}, module `{}`:", i, module.name),
+            None => println!(" Scope {}, parent {:?}:", i, scope.parent),
+        }
 
         for (_, symbol) in &scope.symbols {
             println!(
@@ -118,26 +177,73 @@ fn main() {
         }
     }
 
+    // Type Checking
+
+    println!();
+    println!("Checking types ..");
+
+    if let Err(err) = type_checker::TypeChecker::new(&symbol_table).check_program(&modules) {
+        ErrorReporter::print(&source_map, &err);
+        return;
+    }
+
+    println!("Done checking types.");
+
     // Intermediate Representation
 
-    let mut ir_builder = ir_builder::IrBuilder::new(&mut symbol_table);
+    let mut ir_builder = ir_builder::IrBuilder::new(&symbol_table, &target, bounds_checks);
     println!();
     println!("Generating Intermediate Representation ..");
 
-    let instrs = ir_builder.build(&statements);
+    let instrs = match ir_builder.build(&modules) {
+        Ok(instrs) => instrs.clone(),
+        Err(err) => {
+            ErrorReporter::print(&source_map, &err);
+            return;
+        }
+    };
 
     println!("Done generating IR.");
 
     println!();
     println!("Generated Instructions:");
 
-    for instr in instrs {
+    for instr in &instrs {
         println!("{:?}", instr);
     }
 
+    println!();
+    println!("Counted Loops:");
+    for counted in &ir_builder.counted_loops {
+        let bound = if counted.inclusive { "up to" } else { "below" };
+        println!(
+            " {:?} {} {:?}, body {:?} to {:?}",
+            counted.counter, bound, counted.limit, counted.head, counted.latch
+        );
+    }
+
+    println!();
+    println!("Optimisation Report:");
+    for dispatch in &ir_builder.matches {
+        let source = &source_map.files[dispatch.source_id];
+        let (line, col) = source.get_line_col(dispatch.position);
+        let location = format!("{}:{}:{}", source.file_name, line, col);
+
+        match &dispatch.rejection {
+            None => println!(
+                " match at {}: jump table of {} entries, {} cycles against {} for compares",
+                location, dispatch.span, dispatch.table_cycles.unwrap_or(0), dispatch.chain_cycles
+            ),
+            Some(rejection) => println!(
+                " match at {}: {} compares, {} cycles (jump table: {})",
+                location, dispatch.values, dispatch.chain_cycles, rejection
+            ),
+        }
+    }
+
     // Legalization
 
-    let mut legalizer = legalizer::Legalizer::new(instrs);
+    let mut legalizer = legalizer::Legalizer::new(&instrs, &ir_builder.types, &target);
 
     println!();
     println!("Legalizing Instructions ..");
@@ -152,15 +258,87 @@ fn main() {
 
     // Register Allocation
 
-    let mut allocator = register_allocator::Allocator::new();
+    let mut allocator = register_allocator::Allocator::new(legalizer.units(), ir_builder.ram_homes());
     println!();
     println!("Allocating Registers ..");
     let allocated_instrs = allocator.allocate(&legalized_instrs);
     println!("Done allocating registers.");
 
+    if let Err(message) = ir_builder.place_homes(&allocated_instrs) {
+        ErrorReporter::print_message(&message);
+        return;
+    }
+
     println!();
     println!("Register Allocated Instructions:");
-    for instr in allocated_instrs {
+    for instr in &allocated_instrs {
         println!("{:?}", instr);
     }
+
+    // Scheduling
+
+    let mut scheduler = scheduler::Scheduler::new(&target);
+    println!();
+    println!("Scheduling Instructions ..");
+    let scheduled_instrs = scheduler.schedule(&allocated_instrs);
+    println!("Done scheduling, inserted {} nops.", scheduler.nops);
+
+    println!();
+    println!("Scheduled Instructions:");
+    for instr in &scheduled_instrs {
+        println!("{:?}", instr);
+    }
+
+    // Emission
+
+    let emitter = emitter::Emitter::new(&ir_builder.layout, &symbol_table, &target);
+
+    println!();
+    println!("Assembly:");
+    print!("{}", emitter.assembly(&scheduled_instrs));
+
+    let map = emitter.map();
+
+    println!();
+    println!("Memory Layout:");
+    print!("{}", map);
+
+    if let Some(map_file_name) = map_file_name {
+        match fs::write(map_file_name, &map) {
+            Ok(()) => println!("Wrote map file `{}`.", map_file_name),
+            Err(err) => println!("Could not write map file `{}`: {}", map_file_name, err),
+        }
+    }
+
+    // Simulation
+
+    let mut simulator = simulator::Simulator::new(&target, &ir_builder.layout, 10_000);
+    println!();
+    println!("Simulating ..");
+    match simulator.run(&scheduled_instrs) {
+        Ok(()) => println!("Done simulating after {} cycles.", simulator.cycles),
+        Err(err) => println!("Simulation trapped after {} cycles: {}", simulator.cycles, err),
+    }
+
+    println!();
+    println!("Registers:");
+    let mut registers: Vec<_> = simulator.registers.iter().collect();
+    registers.sort_by_key(|(value, _)| format!("{:?}", value));
+    for (value, content) in registers {
+        println!(" {:?} = {}", value, content);
+    }
+
+    println!();
+    println!("Port Writes:");
+    for (address, value) in &simulator.port_writes {
+        println!(" [{}] <- {}", address, value);
+    }
+
+    println!();
+    println!("RAM:");
+    let mut ram: Vec<_> = simulator.ram.iter().collect();
+    ram.sort();
+    for (address, content) in ram {
+        println!(" [{}] = {}", address, content);
+    }
 }
@@ -0,0 +1,125 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+use crate::{ast::{Expr, Stmt, StmtNode}, errors::CompileError, lexer::Lexer, parser::Parser, source_map::SourceMap};
+
+/// A source file together with the statements parsed from it.
+pub struct Module {
+    pub name: String,
+    pub source_id: usize,
+    pub stmts: Vec<StmtNode>,
+    /// Names of the items marked `pub`.
+    pub exports: Vec<String>,
+    /// Modules this one imports by name, as indexes into the loaded module list.
+    pub imports: HashMap<String, usize>,
+}
+
+impl Module {
+    pub fn new(name: &str, source_id: usize, stmts: Vec<StmtNode>, exports: Vec<String>) -> Self {
+        Module {
+            name: name.to_string(),
+            source_id,
+            stmts,
+            exports,
+            imports: HashMap::new(),
+        }
+    }
+}
+
+/// Loads every module a program imports, transitively. `import name;` refers to `name.tch`
+/// next to the importing file, and each file is loaded only once however often it is imported.
+pub struct ModuleLoader<'a> {
+    source_map: &'a mut SourceMap,
+    modules: Vec<Module>,
+    /// Index of every loaded module, keyed by its canonical path.
+    loaded: HashMap<PathBuf, usize>,
+    /// Modules whose imports are being loaded right now, to detect import cycles.
+    loading: Vec<PathBuf>,
+}
+
+impl<'a> ModuleLoader<'a> {
+    pub fn new(source_map: &'a mut SourceMap) -> Self {
+        ModuleLoader {
+            source_map,
+            modules: Vec::new(),
+            loaded: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    /// Loads the imports of `root`. Every module in the result comes after the modules it
+    /// imports, `root` itself is last.
+    pub fn load(mut self, root: Module) -> Result<Vec<Module>, CompileError> {
+        let path = Path::new(&self.source_map.files[root.source_id].file_name);
+        if let Ok(path) = path.canonicalize() {
+            self.loading.push(path);
+        }
+
+        let root = self.load_imports(root)?;
+        self.modules.push(root);
+
+        Ok(self.modules)
+    }
+
+    fn load_imports(&mut self, mut module: Module) -> Result<Module, CompileError> {
+        let file_name = &self.source_map.files[module.source_id].file_name;
+        let directory = Path::new(file_name).parent().unwrap_or(Path::new("")).to_path_buf();
+
+        for stmt in &module.stmts {
+            let Stmt::Import { module: name } = &stmt.node else {
+                continue;
+            };
+            let Expr::Variable(import) = &name.node else {
+                unreachable!("the parser only imports names");
+            };
+
+            let error = |message: String| CompileError {
+                message,
+                position: name.position,
+                source_id: name.source_id,
+            };
+
+            let file = directory.join(format!("{}.tch", import));
+            let path = file
+                .canonicalize()
+                .map_err(|err| error(format!("cannot find module `{}` at `{}`: {}", import, file.display(), err)))?;
+
+            let index = match self.loaded.get(&path) {
+                Some(&index) => index,
+                None => {
+                    if self.loading.contains(&path) {
+                        return Err(error(format!("module `{}` is part of an import cycle", import)));
+                    }
+
+                    let source_id = self
+                        .source_map
+                        .add_from_file(&file.display().to_string())
+                        .map_err(|err| error(format!("cannot load module `{}`: {}", import, err)))?;
+
+                    self.loading.push(path.clone());
+                    let imported = self.parse(import, source_id)?;
+                    let imported = self.load_imports(imported)?;
+                    self.loading.pop();
+
+                    self.modules.push(imported);
+                    self.loaded.insert(path, self.modules.len() - 1);
+                    self.modules.len() - 1
+                }
+            };
+
+            if module.imports.insert(import.clone(), index).is_some() {
+                return Err(error(format!("module `{}` is already imported", import)));
+            }
+        }
+
+        Ok(module)
+    }
+
+    fn parse(&mut self, name: &str, source_id: usize) -> Result<Module, CompileError> {
+        let tokens = Lexer::new(self.source_map, source_id).read_all()?;
+
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse_program()?;
+
+        Ok(Module::new(name, source_id, stmts, parser.exports))
+    }
+}
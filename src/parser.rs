@@ -1,8 +1,12 @@
-use crate::{ast::{Expr, ExprNode, Stmt, StmtNode}, errors::CompileError, token::{Keyword, Token, TokenType, TokenValue}};
+use crate::{ast::{ArmNode, AsmInstr, AsmNode, AsmOperand, Expr, ExprNode, MatchArm, Stmt, StmtNode}, errors::CompileError, token::{Keyword, Token, TokenType, TokenValue}, types::Type};
 
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    /// Nesting depth of the blocks being parsed, 0 at the top level of the module.
+    depth: usize,
+    /// Names of the items marked `pub`, which other modules may access.
+    pub exports: Vec<String>,
 }
 
 const EOF_TOKEN: Token = Token {
@@ -17,13 +21,17 @@ pub type ParseResult<T> = Result<T, CompileError>;
 impl Parser {
     // Logical
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+        Parser { tokens, pos: 0, depth: 0, exports: Vec::new() }
     }
 
     fn current(&self) -> &Token {
         self.tokens.get(self.pos).unwrap_or(&EOF_TOKEN)
     }
 
+    fn peek(&self) -> &Token {
+        self.tokens.get(self.pos + 1).unwrap_or(&EOF_TOKEN)
+    }
+
     fn advance(&mut self) -> Token {
         let token = self.current().clone();
         self.pos += 1;
@@ -71,9 +79,27 @@ impl Parser {
     fn parse_statement(&mut self) -> ParseResult<StmtNode> {
         match self.current() {
             Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::IF), .. } => self.parse_if(),
-            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::WHILE), .. } => self.parse_while(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::WHILE), .. } => self.parse_while(None),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::FOR), .. } => self.parse_for(None),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::BREAK), .. } => self.parse_loop_exit(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::CONTINUE), .. } => self.parse_loop_exit(),
             Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::LET), .. } => self.parse_decleration(),
-            Token { token_type: TokenType::IDENTIFIER, .. } => self.parse_assignment(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::CONST), .. } => self.parse_constant(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::PORT), .. } => self.parse_port(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::FN), .. } => self.parse_function(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::RETURN), .. } => self.parse_return(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::IMPORT), .. } => self.parse_import(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::PUB), .. } => self.parse_pub(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::ASM), .. } => self.parse_asm(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::STRUCT), .. } => self.parse_struct(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::ENUM), .. } => self.parse_enum(),
+            Token { token_type: TokenType::KEYWORD, value: TokenValue::Keyword(Keyword::MATCH), .. } => self.parse_match(),
+            Token { token_type: TokenType::EMBED, .. } => self.parse_embed(),
+            Token { token_type: TokenType::IDENTIFIER, .. } if self.starts_call() => self.parse_expression_statement(),
+            Token { token_type: TokenType::IDENTIFIER, .. } if self.tokens.get(self.pos + 1).is_some_and(|token| token.token_type == TokenType::COLON) => {
+                self.parse_labeled_loop()
+            }
+            Token { token_type: TokenType::IDENTIFIER | TokenType::ASTERISK, .. } => self.parse_assignment(),
             Token { token_type, ..} => Err(CompileError {
                 message: format!("unexpected token {:?}", token_type),
                 position: self.current().position,
@@ -82,44 +108,576 @@ impl Parser {
         }
     }
 
+    /// Whether the statement at the current token is a call like `f(..)` or `module.f(..)`.
+    fn starts_call(&self) -> bool {
+        let token_type = |offset: usize| self.tokens.get(self.pos + offset).map(|token| token.token_type);
+
+        match token_type(1) {
+            Some(TokenType::LPAREN) => true,
+            Some(TokenType::DOT) => token_type(3) == Some(TokenType::LPAREN),
+            _ => false,
+        }
+    }
+
+    fn parse_import(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        if self.depth > 0 {
+            return Err(CompileError {
+                message: "`import` is only allowed at the top level of a module".to_string(),
+                position: keyword.position,
+                source_id: keyword.source_id,
+            });
+        }
+
+        let module = self.parse_identifier()?;
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Import { module },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    /// Parses the declaration following `pub` and exports the name it declares.
+    fn parse_pub(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let error = |message: &str| CompileError {
+            message: message.to_string(),
+            position: keyword.position,
+            source_id: keyword.source_id,
+        };
+
+        if self.depth > 0 {
+            return Err(error("`pub` is only allowed at the top level of a module"));
+        }
+
+        let stmt = self.parse_statement()?;
+
+        let name = match &stmt.node {
+            Stmt::Declare { target, .. }
+            | Stmt::DeclareArray { target, .. }
+            | Stmt::Const { target, .. }
+            | Stmt::Port { target, .. }
+            | Stmt::Struct { name: target, .. }
+            | Stmt::Enum { name: target, .. }
+            | Stmt::Function { name: target, .. } => match &target.node {
+                Expr::Variable(name) => name.clone(),
+                _ => unreachable!(),
+            },
+            _ => return Err(error("only declarations can be `pub`")),
+        };
+
+        self.exports.push(name);
+
+        Ok(stmt)
+    }
+
+    fn parse_asm(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let inputs = self.parse_asm_bindings("in")?;
+        let outputs = self.parse_asm_bindings("out")?;
+
+        let mut clobbers = Vec::new();
+        if self.parse_asm_section("clobber")? {
+            if !self.check(&TokenType::RPAREN) {
+                loop {
+                    clobbers.push(self.parse_identifier()?);
+
+                    if !self.matches(&[TokenType::COMMA]) {
+                        break;
+                    }
+                }
+            }
+
+            self.expect(&TokenType::RPAREN, "expected ')'")?;
+        }
+
+        self.expect(&TokenType::LBRACE, "expected '{'")?;
+
+        let mut body = Vec::new();
+        while !self.check(&TokenType::RBRACE) {
+            body.push(self.parse_asm_instr()?);
+        }
+
+        self.expect(&TokenType::RBRACE, "expected '}'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Asm { inputs, outputs, clobbers, body },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    /// Consumes `name(` if an `asm` section of that name follows.
+    fn parse_asm_section(&mut self, name: &str) -> ParseResult<bool> {
+        // `in` is a keyword, the other section names are plain identifiers.
+        let found = match &self.current().value {
+            TokenValue::Identifier(n) => n == name,
+            TokenValue::Keyword(Keyword::IN) => name == "in",
+            _ => false,
+        };
+
+        if !found {
+            return Ok(false);
+        }
+
+        self.advance();
+        self.expect(&TokenType::LPAREN, "expected '('")?;
+
+        Ok(true)
+    }
+
+    /// Parses `name(register: expr, ..)`, or nothing if the section is left out.
+    fn parse_asm_bindings(&mut self, name: &str) -> ParseResult<Vec<(ExprNode, ExprNode)>> {
+        let mut bindings = Vec::new();
+
+        if !self.parse_asm_section(name)? {
+            return Ok(bindings);
+        }
+
+        if !self.check(&TokenType::RPAREN) {
+            loop {
+                let register = self.parse_identifier()?;
+                self.expect(&TokenType::COLON, "expected ':'")?;
+                bindings.push((register, self.parse_expression()?));
+
+                if !self.matches(&[TokenType::COMMA]) {
+                    break;
+                }
+            }
+        }
+
+        self.expect(&TokenType::RPAREN, "expected ')'")?;
+
+        Ok(bindings)
+    }
+
+    /// Parses `mnemonic operand, ..;` where operands are registers or numbers.
+    fn parse_asm_instr(&mut self) -> ParseResult<AsmNode> {
+        let (position, source_id) = (self.current().position, self.current().source_id);
+
+        let mnemonic = match self.expect(&TokenType::IDENTIFIER, "expected instruction")?.value {
+            TokenValue::Identifier(name) => self.parse_path(name)?,
+            _ => unreachable!()
+        };
+
+        let mut operands = Vec::new();
+        if !self.check(&TokenType::SEMICOLON) {
+            loop {
+                let negative = self.matches(&[TokenType::MINUS]);
+
+                operands.push(match self.advance() {
+                    Token { value: TokenValue::Identifier(name), .. } if !negative => AsmOperand::Register(name),
+                    Token { value: TokenValue::Number(n), .. } => AsmOperand::Immediate(if negative { n.wrapping_neg() } else { n }),
+                    token => {
+                        return Err(CompileError {
+                            message: "expected a register or a number".to_string(),
+                            position: token.position,
+                            source_id: token.source_id,
+                        });
+                    }
+                });
+
+                if !self.matches(&[TokenType::COMMA]) {
+                    break;
+                }
+            }
+        }
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(AsmNode { node: AsmInstr { mnemonic, operands }, position, source_id })
+    }
+
     fn parse_if(&mut self) -> ParseResult<StmtNode> {
         let keyword = self.advance();
 
         let condition = self.parse_expression()?;
         let block = self.parse_block()?;
 
+        let else_block = if matches!(self.current().value, TokenValue::Keyword(Keyword::ELSE)) {
+            self.advance();
+
+            if matches!(self.current().value, TokenValue::Keyword(Keyword::IF)) {
+                Some(vec![self.parse_if()?])
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
         Ok(StmtNode {
-            node: Stmt::If { condition, body: block },
+            node: Stmt::If { condition, body: block, else_body: else_block },
             position: keyword.position,
             source_id: keyword.source_id,
         })
     }
 
-    fn parse_while(&mut self) -> ParseResult<StmtNode> {
+    /// Parses `name: while ..` and `name: for ..`.
+    fn parse_labeled_loop(&mut self) -> ParseResult<StmtNode> {
+        let label = self.parse_identifier()?;
+        self.expect(&TokenType::COLON, "expected ':'")?;
+
+        match self.current() {
+            Token { value: TokenValue::Keyword(Keyword::WHILE), .. } => self.parse_while(Some(label)),
+            Token { value: TokenValue::Keyword(Keyword::FOR), .. } => self.parse_for(Some(label)),
+            token => Err(CompileError {
+                message: "expected a loop after the label".to_string(),
+                position: token.position,
+                source_id: token.source_id,
+            }),
+        }
+    }
+
+    fn parse_while(&mut self, label: Option<ExprNode>) -> ParseResult<StmtNode> {
         let keyword = self.advance();
 
         let condition = self.parse_expression()?;
         let block = self.parse_block()?;
 
         Ok(StmtNode {
-            node: Stmt::While { condition, body: block },
+            node: Stmt::While { label, condition, body: block },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_for(&mut self, label: Option<ExprNode>) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let var = self.parse_identifier()?;
+
+        if !matches!(self.current().value, TokenValue::Keyword(Keyword::IN)) {
+            return Err(CompileError {
+                message: "expected `in`".to_string(),
+                position: self.current().position,
+                source_id: self.current().source_id,
+            });
+        }
+        self.advance();
+
+        let start = self.parse_expression()?;
+
+        let inclusive = match self.current().token_type {
+            TokenType::DOTDOT => false,
+            TokenType::DOTDOTEQ => true,
+            _ => {
+                return Err(CompileError {
+                    message: "expected `..` or `..=`".to_string(),
+                    position: self.current().position,
+                    source_id: self.current().source_id,
+                });
+            }
+        };
+        self.advance();
+
+        let end = self.parse_expression()?;
+        let body = self.parse_block()?;
+
+        Ok(StmtNode {
+            node: Stmt::For { label, var, start, end, inclusive, body },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_function(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let name = self.parse_identifier()?;
+
+        self.expect(&TokenType::LPAREN, "expected '('")?;
+
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RPAREN) {
+            loop {
+                params.push(self.parse_identifier()?);
+
+                if !self.matches(&[TokenType::COMMA]) {
+                    break;
+                }
+            }
+        }
+
+        self.expect(&TokenType::RPAREN, "expected ')'")?;
+
+        let body = self.parse_block()?;
+
+        Ok(StmtNode {
+            node: Stmt::Function { name, params, body },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    /// Parses `break;` and `continue;`, optionally naming the loop.
+    fn parse_loop_exit(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let label = match self.check(&TokenType::IDENTIFIER) {
+            true => Some(self.parse_identifier()?),
+            false => None,
+        };
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        let node = match keyword.value {
+            TokenValue::Keyword(Keyword::BREAK) => Stmt::Break { label },
+            _ => Stmt::Continue { label },
+        };
+
+        Ok(StmtNode {
+            node,
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_return(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let value = if self.check(&TokenType::SEMICOLON) {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Return { value },
             position: keyword.position,
             source_id: keyword.source_id,
         })
     }
 
+    fn parse_expression_statement(&mut self) -> ParseResult<StmtNode> {
+        let expr = self.parse_expression()?;
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(StmtNode {
+            position: expr.position,
+            source_id: expr.source_id,
+            node: Stmt::Expression { expr },
+        })
+    }
+
+    fn parse_identifier(&mut self) -> ParseResult<ExprNode> {
+        match self.expect(&TokenType::IDENTIFIER, "expected identifier")? {
+            Token { value: TokenValue::Identifier(n), position, source_id, .. } => Ok(ExprNode { node: Expr::Variable(n), position, source_id }),
+            _ => unreachable!()
+        }
+    }
+
     fn parse_block(&mut self) -> ParseResult<Vec<StmtNode>> {
         self.expect(&TokenType::LBRACE, "expected '{'")?;
+        self.depth += 1;
 
         let mut stmts = Vec::new();
         while !self.check(&TokenType::RBRACE) {
             stmts.push(self.parse_statement()?);
         }
 
+        self.depth -= 1;
         self.expect(&TokenType::RBRACE, "expected '}'")?;
         Ok(stmts)
     }
 
+    /// Appends every `.item` following `name`, for names reaching into modules, enums and structs.
+    fn parse_path(&mut self, mut name: String) -> ParseResult<String> {
+        while self.matches(&[TokenType::DOT]) {
+            match self.expect(&TokenType::IDENTIFIER, "expected identifier after '.'")?.value {
+                TokenValue::Identifier(item) => name = format!("{}.{}", name, item),
+                _ => unreachable!()
+            }
+        }
+
+        Ok(name)
+    }
+
+    fn parse_struct(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        if self.depth > 0 {
+            return Err(CompileError {
+                message: "structs can only be declared at the top level".to_string(),
+                position: keyword.position,
+                source_id: keyword.source_id,
+            });
+        }
+
+        let name = self.parse_identifier()?;
+
+        self.expect(&TokenType::LBRACE, "expected '{'")?;
+
+        let mut fields = Vec::new();
+        while !self.check(&TokenType::RBRACE) {
+            fields.push(self.parse_identifier()?);
+
+            if !self.matches(&[TokenType::COMMA]) {
+                break;
+            }
+        }
+
+        self.expect(&TokenType::RBRACE, "expected '}'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Struct { name, fields },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_enum(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        if self.depth > 0 {
+            return Err(CompileError {
+                message: "enums can only be declared at the top level".to_string(),
+                position: keyword.position,
+                source_id: keyword.source_id,
+            });
+        }
+
+        let name = self.parse_identifier()?;
+
+        self.expect(&TokenType::LBRACE, "expected '{'")?;
+
+        let mut variants = Vec::new();
+        while !self.check(&TokenType::RBRACE) {
+            let variant = self.parse_identifier()?;
+            let value = match self.matches(&[TokenType::ASSIGN]) {
+                true => Some(self.parse_expression()?),
+                false => None,
+            };
+            variants.push((variant, value));
+
+            if !self.matches(&[TokenType::COMMA]) {
+                break;
+            }
+        }
+
+        self.expect(&TokenType::RBRACE, "expected '}'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Enum { name, variants },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_match(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let value = self.parse_expression()?;
+
+        self.expect(&TokenType::LBRACE, "expected '{'")?;
+
+        let mut arms = Vec::new();
+        while !self.check(&TokenType::RBRACE) {
+            arms.push(self.parse_arm()?);
+        }
+
+        self.expect(&TokenType::RBRACE, "expected '}'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Match { value, arms },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    /// Parses `A, B => { .. }` or `_ => { .. }`, optionally followed by a comma.
+    fn parse_arm(&mut self) -> ParseResult<ArmNode> {
+        let (position, source_id) = (self.current().position, self.current().source_id);
+
+        let patterns = match &self.current().value {
+            TokenValue::Identifier(name) if name == "_" => {
+                self.advance();
+                None
+            }
+            _ => {
+                let mut patterns = vec![self.parse_expression()?];
+                while self.matches(&[TokenType::COMMA]) {
+                    patterns.push(self.parse_expression()?);
+                }
+                Some(patterns)
+            }
+        };
+
+        self.expect(&TokenType::FATARROW, "expected '=>'")?;
+
+        let body = self.parse_block()?;
+        self.matches(&[TokenType::COMMA]);
+
+        Ok(ArmNode { node: MatchArm { patterns, body }, position, source_id })
+    }
+
+    /// Parses the value of a declaration or assignment, which may also be a struct literal.
+    fn parse_value(&mut self) -> ParseResult<ExprNode> {
+        let token_type = |offset: usize| self.tokens.get(self.pos + offset).map(|token| token.token_type);
+
+        let literal = token_type(0) == Some(TokenType::IDENTIFIER)
+            && match token_type(1) {
+                Some(TokenType::LBRACE) => true,
+                Some(TokenType::DOT) => token_type(3) == Some(TokenType::LBRACE),
+                _ => false,
+            };
+
+        if !literal {
+            return self.parse_expression();
+        }
+
+        let (position, source_id) = (self.current().position, self.current().source_id);
+        let name = match self.advance().value {
+            TokenValue::Identifier(name) => self.parse_path(name)?,
+            _ => unreachable!()
+        };
+
+        self.expect(&TokenType::LBRACE, "expected '{'")?;
+
+        let mut fields = Vec::new();
+        while !self.check(&TokenType::RBRACE) {
+            let field = self.parse_identifier()?;
+            self.expect(&TokenType::COLON, "expected ':'")?;
+            fields.push((field, self.parse_expression()?));
+
+            if !self.matches(&[TokenType::COMMA]) {
+                break;
+            }
+        }
+
+        self.expect(&TokenType::RBRACE, "expected '}'")?;
+
+        Ok(ExprNode { node: Expr::StructLiteral { name, fields }, position, source_id })
+    }
+
+    /// Wraps an array element in a field access if a `.field` follows it.
+    fn parse_field(&mut self, base: ExprNode) -> ParseResult<ExprNode> {
+        if !self.check(&TokenType::DOT) {
+            return Ok(base);
+        }
+
+        let dot = self.advance();
+        let field = match self.expect(&TokenType::IDENTIFIER, "expected field name after '.'")?.value {
+            TokenValue::Identifier(field) => field,
+            _ => unreachable!()
+        };
+
+        Ok(ExprNode {
+            node: Expr::Field { base: Box::new(base), field },
+            position: dot.position,
+            source_id: dot.source_id,
+        })
+    }
+
     fn parse_decleration(&mut self) -> ParseResult<StmtNode> {
         let keyword = self.advance();
 
@@ -128,6 +686,38 @@ impl Parser {
             _ => unreachable!()
         };
 
+        if self.starts_array() {
+            return self.parse_array_decleration(keyword, target);
+        }
+
+        let ty = if self.matches(&[TokenType::COLON]) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        self.expect(&TokenType::ASSIGN, "expected '='")?;
+
+        let value = self.parse_value()?;
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Declare { target, ty, value },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_constant(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let target = self.parse_identifier()?;
+
+        if self.starts_array() {
+            return self.parse_array_decleration(keyword, target);
+        }
+
         self.expect(&TokenType::ASSIGN, "expected '='")?;
 
         let value = self.parse_expression()?;
@@ -135,37 +725,285 @@ impl Parser {
         self.expect(&TokenType::SEMICOLON, "expected ';'")?;
 
         Ok(StmtNode {
-            node: Stmt::Declare { target, value },
+            node: Stmt::Const { target, value },
             position: keyword.position,
             source_id: keyword.source_id,
         })
     }
 
-    fn parse_assignment(&mut self) -> ParseResult<StmtNode> {
-        let target = match self.expect(&TokenType::IDENTIFIER, "expected identifier")?.value {
-            TokenValue::Identifier(n) => ExprNode { node: Expr::Variable(n), position: self.current().position, source_id: self.current().source_id },
+    fn parse_port(&mut self) -> ParseResult<StmtNode> {
+        let keyword = self.advance();
+
+        let target = self.parse_identifier()?;
+
+        let ty = if self.matches(&[TokenType::COLON]) {
+            let (position, source_id) = (self.current().position, self.current().source_id);
+            match self.parse_type()? {
+                Type::Int(ty) => ty,
+                ty => {
+                    return Err(CompileError {
+                        message: format!("ports hold integers, not `{}`", ty.name()),
+                        position,
+                        source_id,
+                    });
+                }
+            }
+        } else {
+            None
+        };
+
+        self.expect(&TokenType::AT, "expected '@'")?;
+
+        let address = self.parse_expression()?;
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(StmtNode {
+            node: Stmt::Port { target, ty, address },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_type(&mut self) -> ParseResult<Type> {
+        match self.expect(&TokenType::IDENTIFIER, "expected type")? {
+            Token { value: TokenValue::Identifier(name), position, source_id, .. } => {
+                Type::from_name(&name).ok_or_else(|| CompileError {
+                    message: format!("unknown type `{}`", name),
+                    position,
+                    source_id,
+                })
+            }
             _ => unreachable!()
+        }
+    }
+
+    /// Parses `!embed "file" as NAME;`, a constant table of the data the lexer read from the file.
+    fn parse_embed(&mut self) -> ParseResult<StmtNode> {
+        let directive = self.advance();
+        let TokenValue::Str(data) = directive.value else {
+            unreachable!("the lexer gives embeds their data")
         };
 
-        self.expect(&TokenType::ASSIGN, "expected '='")?;
+        match self.advance() {
+            Token { value: TokenValue::Identifier(word), .. } if word == "as" => {}
+            token => {
+                return Err(CompileError {
+                    message: "expected `as` and a name for the embedded data".to_string(),
+                    position: token.position,
+                    source_id: token.source_id,
+                });
+            }
+        }
 
-        let value = self.parse_expression()?;
+        let target = self.parse_identifier()?;
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        let (position, source_id) = (directive.position, directive.source_id);
+        let values = data.into_iter().map(|byte| ExprNode { node: Expr::Number(byte as i64), position, source_id }).collect();
+
+        Ok(StmtNode {
+            node: Stmt::DeclareArray { target, length: None, element: None, values: Some(values), constant: true },
+            position,
+            source_id,
+        })
+    }
+
+    /// Parses `[a, b, ..]`, or a string literal as its characters followed by a 0.
+    fn parse_array_values(&mut self) -> ParseResult<Vec<ExprNode>> {
+        if let Token { value: TokenValue::Str(text), position, source_id, .. } = self.current().clone() {
+            self.advance();
+
+            let characters = text.into_iter().chain([0]);
+            return Ok(characters.map(|c| ExprNode { node: Expr::Number(c as i64), position, source_id }).collect());
+        }
+
+        self.expect(&TokenType::LBRACKET, "expected '['")?;
+
+        let mut values = Vec::new();
+        if !self.check(&TokenType::RBRACKET) {
+            loop {
+                values.push(self.parse_value()?);
+
+                if !self.matches(&[TokenType::COMMA]) {
+                    break;
+                }
+            }
+        }
+
+        self.expect(&TokenType::RBRACKET, "expected ']'")?;
+
+        Ok(values)
+    }
+
+    /// Whether the declaration continues like one of an array, with a length or array values.
+    fn starts_array(&self) -> bool {
+        self.check(&TokenType::LBRACKET)
+            || (self.check(&TokenType::ASSIGN) && matches!(self.peek().token_type, TokenType::LBRACKET | TokenType::STRING))
+    }
+
+    fn parse_array_decleration(&mut self, keyword: Token, target: ExprNode) -> ParseResult<StmtNode> {
+        let length = if self.matches(&[TokenType::LBRACKET]) {
+            let length = self.parse_expression()?;
+            self.expect(&TokenType::RBRACKET, "expected ']'")?;
+            Some(length)
+        } else {
+            None
+        };
+
+        let element = match self.matches(&[TokenType::COLON]) {
+            true => {
+                let ExprNode { node: Expr::Variable(name), position, source_id } = self.parse_identifier()? else {
+                    unreachable!()
+                };
+                Some(ExprNode { node: Expr::Variable(self.parse_path(name)?), position, source_id })
+            }
+            false => None,
+        };
+
+        let values = if self.matches(&[TokenType::ASSIGN]) {
+            Some(self.parse_array_values()?)
+        } else {
+            None
+        };
+
+        let constant = matches!(keyword.value, TokenValue::Keyword(Keyword::CONST));
+
+        self.expect(&TokenType::SEMICOLON, "expected ';'")?;
+
+        Ok(StmtNode {
+            node: Stmt::DeclareArray { target, length, element, values, constant },
+            position: keyword.position,
+            source_id: keyword.source_id,
+        })
+    }
+
+    fn parse_assignment(&mut self) -> ParseResult<StmtNode> {
+        // `*pointer = value;` writes through a pointer.
+        let target = if self.check(&TokenType::ASTERISK) {
+            self.parse_unary()?
+        } else {
+            match self.expect(&TokenType::IDENTIFIER, "expected identifier")?.value {
+                TokenValue::Identifier(n) => {
+                    let (position, source_id) = (self.current().position, self.current().source_id);
+                    ExprNode { node: Expr::Variable(self.parse_path(n)?), position, source_id }
+                }
+                _ => unreachable!()
+            }
+        };
+
+        let target = if self.check(&TokenType::LBRACKET) {
+            let bracket = self.advance();
+            let index = self.parse_expression()?;
+            self.expect(&TokenType::RBRACKET, "expected ']'")?;
+
+            let index = ExprNode {
+                node: Expr::Index { array: Box::new(target), index: Box::new(index) },
+                position: bracket.position,
+                source_id: bracket.source_id,
+            };
+
+            self.parse_field(index)?
+        } else {
+            target
+        };
+
+        let token = self.advance();
+        let operator = match token.token_type {
+            TokenType::ASSIGN => None,
+            TokenType::PLUSASSIGN | TokenType::INCREMENT => Some(TokenType::PLUS),
+            TokenType::MINUSASSIGN | TokenType::DECREMENT => Some(TokenType::MINUS),
+            TokenType::ASTERISKASSIGN => Some(TokenType::ASTERISK),
+            TokenType::SLASHASSIGN => Some(TokenType::SLASH),
+            TokenType::PERCENTASSIGN => Some(TokenType::PERCENT),
+            TokenType::AMPERSANDASSIGN => Some(TokenType::AMPERSAND),
+            TokenType::PIPEASSIGN => Some(TokenType::PIPE),
+            TokenType::CARETASSIGN => Some(TokenType::CARET),
+            TokenType::SHLASSIGN => Some(TokenType::SHL),
+            TokenType::SHRASSIGN => Some(TokenType::SHR),
+            _ => {
+                return Err(CompileError {
+                    message: "expected '='".to_string(),
+                    position: token.position,
+                    source_id: token.source_id,
+                });
+            }
+        };
+
+        let value = match token.token_type {
+            TokenType::INCREMENT | TokenType::DECREMENT => ExprNode {
+                node: Expr::Number(1),
+                position: token.position,
+                source_id: token.source_id,
+            },
+            TokenType::ASSIGN => self.parse_value()?,
+            _ => self.parse_expression()?,
+        };
 
         self.expect(&TokenType::SEMICOLON, "expected ';'")?;
 
+        let (target_position, target_source_id) = (target.position, target.source_id);
+        let node = match operator {
+            Some(operator) => Stmt::CompoundAssign { target, operator, value },
+            None => Stmt::Assign { target, value },
+        };
+
         Ok(StmtNode {
-            position: target.position,
-            source_id: target.source_id,
-            node: Stmt::Assign { target, value },
+            position: target_position,
+            source_id: target_source_id,
+            node,
         })
     }
 
     fn parse_expression(&mut self) -> ParseResult<ExprNode> {
-        self.parse_comparison()
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> ParseResult<ExprNode> {
+        let mut expr = self.parse_and()?;
+
+        while self.check(&TokenType::OR) {
+            let op = self.advance();
+            let right = self.parse_and()?;
+
+            expr = ExprNode {
+                position: expr.position,
+                source_id: expr.source_id,
+                node: Expr::Binary {
+                    left: Box::new(expr),
+                    operator: op.token_type,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> ParseResult<ExprNode> {
+        let mut expr = self.parse_comparison()?;
+
+        while self.check(&TokenType::AND) {
+            let op = self.advance();
+            let right = self.parse_comparison()?;
+
+            expr = ExprNode {
+                position: expr.position,
+                source_id: expr.source_id,
+                node: Expr::Binary {
+                    left: Box::new(expr),
+                    operator: op.token_type,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
     }
 
     fn parse_comparison(&mut self) -> ParseResult<ExprNode> {
-        let mut expr = self.parse_term()?;
+        let mut expr = self.parse_bit_or()?;
 
         while self.matches(&[
             TokenType::GT,
@@ -176,6 +1014,90 @@ impl Parser {
             TokenType::NEQ,
         ]) {
             let op = self.tokens[self.pos - 1].clone();
+            let right = self.parse_bit_or()?;
+
+            expr = ExprNode {
+                position: expr.position,
+                source_id: expr.source_id,
+                node: Expr::Binary {
+                    left: Box::new(expr),
+                    operator: op.token_type,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_bit_or(&mut self) -> ParseResult<ExprNode> {
+        let mut expr = self.parse_bit_xor()?;
+
+        while self.check(&TokenType::PIPE) {
+            let op = self.advance();
+            let right = self.parse_bit_xor()?;
+
+            expr = ExprNode {
+                position: expr.position,
+                source_id: expr.source_id,
+                node: Expr::Binary {
+                    left: Box::new(expr),
+                    operator: op.token_type,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_bit_xor(&mut self) -> ParseResult<ExprNode> {
+        let mut expr = self.parse_bit_and()?;
+
+        while self.check(&TokenType::CARET) {
+            let op = self.advance();
+            let right = self.parse_bit_and()?;
+
+            expr = ExprNode {
+                position: expr.position,
+                source_id: expr.source_id,
+                node: Expr::Binary {
+                    left: Box::new(expr),
+                    operator: op.token_type,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_bit_and(&mut self) -> ParseResult<ExprNode> {
+        let mut expr = self.parse_shift()?;
+
+        while self.check(&TokenType::AMPERSAND) {
+            let op = self.advance();
+            let right = self.parse_shift()?;
+
+            expr = ExprNode {
+                position: expr.position,
+                source_id: expr.source_id,
+                node: Expr::Binary {
+                    left: Box::new(expr),
+                    operator: op.token_type,
+                    right: Box::new(right),
+                },
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_shift(&mut self) -> ParseResult<ExprNode> {
+        let mut expr = self.parse_term()?;
+
+        while matches!(self.current().token_type, TokenType::SHL | TokenType::SHR) {
+            let op = self.advance();
             let right = self.parse_term()?;
 
             expr = ExprNode {
@@ -216,7 +1138,7 @@ impl Parser {
     fn parse_factor(&mut self) -> ParseResult<ExprNode> {
         let mut expr = self.parse_unary()?;
 
-        while self.check(&TokenType::ASTERISK) || self.check(&TokenType::SLASH) {
+        while self.check(&TokenType::ASTERISK) || self.check(&TokenType::SLASH) || self.check(&TokenType::PERCENT) {
             let op = self.advance();
             let right = self.parse_unary()?;
 
@@ -235,7 +1157,7 @@ impl Parser {
     }
 
     fn parse_unary(&mut self) -> ParseResult<ExprNode> {
-        if self.check(&TokenType::MINUS) {
+        if self.check(&TokenType::MINUS) || self.check(&TokenType::TILDE) || self.check(&TokenType::BANG) {
             let op = self.advance();
             let right = self.parse_unary()?;
 
@@ -249,12 +1171,64 @@ impl Parser {
             });
         }
 
+        if self.check(&TokenType::AMPERSAND) || self.check(&TokenType::ASTERISK) {
+            let op = self.advance();
+            let operand = Box::new(self.parse_unary()?);
+
+            let node = match op.token_type {
+                TokenType::AMPERSAND => Expr::AddressOf { target: operand },
+                _ => Expr::Deref { pointer: operand },
+            };
+
+            return Ok(ExprNode { node, position: op.position, source_id: op.source_id });
+        }
+
         self.parse_primary()
     }
 
     fn parse_primary(&mut self) -> ParseResult<ExprNode> {
-        match self.advance() {
+        let token = match self.advance() {
+            Token { token_type: TokenType::IDENTIFIER, value: TokenValue::Identifier(name), position, source_id } => {
+                let name = self.parse_path(name)?;
+                Token { token_type: TokenType::IDENTIFIER, value: TokenValue::Identifier(name), position, source_id }
+            }
+            token => token,
+        };
+
+        match token {
             Token { token_type: TokenType::NUMBER, value: TokenValue::Number(n), position, source_id } => Ok(ExprNode { node: Expr::Number(n), position, source_id }),
+            Token { value: TokenValue::Keyword(Keyword::TRUE), position, source_id, .. } => Ok(ExprNode { node: Expr::Bool(true), position, source_id }),
+            Token { value: TokenValue::Keyword(Keyword::FALSE), position, source_id, .. } => Ok(ExprNode { node: Expr::Bool(false), position, source_id }),
+            Token { token_type: TokenType::IDENTIFIER, value: TokenValue::Identifier(name), position, source_id } if self.check(&TokenType::LPAREN) => {
+                self.advance();
+
+                let mut args = Vec::new();
+                if !self.check(&TokenType::RPAREN) {
+                    loop {
+                        args.push(self.parse_expression()?);
+
+                        if !self.matches(&[TokenType::COMMA]) {
+                            break;
+                        }
+                    }
+                }
+
+                self.expect(&TokenType::RPAREN, "expected ')'")?;
+
+                Ok(ExprNode { node: Expr::Call { callee: name, args }, position, source_id })
+            }
+            Token { token_type: TokenType::IDENTIFIER, value: TokenValue::Identifier(name), position, source_id } if self.check(&TokenType::LBRACKET) => {
+                let bracket = self.advance();
+                let index = self.parse_expression()?;
+                self.expect(&TokenType::RBRACKET, "expected ']'")?;
+
+                let array = ExprNode { node: Expr::Variable(name), position, source_id };
+                self.parse_field(ExprNode {
+                    node: Expr::Index { array: Box::new(array), index: Box::new(index) },
+                    position: bracket.position,
+                    source_id: bracket.source_id,
+                })
+            }
             Token { token_type: TokenType::IDENTIFIER, value: TokenValue::Identifier(name), position, source_id } => Ok(ExprNode { node: Expr::Variable(name), position, source_id }),
 
             Token { token_type: TokenType::LPAREN, .. } => {
@@ -263,6 +1237,12 @@ impl Parser {
                 Ok(expr)
             }
 
+            Token { token_type: TokenType::STRING, position, source_id, .. } => Err(CompileError {
+                message: "string literals only initialise arrays, like `let text = \"..\";`".to_string(),
+                position,
+                source_id,
+            }),
+
             token => Err(CompileError {
                 message: format!("expected expression, found {:?}", token.token_type),
                 position: token.position,
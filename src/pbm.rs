@@ -0,0 +1,101 @@
+use crate::errors::CompileError;
+
+/// Decodes a PBM image, plain (`P1`) or raw (`P4`), into its pixel rows. Each row takes
+/// one byte per 8 pixels, the leftmost pixel in the highest bit and 1 meaning black, with
+/// the last byte padded with zeros like in a raw PBM.
+pub fn rows(bytes: &[u8], source_id: usize) -> Result<Vec<u8>, CompileError> {
+    let mut reader = Reader { bytes, pos: 0, source_id };
+
+    let raw = match bytes.get(..2) {
+        Some(b"P1") => false,
+        Some(b"P4") => true,
+        _ => return Err(reader.error("expected a PBM image starting with `P1` or `P4`")),
+    };
+    reader.pos = 2;
+
+    let width = reader.number()?;
+    let height = reader.number()?;
+    let row_bytes = width.div_ceil(8);
+
+    if raw {
+        // A single whitespace character separates the header from the raster.
+        reader.pos += 1;
+
+        let raster = bytes.get(reader.pos..reader.pos + row_bytes * height);
+        return raster.map(<[u8]>::to_vec).ok_or_else(|| reader.error(&format!("image data ends before all {} rows", height)));
+    }
+
+    let mut rows = vec![0; row_bytes * height];
+    for y in 0..height {
+        for x in 0..width {
+            if reader.pixel()? {
+                rows[y * row_bytes + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    source_id: usize,
+}
+
+impl Reader<'_> {
+    fn error(&self, message: &str) -> CompileError {
+        CompileError {
+            message: message.to_string(),
+            position: self.pos.min(self.bytes.len().saturating_sub(1)),
+            source_id: self.source_id,
+        }
+    }
+
+    /// Skips whitespace and `#` comments, which may appear anywhere in the header.
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.bytes.get(self.pos) {
+            match c {
+                b'#' => {
+                    while self.bytes.get(self.pos).is_some_and(|&c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                c if c.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, CompileError> {
+        self.skip_whitespace();
+
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+
+        match std::str::from_utf8(&self.bytes[start..self.pos]).unwrap().parse() {
+            Ok(n @ 1..) => Ok(n),
+            _ => {
+                self.pos = start;
+                Err(self.error("expected the image size as positive numbers"))
+            }
+        }
+    }
+
+    /// Reads the next pixel of a plain PBM, where pixels need no whitespace between them.
+    fn pixel(&mut self) -> Result<bool, CompileError> {
+        self.skip_whitespace();
+
+        let pixel = match self.bytes.get(self.pos) {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            Some(_) => return Err(self.error("expected a pixel, `0` or `1`")),
+            None => return Err(self.error("image data ends before the last pixel")),
+        };
+
+        self.pos += 1;
+        Ok(pixel)
+    }
+}
@@ -1,26 +1,50 @@
+use std::collections::HashMap;
+
 use crate::instructions::{Instr, Value};
 
-const NUM_REGISTERS: usize = 7;
+pub const NUM_REGISTERS: usize = 7;
 
 pub struct Allocator {
     instrs: Vec<Instr>,
     regs: [Option<Value>; NUM_REGISTERS],
     ram: Vec<Value>,
     dirty: [bool; NUM_REGISTERS],
+    /// Index of the last instruction reading each temporary.
+    last_use: HashMap<Value, usize>,
+    /// Pieces of multi-word values, which are spilled together.
+    units: Vec<Vec<Value>>,
+    /// RAM cells of the variables whose address is taken, which are never kept in a
+    /// register past the instruction using them.
+    homes: HashMap<Value, Value>,
+    position: usize,
 }
 
 impl Allocator {
-    pub fn new() -> Self {
+    pub fn new(units: Vec<Vec<Value>>, homes: HashMap<Value, Value>) -> Self {
         Self {
+            units,
+            homes,
             instrs: Vec::new(),
             regs: [None; NUM_REGISTERS],
             ram: Vec::new(),
             dirty: [false; NUM_REGISTERS],
+            last_use: HashMap::new(),
+            position: 0,
         }
     }
 
     pub fn allocate(&mut self, instrs: &Vec<Instr>) -> Vec<Instr> {
-        for instr in instrs.iter() {
+        for (i, instr) in instrs.iter().enumerate() {
+            for value in instr.uses() {
+                if let Value::Temp(_) = value {
+                    self.last_use.insert(value, i);
+                }
+            }
+        }
+
+        for (i, instr) in instrs.iter().enumerate() {
+            self.position = i;
+
             match instr {
                 Instr::Move { dst, src } => {
                     let src_reg = self.get_or_load(src, &[]);
@@ -39,23 +63,39 @@ impl Allocator {
                     });
                     self.dirty[Self::get_id_of(&dst_reg) as usize] = true;
                 }
-                Instr::Add { dst, lhs, rhs } => {
-                    let lhs_reg = self.get_or_load(lhs, &[]);
-                    let rhs_reg = self.get_or_load(rhs, &[Self::get_id_of(&lhs_reg) as u8]);
-                    let dst_reg = self.allocate_register(
-                        dst,
-                        &[
-                            Self::get_id_of(&lhs_reg) as u8,
-                            Self::get_id_of(&rhs_reg) as u8,
-                        ],
-                    );
-                    self.instrs.push(Instr::Add {
-                        dst: dst_reg,
-                        lhs: lhs_reg,
-                        rhs: rhs_reg,
+                // Array elements are never cached in registers, every access goes to memory.
+                // Fixed addresses stay operands, computed ones are taken from a register.
+                Instr::Load { dst, src } | Instr::LoadRom { dst, src } => {
+                    let src_reg = match src {
+                        Value::Ptr(_) | Value::Const(_) => *src,
+                        _ => self.get_or_load(src, &[]),
+                    };
+                    let locked: Vec<u8> = match src_reg {
+                        Value::Reg(id) => vec![id],
+                        _ => Vec::new(),
+                    };
+                    let dst_reg = self.allocate_register(dst, &locked);
+                    self.instrs.push(match instr {
+                        Instr::Load { .. } => Instr::Load { dst: dst_reg, src: src_reg },
+                        _ => Instr::LoadRom { dst: dst_reg, src: src_reg },
                     });
                     self.dirty[Self::get_id_of(&dst_reg) as usize] = true;
                 }
+                Instr::Store { dst, src } => {
+                    let src_reg = self.get_or_load(src, &[]);
+                    let dst_reg = match dst {
+                        Value::Ptr(_) | Value::Const(_) => *dst,
+                        _ => self.get_or_load(dst, &[Self::get_id_of(&src_reg) as u8]),
+                    };
+                    self.instrs.push(Instr::Store {
+                        dst: dst_reg,
+                        src: src_reg,
+                    });
+                }
+                Instr::Add { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Add { dst, lhs, rhs });
+                }
                 Instr::AddImmediate { dst, lhs, imm } => {
                     let lhs_reg = self.get_or_load(lhs, &[]);
                     let dst_reg = self.allocate_register(dst, &[Self::get_id_of(&lhs_reg) as u8]);
@@ -67,67 +107,279 @@ impl Allocator {
                     self.dirty[Self::get_id_of(&dst_reg) as usize] = true;
                 }
                 Instr::Sub { dst, lhs, rhs } => {
-                    let lhs_reg = self.get_or_load(lhs, &[]);
-                    let rhs_reg = self.get_or_load(rhs, &[Self::get_id_of(&lhs_reg) as u8]);
-                    let dst_reg = self.allocate_register(
-                        dst,
-                        &[
-                            Self::get_id_of(&lhs_reg) as u8,
-                            Self::get_id_of(&rhs_reg) as u8,
-                        ],
-                    );
-                    self.instrs.push(Instr::Sub {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Sub { dst, lhs, rhs });
+                }
+                Instr::AddCarry { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::AddCarry { dst, lhs, rhs });
+                }
+                Instr::SubBorrow { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::SubBorrow { dst, lhs, rhs });
+                }
+                Instr::Mul { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Mul { dst, lhs, rhs });
+                }
+                Instr::Div { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Div { dst, lhs, rhs });
+                }
+                Instr::Mod { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Mod { dst, lhs, rhs });
+                }
+                Instr::DivU { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::DivU { dst, lhs, rhs });
+                }
+                Instr::ModU { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::ModU { dst, lhs, rhs });
+                }
+                Instr::And { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::And { dst, lhs, rhs });
+                }
+                Instr::Or { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Or { dst, lhs, rhs });
+                }
+                Instr::Xor { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Xor { dst, lhs, rhs });
+                }
+                Instr::Shl { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Shl { dst, lhs, rhs });
+                }
+                Instr::Shr { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Shr { dst, lhs, rhs });
+                }
+                Instr::ShrU { dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::ShrU { dst, lhs, rhs });
+                }
+                Instr::Not { dst, src } => {
+                    let src_reg = self.get_or_load(src, &[]);
+                    let dst_reg = self.allocate_register(dst, &[Self::get_id_of(&src_reg) as u8]);
+                    self.instrs.push(Instr::Not {
                         dst: dst_reg,
-                        lhs: lhs_reg,
-                        rhs: rhs_reg,
+                        src: src_reg,
                     });
                     self.dirty[Self::get_id_of(&dst_reg) as usize] = true;
                 }
+                Instr::Cmp { cond, dst, lhs, rhs } => {
+                    let (dst, lhs, rhs) = self.allocate_binary(dst, lhs, rhs);
+                    self.instrs.push(Instr::Cmp { cond: *cond, dst, lhs, rhs });
+                }
+                // The registers an asm block names are taken over for it. Inputs are copied into
+                // theirs beforehand, and afterwards the outputs live in theirs.
+                Instr::Asm { body, inputs, outputs, clobbers } => {
+                    let bound: Vec<u8> = inputs.iter().chain(outputs).map(|&(reg, _)| reg).chain(clobbers.iter().copied()).collect();
+
+                    for &reg in &bound {
+                        self.evict(reg as usize, &bound);
+                    }
+
+                    for &(reg, value) in inputs {
+                        let dst = Value::Reg(reg);
+                        match self.regs.iter().position(|held| *held == Some(value)) {
+                            Some(src) => self.instrs.push(Instr::Move { dst, src: Value::Reg(src as u8) }),
+                            None if matches!(value, Value::Const(_) | Value::Ptr(_)) => self.instrs.push(Instr::Immediate { dst, value }),
+                            None => self.instrs.push(Instr::Load { dst, src: self.home(&value) }),
+                        }
+                    }
+
+                    for &(reg, var) in outputs {
+                        for other in 0..NUM_REGISTERS {
+                            if self.regs[other] == Some(var) {
+                                self.regs[other] = None;
+                                self.dirty[other] = false;
+                            }
+                        }
+
+                        self.regs[reg as usize] = Some(var);
+                        self.dirty[reg as usize] = true;
+                    }
+
+                    self.instrs.push(Instr::Asm {
+                        body: body.clone(),
+                        inputs: inputs.iter().map(|&(reg, _)| (reg, Value::Reg(reg))).collect(),
+                        outputs: outputs.iter().map(|&(reg, _)| (reg, Value::Reg(reg))).collect(),
+                        clobbers: clobbers.clone(),
+                    });
+                }
+                Instr::JumpIfFalse { cond, target } => {
+                    let cond_reg = self.get_or_load(cond, &[]);
+                    self.flush();
+                    self.instrs.push(Instr::JumpIfFalse {
+                        cond: cond_reg,
+                        target: *target,
+                    });
+                }
+                Instr::JumpTable { index, targets } => {
+                    let index_reg = self.get_or_load(index, &[]);
+                    self.flush();
+                    self.instrs.push(Instr::JumpTable {
+                        index: index_reg,
+                        targets: targets.clone(),
+                    });
+                }
+                // Registers are only tracked within a basic block, and a callee may use all
+                // of them, so values are written back to memory at every control transfer.
+                Instr::Label(_) | Instr::Jump(_) | Instr::Call(_) | Instr::Return | Instr::Halt | Instr::Trap => {
+                    self.flush();
+                    self.instrs.push(instr.clone());
+                }
                 _ => {
                     self.instrs.push(instr.clone());
                 }
             }
+
+            self.release_ram_homes();
         }
 
         self.instrs.clone()
     }
 
+    fn allocate_binary(&mut self, dst: &Value, lhs: &Value, rhs: &Value) -> (Value, Value, Value) {
+        let lhs_reg = self.get_or_load(lhs, &[]);
+        let rhs_reg = self.get_or_load(rhs, &[Self::get_id_of(&lhs_reg) as u8]);
+        let dst_reg = self.allocate_register(
+            dst,
+            &[
+                Self::get_id_of(&lhs_reg) as u8,
+                Self::get_id_of(&rhs_reg) as u8,
+            ],
+        );
+        self.dirty[Self::get_id_of(&dst_reg) as usize] = true;
+
+        (dst_reg, lhs_reg, rhs_reg)
+    }
+
+    /// Writes every modified variable and every still needed temporary back to
+    /// memory and forgets all register contents.
+    fn flush(&mut self) {
+        for reg in 0..NUM_REGISTERS {
+            if let Some(value) = self.regs[reg].take()
+                && self.dirty[reg]
+                && self.is_live(&value, self.position + 1)
+            {
+                self.instrs.push(Instr::Store {
+                    dst: self.home(&value),
+                    src: Value::Reg(reg as u8),
+                });
+            }
+
+            self.dirty[reg] = false;
+        }
+    }
+
+    /// Writes variables living in RAM back as soon as they change and forgets them, so loads
+    /// and stores through pointers always see their current value.
+    fn release_ram_homes(&mut self) {
+        for reg in 0..NUM_REGISTERS {
+            if self.regs[reg].is_some_and(|value| self.homes.contains_key(&value)) {
+                self.spill(reg);
+            }
+        }
+    }
+
+    /// Where `value` is kept in memory, a RAM cell if its address is taken.
+    fn home(&self, value: &Value) -> Value {
+        self.homes.get(value).copied().unwrap_or(*value)
+    }
+
+    /// Whether `value` is still read at or after instruction `from`.
+    fn is_live(&self, value: &Value, from: usize) -> bool {
+        match value {
+            Value::Temp(_) => self.last_use.get(value).is_some_and(|&i| i >= from),
+            _ => true,
+        }
+    }
+
     fn allocate_register(&mut self, value: &Value, locked_regs: &[u8]) -> Value {
+        for reg in 0..NUM_REGISTERS {
+            if self.regs[reg] == Some(*value) {
+                return Value::Reg(reg as u8);
+            }
+        }
+
         for reg in 0..NUM_REGISTERS {
             if self.regs[reg].is_none() && !locked_regs.contains(&(reg as u8)) {
                 self.regs[reg] = Some(value.clone());
+                self.dirty[reg] = false;
                 return Value::Reg(reg as u8);
             }
         }
 
-        // Simple spill strategy: spill the first register - for demonstration purposes
-        let spilled_value = self.regs[self.pick_spill_register(locked_regs)]
-            .take()
-            .unwrap();
-        self.ram.push(spilled_value.clone());
-
-        self.instrs.push(Instr::Store {
-            dst: spilled_value,
-            src: Value::Reg(0),
-        });
-        self.regs[0] = Some(value.clone());
-        Value::Reg(0)
+        let reg = self.pick_spill_register(locked_regs);
+        self.evict(reg, locked_regs);
+
+        self.regs[reg] = Some(value.clone());
+        self.dirty[reg] = false;
+        Value::Reg(reg as u8)
+    }
+
+    /// Spills `reg` together with the other pieces of a multi-word value it holds, so those
+    /// never end up split between registers and memory.
+    fn evict(&mut self, reg: usize, locked_regs: &[u8]) {
+        let Some(spilled_value) = self.regs[reg] else {
+            return;
+        };
+        self.spill(reg);
+
+        if let Some(unit) = self.units.iter().find(|unit| unit.contains(&spilled_value)).cloned() {
+            for other in 0..NUM_REGISTERS {
+                if self.regs[other].is_some_and(|value| unit.contains(&value)) && !locked_regs.contains(&(other as u8)) {
+                    self.spill(other);
+                }
+            }
+        }
+    }
+
+    /// Frees `reg`, writing its value back to memory if it is modified and still needed.
+    fn spill(&mut self, reg: usize) {
+        if let Some(value) = self.regs[reg].take()
+            && self.dirty[reg]
+            && self.is_live(&value, self.position)
+        {
+            self.ram.push(value);
+
+            self.instrs.push(Instr::Store {
+                dst: self.home(&value),
+                src: Value::Reg(reg as u8),
+            });
+        }
+
+        self.dirty[reg] = false;
     }
 
     fn get_or_load(&mut self, value: &Value, locked_regs: &[u8]) -> Value {
         for reg in 0..NUM_REGISTERS {
-            if let Some(v) = &self.regs[reg] {
-                if Self::get_id_of(v) == Self::get_id_of(value) {
-                    return Value::Reg(reg as u8);
-                }
+            if self.regs[reg] == Some(*value) {
+                return Value::Reg(reg as u8);
             }
         }
 
         let reg = self.allocate_register(value, locked_regs);
-        self.instrs.push(Instr::Load {
-            dst: reg.clone(),
-            src: value.clone(),
-        });
+
+        // Constants are materialised rather than loaded; the register stays clean, so it is
+        // reused for the same constant and simply dropped when spilled.
+        if let Value::Const(_) | Value::Ptr(_) = value {
+            self.instrs.push(Instr::Immediate {
+                dst: reg,
+                value: *value,
+            });
+        } else {
+            self.instrs.push(Instr::Load {
+                dst: reg.clone(),
+                src: self.home(value),
+            });
+        }
 
         reg
     }
@@ -1,24 +1,45 @@
-use crate::{ast::{Expr, ExprNode, Stmt, StmtNode}, errors::CompileError, symbols::SymbolTable};
+use std::collections::{HashMap, HashSet};
+
+use crate::{asm, ast::{Expr, ExprNode, Stmt, StmtNode}, errors::CompileError, instructions::{Instr, Value}, modules::Module, symbols::{EnumDef, StructDef, SymbolKind, SymbolTable}, target::Target, token::TokenType, types::{IntType, Type}};
 
 pub struct Resolver<'a> {
     table: &'a mut SymbolTable,
+    target: &'a Target,
+    function: Option<u32>,
+    /// Labels of the loops enclosing the current statement, innermost last.
+    loops: Vec<Option<String>>,
+    /// Induction variables of the enclosing `for` loops, which only the loop itself changes.
+    counters: Vec<u32>,
+    /// Calls made from inside functions as (caller, callee, position, source_id).
+    calls: Vec<(u32, u32, usize, usize)>,
+    /// Width literals of the current statement must fit in, wider than the machine word
+    /// when they are combined with a multi-word variable.
+    literal_bits: u32,
 }
 
 impl<'a> Resolver<'a> {
-    pub fn new(table: &'a mut SymbolTable) -> Self {
+    pub fn new(table: &'a mut SymbolTable, target: &'a Target) -> Self {
         Resolver {
             table,
+            target,
+            function: None,
+            loops: Vec::new(),
+            counters: Vec::new(),
+            calls: Vec::new(),
+            literal_bits: target.word_bits,
         }
     }
 
-    fn resolve(&mut self, name: &str, pos: usize, source_id: usize) -> Result<(), CompileError> {
-        self.table.resolve(name).map_err(|msg| CompileError {
+    fn resolve(&mut self, name: &str, pos: usize, source_id: usize) -> Result<SymbolKind, CompileError> {
+        let (id, kind) = self.table.resolve(name).map(|sym| (sym.id, sym.kind)).map_err(|msg| CompileError {
             position: pos,
             source_id,
             message: msg,
         })?;
 
-        Ok(())
+        self.table.resolved.insert((source_id, pos), id);
+
+        Ok(kind)
     }
 
     fn define(&mut self, name: &str, pos: usize, source_id: usize) -> Result<(), CompileError> {
@@ -31,65 +52,1390 @@ impl<'a> Resolver<'a> {
         Ok(())
     }
 
-    pub fn resolve_program(mut self, stmts: &[StmtNode]) -> Result<(), CompileError> {
-        for stmt in stmts {
-            self.resolve_stmt(stmt)?;
+    /// Resolves every module in order, so a module's imports are resolved before it.
+    pub fn resolve_program(mut self, modules: &[Module]) -> Result<(), CompileError> {
+        for module in modules {
+            self.table.begin_module(&module.name, module.exports.iter().cloned().collect(), module.imports.clone());
+
+            // Functions may be called before they are declared, so they are all defined up front,
+            // as are structs.
+            for stmt in &module.stmts {
+                if let Stmt::Struct { name, fields } = &stmt.node {
+                    self.define_struct(name, fields)?;
+                }
+
+                if let Stmt::Function { name: ExprNode { node: Expr::Variable(name), position, source_id }, params, .. } = &stmt.node {
+                    self.table
+                        .define_kind(name, SymbolKind::Function { arity: params.len() }, *position, *source_id)
+                        .map_err(|msg| CompileError { position: *position, source_id: *source_id, message: msg })?;
+                }
+            }
+
+            for stmt in &module.stmts {
+                self.resolve_stmt(stmt)?;
+            }
         }
 
-        Ok(())
+        self.check_recursion()
     }
 
     fn resolve_stmt(&mut self, stmt: &StmtNode) -> Result<(), CompileError> {
+        let word = self.target.word_bits;
+
         match &stmt.node {
-            Stmt::Declare { target, value } => {
+            Stmt::Declare { target, ty, value } => {
+                if let Some(index) = self.resolve_struct_value(value)? {
+                    if ty.is_some() {
+                        return Err(CompileError {
+                            message: "struct variables take the struct's fields, not a type annotation".to_string(),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    }
+
+                    if let Expr::Variable(name) = &target.node {
+                        self.table
+                            .define_record(name, index, target.position, target.source_id)
+                            .map_err(|msg| CompileError { position: target.position, source_id: target.source_id, message: msg })?;
+                    }
+
+                    return Ok(());
+                }
+
+                // Only integer types affect code generation, `bool` is checked by the type checker.
+                let ty = match ty {
+                    Some(Type::Int(ty)) => *ty,
+                    _ => None,
+                };
+
+                self.literal_bits = word.max(self.widest(value)).max(ty.map_or(0, |ty| ty.bits));
                 self.resolve_expr(value)?;
 
                 if let ExprNode { node: Expr::Variable(name), position: _, source_id: _ } = target {
                     self.define(name, target.position, target.source_id)?;
                 }
+
+                if let Some(ty) = ty {
+                    // Wider values are split into whole words by the legalizer.
+                    if ty.bits > word && ty.bits % word != 0 {
+                        return Err(CompileError {
+                            message: format!("`{}` is wider than the {}-bit machine word, but not a multiple of it", ty.name(), word),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    }
+
+                    let id = self.table.id_at(target.position, target.source_id);
+                    self.table.types.insert(id, ty);
+                    Self::check_fits(value, ty)?;
+                } else {
+                    // Untyped variables hold a signed machine word.
+                    Self::check_fits(value, IntType { signed: true, bits: word })?;
+                }
             }
 
-            Stmt::Assign { target, value } => {
+            // Imported modules were loaded and resolved before this one, structs were defined up front.
+            Stmt::Import { .. } | Stmt::Struct { .. } => {}
+
+            Stmt::Const { target, value } => {
+                self.record_names(value)?;
+                let value = self.evaluate(value)?;
+
+                if let Expr::Variable(name) = &target.node {
+                    self.table
+                        .define_kind(name, SymbolKind::Constant { value }, target.position, target.source_id)
+                        .map_err(|msg| CompileError { position: target.position, source_id: target.source_id, message: msg })?;
+                }
+            }
+
+            Stmt::Enum { name, variants } => self.define_enum(name, variants)?,
+
+            Stmt::Port { target, ty, address } => {
+                let value = self.evaluate(address)?;
+
+                if !(IntType { signed: false, bits: word }).contains(value as i64) {
+                    return Err(CompileError {
+                        message: format!("port address {} is outside the {}-bit address space", value, word),
+                        position: address.position,
+                        source_id: address.source_id,
+                    });
+                }
+
+                if let Some(ty) = ty
+                    && ty.bits > word
+                {
+                    return Err(CompileError {
+                        message: format!("port type `{}` is wider than the {}-bit machine word", ty.name(), word),
+                        position: target.position,
+                        source_id: target.source_id,
+                    });
+                }
+
+                if let Expr::Variable(name) = &target.node {
+                    self.table
+                        .define_kind(name, SymbolKind::Port { address: value }, target.position, target.source_id)
+                        .map_err(|msg| CompileError { position: target.position, source_id: target.source_id, message: msg })?;
+                }
+
+                if let Some(ty) = ty {
+                    let id = self.table.id_at(target.position, target.source_id);
+                    self.table.types.insert(id, *ty);
+                }
+            }
+
+            Stmt::DeclareArray { target, length, element, values, constant } => {
+                let length = match (length, values) {
+                    (Some(length), _) => match self.evaluate(length)? {
+                        n @ 1.. => n as u32,
+                        _ => {
+                            return Err(CompileError {
+                                message: "array length must be a positive number".to_string(),
+                                position: length.position,
+                                source_id: length.source_id,
+                            });
+                        }
+                    },
+                    (None, Some(values)) if !values.is_empty() => values.len() as u32,
+                    (None, _) => {
+                        return Err(CompileError {
+                            message: "array needs at least one element".to_string(),
+                            position: stmt.position,
+                            source_id: stmt.source_id,
+                        });
+                    }
+                };
+
+                if let Some(values) = values
+                    && values.len() as u32 != length
+                {
+                    return Err(CompileError {
+                        message: format!("array has length {}, but {} values were given", length, values.len()),
+                        position: stmt.position,
+                        source_id: stmt.source_id,
+                    });
+                }
+
+                // Without a declared element the values decide whether the array holds structs.
+                let mut element = match element {
+                    Some(name) => match &name.node {
+                        Expr::Variable(struct_name) => Some(self.struct_named(struct_name, name.position, name.source_id)?),
+                        _ => unreachable!("the parser only names element structs"),
+                    },
+                    None => None,
+                };
+
+                for (i, value) in values.iter().flatten().enumerate() {
+                    let found = self.resolve_struct_value(value)?;
+
+                    if i == 0 && element.is_none() {
+                        element = found;
+                    }
+
+                    if found != element {
+                        return Err(self.struct_mismatch(element, found, value));
+                    }
+
+                    if found.is_none() {
+                        self.literal_bits = word;
+                        self.resolve_expr(value)?;
+                    }
+                }
+
+                if let Expr::Variable(name) = &target.node {
+                    self.table
+                        .define_kind(name, SymbolKind::Array { length, element }, target.position, target.source_id)
+                        .map_err(|msg| CompileError { position: target.position, source_id: target.source_id, message: msg })?;
+                }
+
+                // Tables are evaluated here, the code generator only places their contents.
+                if *constant {
+                    let error = |message: &str| CompileError {
+                        message: message.to_string(),
+                        position: stmt.position,
+                        source_id: stmt.source_id,
+                    };
+
+                    let Some(values) = values else {
+                        return Err(error("constant arrays need their values, like `const TABLE = [1, 2, 3];`"));
+                    };
+
+                    if element.is_some() {
+                        return Err(error("constant arrays hold integers, not structs"));
+                    }
+
+                    let words = values.iter().map(|value| self.evaluate(value).map(|n| self.target.signed(n))).collect::<Result<_, _>>()?;
+                    let id = self.table.id_at(target.position, target.source_id);
+                    self.table.tables.insert(id, words);
+                }
+            }
+
+            Stmt::Assign { target, value } | Stmt::CompoundAssign { target, value, .. } => {
+                // Whole structs are copied field by field, between variables and array elements.
+                if let Stmt::Assign { .. } = &stmt.node {
+                    match (self.resolve_struct_value(target)?, self.resolve_struct_value(value)?) {
+                        (None, None) => {}
+                        (expected, found) if expected == found => return Ok(()),
+                        (expected, found) => return Err(self.struct_mismatch(expected, found, value)),
+                    }
+                }
+
+                self.literal_bits = word.max(self.widest(target)).max(self.widest(value));
                 self.resolve_expr(value)?;
 
                 self.resolve_expr(target)?;
+
+                if let Expr::Index { array, .. } = &target.node
+                    && let Expr::Variable(name) = &array.node
+                    && self.table.tables.contains_key(&self.table.id_at(array.position, array.source_id))
+                {
+                    return Err(CompileError {
+                        message: format!("cannot assign to constant array `{}`", name),
+                        position: target.position,
+                        source_id: target.source_id,
+                    });
+                }
+
+                if let Stmt::CompoundAssign { operator, .. } = &stmt.node {
+                    self.check_wide_operator(operator, target, value, stmt.position, stmt.source_id)?;
+                }
+
+                if let Expr::Variable(name) = &target.node
+                    && let SymbolKind::Constant { .. } = self.table.kind_of(self.table.id_at(target.position, target.source_id))
+                {
+                    return Err(CompileError {
+                        message: format!("cannot assign to constant `{}`", name),
+                        position: target.position,
+                        source_id: target.source_id,
+                    });
+                }
+
+                if let Expr::Variable(name) = &target.node
+                    && self.counters.contains(&self.table.id_at(target.position, target.source_id))
+                {
+                    return Err(CompileError {
+                        message: format!("cannot assign to loop variable `{}`", name),
+                        position: target.position,
+                        source_id: target.source_id,
+                    });
+                }
+
+                if let (Stmt::Assign { .. }, Expr::Variable(_)) = (&stmt.node, &target.node)
+                    && let Some(&ty) = self.table.types.get(&self.table.id_at(target.position, target.source_id))
+                {
+                    Self::check_fits(value, ty)?;
+                }
+            }
+
+            Stmt::If { condition, body, else_body } => {
+                self.literal_bits = word.max(self.widest(condition));
+                self.resolve_expr(condition)?;
+
+                self.resolve_block(body)?;
+
+                if let Some(else_body) = else_body {
+                    self.resolve_block(else_body)?;
+                }
             }
 
-            Stmt::If { condition, body } |
-            Stmt::While { condition, body } => {
+            Stmt::While { label, condition, body } => {
+                self.literal_bits = word.max(self.widest(condition));
                 self.resolve_expr(condition)?;
 
+                self.enter_loop(label)?;
+                self.resolve_block(body)?;
+                self.loops.pop();
+            }
+
+            Stmt::For { label, var, start, end, body, .. } => {
+                self.literal_bits = word;
+
+                for bound in [start, end] {
+                    self.resolve_expr(bound)?;
+
+                    if self.width(bound) > word {
+                        return Err(CompileError {
+                            message: format!("loop bounds must fit the {}-bit machine word", word),
+                            position: bound.position,
+                            source_id: bound.source_id,
+                        });
+                    }
+                }
+
+                self.enter_loop(label)?;
                 self.table.begin_scope();
 
+                if let Expr::Variable(name) = &var.node {
+                    self.define(name, var.position, var.source_id)?;
+                }
+
+                self.counters.push(self.table.id_at(var.position, var.source_id));
+                self.resolve_block(body)?;
+                self.counters.pop();
+
+                self.table.end_scope();
+                self.loops.pop();
+            }
+
+            // Patterns are constants with distinct values, and `_` must come last.
+            Stmt::Match { value, arms } => {
+                self.literal_bits = word.max(self.widest(value));
+                self.resolve_expr(value)?;
+
+                if self.width(value) > word {
+                    return Err(CompileError {
+                        message: format!("matched values must fit the {}-bit machine word", word),
+                        position: value.position,
+                        source_id: value.source_id,
+                    });
+                }
+
+                let mut matched: HashMap<i32, &ExprNode> = HashMap::new();
+                let mut wildcard = false;
+
+                for arm in arms {
+                    if wildcard {
+                        return Err(CompileError {
+                            message: "unreachable arm, `_` already matches every value".to_string(),
+                            position: arm.position,
+                            source_id: arm.source_id,
+                        });
+                    }
+
+                    for pattern in arm.node.patterns.iter().flatten() {
+                        let n = self.target.signed(self.evaluate(pattern)?);
+
+                        self.literal_bits = word;
+                        self.resolve_expr(pattern)?;
+
+                        if matched.insert(n, pattern).is_some() {
+                            return Err(CompileError {
+                                message: format!("value {} is already matched by an earlier pattern", n),
+                                position: pattern.position,
+                                source_id: pattern.source_id,
+                            });
+                        }
+
+                        self.table.patterns.insert((pattern.source_id, pattern.position), n);
+                    }
+
+                    wildcard |= arm.node.patterns.is_none();
+                    self.resolve_block(&arm.node.body)?;
+                }
+            }
+
+            Stmt::Break { label } | Stmt::Continue { label } => {
+                let keyword = match stmt.node {
+                    Stmt::Break { .. } => "break",
+                    _ => "continue",
+                };
+
+                if self.loops.is_empty() {
+                    return Err(CompileError {
+                        message: format!("`{}` outside of a loop", keyword),
+                        position: stmt.position,
+                        source_id: stmt.source_id,
+                    });
+                }
+
+                if let Some(ExprNode { node: Expr::Variable(name), position, source_id }) = label
+                    && !self.loops.iter().flatten().any(|outer| outer == name)
+                {
+                    return Err(CompileError {
+                        message: format!("`{}` to unknown loop label `{}`", keyword, name),
+                        position: *position,
+                        source_id: *source_id,
+                    });
+                }
+            }
+
+            Stmt::Function { name, params, body } => {
+                if self.function.is_some() || !self.table.at_top_level() {
+                    return Err(CompileError {
+                        message: "functions can only be declared at the top level".to_string(),
+                        position: stmt.position,
+                        source_id: stmt.source_id,
+                    });
+                }
+
+                self.function = Some(self.table.id_at(name.position, name.source_id));
+                self.table.begin_scope();
+
+                for param in params {
+                    if let Expr::Variable(param_name) = &param.node {
+                        self.define(param_name, param.position, param.source_id)?;
+                    }
+                }
+
                 for stmt in body {
                     self.resolve_stmt(stmt)?;
                 }
 
                 self.table.end_scope();
+                self.function = None;
+            }
+
+            Stmt::Return { value } => {
+                if self.function.is_none() {
+                    return Err(CompileError {
+                        message: "`return` outside of a function".to_string(),
+                        position: stmt.position,
+                        source_id: stmt.source_id,
+                    });
+                }
+
+                if let Some(value) = value {
+                    self.literal_bits = word.max(self.widest(value));
+                    self.resolve_expr(value)?;
+                }
+            }
+
+            Stmt::Expression { expr } => {
+                self.literal_bits = word.max(self.widest(expr));
+                self.resolve_expr(expr)?;
+            }
+
+            Stmt::Asm { inputs, outputs, clobbers, body } => {
+                let mut readable = HashSet::new();
+                let mut writable = HashSet::new();
+                let mut output_vars = HashSet::new();
+                let mut output_regs = Vec::new();
+
+                for (register, value) in inputs {
+                    let reg = Self::asm_register(register, &mut readable)?;
+
+                    self.literal_bits = word;
+                    self.resolve_expr(value)?;
+
+                    if self.width(value) > word {
+                        return Err(CompileError {
+                            message: format!("`r{}` cannot hold a value wider than the {}-bit machine word", reg, word),
+                            position: value.position,
+                            source_id: value.source_id,
+                        });
+                    }
+                }
+
+                for (register, target) in outputs {
+                    output_regs.push((Self::asm_register(register, &mut writable)?, register));
+
+                    let Expr::Variable(name) = &target.node else {
+                        return Err(CompileError {
+                            message: "asm outputs must be variables".to_string(),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    };
+
+                    if self.resolve(name, target.position, target.source_id)? != SymbolKind::Variable {
+                        return Err(CompileError {
+                            message: format!("`{}` is not a variable", name),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    }
+
+                    let id = self.table.id_at(target.position, target.source_id);
+                    if self.counters.contains(&id) {
+                        return Err(CompileError {
+                            message: format!("cannot assign to loop variable `{}`", name),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    }
+
+                    if !output_vars.insert(id) {
+                        return Err(CompileError {
+                            message: format!("`{}` is bound to more than one output", name),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    }
+
+                    if let Some(ty) = self.table.types.get(&id)
+                        && ty.bits > word
+                    {
+                        return Err(CompileError {
+                            message: format!("`{}` of type `{}` does not fit a {}-bit register", name, ty.name(), word),
+                            position: target.position,
+                            source_id: target.source_id,
+                        });
+                    }
+                }
+
+                for clobber in clobbers {
+                    Self::asm_register(clobber, &mut writable)?;
+                }
+
+                // Registers the body reads must have been given a value, and the ones it writes
+                // must be known to the register allocator.
+                let mut written = HashSet::new();
+                let mut assembled: Vec<Instr> = Vec::new();
+
+                for line in body {
+                    let error = |message: String| CompileError {
+                        message,
+                        position: line.position,
+                        source_id: line.source_id,
+                    };
+
+                    let instr = asm::assemble(&line.node, self.target).map_err(error)?;
+
+                    for value in instr.uses() {
+                        let Value::Reg(reg) = value else {
+                            continue;
+                        };
+
+                        if !readable.contains(&reg) && !written.contains(&reg) {
+                            return Err(error(format!("`r{}` is read before it is written, pass it in with `in(r{}: ...)`", reg, reg)));
+                        }
+
+                        // Inside the block hazards are not resolved by the scheduler.
+                        for (between, earlier) in assembled.iter().rev().enumerate() {
+                            if earlier.def() == Some(value) {
+                                let latency = self.target.latency_of(earlier);
+                                if (between as u32) < latency {
+                                    return Err(error(format!(
+                                        "`r{}` is read {} instructions after it is written, target `{}` needs {} in between",
+                                        reg, between, self.target.name, latency
+                                    )));
+                                }
+                                break;
+                            }
+                        }
+                    }
+
+                    if let Some(Value::Reg(reg)) = instr.def() {
+                        if !writable.contains(&reg) {
+                            return Err(error(format!("`r{}` is overwritten, list it in `out` or `clobber`", reg)));
+                        }
+
+                        written.insert(reg);
+                    }
+
+                    assembled.push(instr);
+                }
+
+                for (reg, register) in output_regs {
+                    if !written.contains(&reg) && !readable.contains(&reg) {
+                        return Err(CompileError {
+                            message: format!("output `r{}` is never written", reg),
+                            position: register.position,
+                            source_id: register.source_id,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rejects operators on multi-word values that only get carry chains, there are no routines for these.
+    fn check_wide_operator(&self, operator: &TokenType, left: &ExprNode, right: &ExprNode, position: usize, source_id: usize) -> Result<(), CompileError> {
+        let word = self.target.word_bits;
+        let operand_bits = match operator {
+            TokenType::SHL | TokenType::SHR => self.width(left),
+            _ => self.width(left).max(self.width(right)),
+        };
+        let unsupported = match operator {
+            TokenType::ASTERISK => Some("*"),
+            TokenType::SLASH => Some("/"),
+            TokenType::PERCENT => Some("%"),
+            TokenType::SHL => Some("<<"),
+            TokenType::SHR => Some(">>"),
+            _ => None,
+        };
+
+        match unsupported {
+            Some(symbol) if operand_bits > word => Err(CompileError {
+                message: format!("`{}` is not supported on values wider than the {}-bit machine word", symbol, word),
+                position,
+                source_id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a `struct` declaration, which needs at least one field and no field twice.
+    fn define_struct(&mut self, name: &ExprNode, fields: &[ExprNode]) -> Result<(), CompileError> {
+        let Expr::Variable(struct_name) = &name.node else {
+            unreachable!("the parser only declares names");
+        };
+
+        let mut names: Vec<String> = Vec::new();
+        for field in fields {
+            let Expr::Variable(field_name) = &field.node else {
+                unreachable!("the parser only declares names");
+            };
+
+            if names.contains(field_name) {
+                return Err(CompileError {
+                    message: format!("field `{}` is declared twice in struct `{}`", field_name, struct_name),
+                    position: field.position,
+                    source_id: field.source_id,
+                });
             }
+
+            names.push(field_name.clone());
+        }
+
+        if names.is_empty() {
+            return Err(CompileError {
+                message: format!("struct `{}` needs at least one field", struct_name),
+                position: name.position,
+                source_id: name.source_id,
+            });
         }
 
+        let index = self.table.structs.len();
+        self.table
+            .define_kind(struct_name, SymbolKind::Struct { index }, name.position, name.source_id)
+            .map_err(|msg| CompileError { position: name.position, source_id: name.source_id, message: msg })?;
+        self.table.structs.push(StructDef { name: struct_name.clone(), fields: names });
+
+        Ok(())
+    }
+
+    /// Records an `enum` declaration and defines its variants as constants, which need
+    /// distinct names and values that fit the machine word.
+    fn define_enum(&mut self, name: &ExprNode, variants: &[(ExprNode, Option<ExprNode>)]) -> Result<(), CompileError> {
+        let Expr::Variable(enum_name) = &name.node else {
+            unreachable!("the parser only declares names");
+        };
+
+        if variants.is_empty() {
+            return Err(CompileError {
+                message: format!("enum `{}` needs at least one variant", enum_name),
+                position: name.position,
+                source_id: name.source_id,
+            });
+        }
+
+        let index = self.table.enums.len();
+        self.table
+            .define_kind(enum_name, SymbolKind::Enum { index }, name.position, name.source_id)
+            .map_err(|msg| CompileError { position: name.position, source_id: name.source_id, message: msg })?;
+
+        let word = self.target.word_bits;
+        let mut values: Vec<(String, i32)> = Vec::new();
+        let mut next = 0i64;
+
+        for (variant, value) in variants {
+            let Expr::Variable(variant_name) = &variant.node else {
+                unreachable!("the parser only declares names");
+            };
+
+            let error = |message: String| CompileError {
+                message,
+                position: variant.position,
+                source_id: variant.source_id,
+            };
+
+            let n = match value {
+                Some(value) => self.evaluate(value)? as i64,
+                None => next,
+            };
+
+            if !(IntType { signed: true, bits: word }).contains(n) && !(IntType { signed: false, bits: word }).contains(n) {
+                return Err(error(format!("`{}.{}` = {} does not fit in the {}-bit machine word", enum_name, variant_name, n, word)));
+            }
+
+            if let Some((other, _)) = values.iter().find(|(_, other)| self.target.signed(*other) == self.target.signed(n as i32)) {
+                return Err(error(format!("`{}` has the same value as `{}`", variant_name, other)));
+            }
+
+            self.table
+                .define_kind(&format!("{}.{}", enum_name, variant_name), SymbolKind::Constant { value: n as i32 }, variant.position, variant.source_id)
+                .map_err(|_| error(format!("variant `{}` is declared twice in enum `{}`", variant_name, enum_name)))?;
+
+            let id = self.table.id_at(variant.position, variant.source_id);
+            self.table.variants.insert(id, index);
+
+            values.push((variant_name.clone(), n as i32));
+            next = n + 1;
+        }
+
+        self.table.enums.push(EnumDef { name: enum_name.clone(), variants: values });
+
+        Ok(())
+    }
+
+    /// Index of the struct called `name`.
+    fn struct_named(&mut self, name: &str, pos: usize, source_id: usize) -> Result<usize, CompileError> {
+        match self.resolve(name, pos, source_id)? {
+            SymbolKind::Struct { index } => Ok(index),
+            _ => Err(CompileError {
+                message: format!("`{}` is not a struct", name),
+                position: pos,
+                source_id,
+            }),
+        }
+    }
+
+    /// Resolves `value` if it is a whole struct, a literal, a struct variable or an element of
+    /// an array of structs, and returns which struct it is. Anything else is left unresolved.
+    fn resolve_struct_value(&mut self, value: &ExprNode) -> Result<Option<usize>, CompileError> {
+        let word = self.target.word_bits;
+
+        match &value.node {
+            Expr::StructLiteral { name, fields } => {
+                let index = self.struct_named(name, value.position, value.source_id)?;
+                let StructDef { name, fields: declared } = &self.table.structs[index];
+                let (name, declared) = (name.clone(), declared.clone());
+
+                let mut given: Vec<&String> = Vec::new();
+                for (field, field_value) in fields {
+                    let Expr::Variable(field_name) = &field.node else {
+                        unreachable!("the parser only names fields");
+                    };
+
+                    let error = |message: String| CompileError {
+                        message,
+                        position: field.position,
+                        source_id: field.source_id,
+                    };
+
+                    if !declared.contains(field_name) {
+                        return Err(error(format!("struct `{}` has no field `{}`", name, field_name)));
+                    }
+
+                    if given.contains(&field_name) {
+                        return Err(error(format!("field `{}` is given twice", field_name)));
+                    }
+
+                    given.push(field_name);
+
+                    self.literal_bits = word;
+                    self.resolve_expr(field_value)?;
+
+                    if self.width(field_value) > word {
+                        return Err(error(format!("struct fields hold a single {}-bit machine word", word)));
+                    }
+                }
+
+                if let Some(missing) = declared.iter().find(|field| !given.contains(field)) {
+                    return Err(CompileError {
+                        message: format!("missing field `{}` in `{}`", missing, name),
+                        position: value.position,
+                        source_id: value.source_id,
+                    });
+                }
+
+                Ok(Some(index))
+            }
+
+            Expr::Variable(name) => match self.table.resolve(name).map(|symbol| symbol.kind) {
+                Ok(SymbolKind::Record { index }) => {
+                    self.resolve(name, value.position, value.source_id)?;
+                    Ok(Some(index))
+                }
+                _ => Ok(None),
+            },
+
+            Expr::Index { array, index } => {
+                let Expr::Variable(name) = &array.node else {
+                    unreachable!("the parser only indexes names");
+                };
+
+                match self.table.resolve(name).map(|symbol| symbol.kind) {
+                    Ok(SymbolKind::Array { element: Some(element), .. }) => {
+                        self.literal_bits = word;
+                        self.resolve_element(array, index)?;
+                        Ok(Some(element))
+                    }
+                    _ => Ok(None),
+                }
+            }
+
+            _ => Ok(None),
+        }
+    }
+
+    /// Resolves `array[index]` and returns the struct the array holds, if any.
+    fn resolve_element(&mut self, array: &ExprNode, index: &ExprNode) -> Result<Option<usize>, CompileError> {
+        let Expr::Variable(name) = &array.node else {
+            unreachable!("the parser only indexes names");
+        };
+
+        let (length, element) = match self.resolve(name, array.position, array.source_id)? {
+            SymbolKind::Array { length, element } => (length, element),
+            _ => {
+                return Err(CompileError {
+                    message: format!("`{}` is not an array", name),
+                    position: array.position,
+                    source_id: array.source_id,
+                });
+            }
+        };
+
+        // Indexes known at compile time are checked here, the rest at runtime if enabled.
+        if let Ok(n) = self.evaluate(index)
+            && (n < 0 || n as u32 >= length)
+        {
+            return Err(CompileError {
+                message: format!("index {} is out of bounds for `{}` of length {}", n, name, length),
+                position: index.position,
+                source_id: index.source_id,
+            });
+        }
+
+        self.resolve_expr(index)?;
+
+        Ok(element)
+    }
+
+    /// Error for a value that is a different struct than expected, or `None` for an integer.
+    fn struct_mismatch(&self, expected: Option<usize>, found: Option<usize>, value: &ExprNode) -> CompileError {
+        let name = |index: Option<usize>| index.map_or("integer".to_string(), |index| self.table.structs[index].name.clone());
+
+        CompileError {
+            message: format!("expected `{}`, found `{}`", name(expected), name(found)),
+            position: value.position,
+            source_id: value.source_id,
+        }
+    }
+
+    /// Enters a loop, whose label must differ from those of the loops around it.
+    fn enter_loop(&mut self, label: &Option<ExprNode>) -> Result<(), CompileError> {
+        let name = label.as_ref().map(|label| match &label.node {
+            Expr::Variable(name) => name.clone(),
+            _ => unreachable!("the parser only labels with names"),
+        });
+
+        if let (Some(label), Some(name)) = (label, &name)
+            && self.loops.iter().flatten().any(|outer| outer == name)
+        {
+            return Err(CompileError {
+                message: format!("loop label `{}` is already used by an enclosing loop", name),
+                position: label.position,
+                source_id: label.source_id,
+            });
+        }
+
+        self.loops.push(name);
+
+        Ok(())
+    }
+
+    fn resolve_block(&mut self, body: &[StmtNode]) -> Result<(), CompileError> {
+        self.table.begin_scope();
+
+        for stmt in body {
+            self.resolve_stmt(stmt)?;
+        }
+
+        self.table.end_scope();
+
         Ok(())
     }
 
     fn resolve_expr(&mut self, expr: &ExprNode) -> Result<(), CompileError> {
         match &expr.node {
-            Expr::Variable(name) => {
-                self.resolve(name, expr.position, expr.source_id)?;
+            Expr::Variable(name) => match self.resolve(name, expr.position, expr.source_id)? {
+                SymbolKind::Variable | SymbolKind::Port { .. } => {}
+                SymbolKind::Constant { value } => self.check_literal(value as i64, expr)?,
+                SymbolKind::Array { .. } => {
+                    return Err(CompileError {
+                        message: format!("array `{}` used as a value, index it with `[]`", name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
+                SymbolKind::Function { .. } => {
+                    return Err(CompileError {
+                        message: format!("function `{}` used as a variable", name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
+                SymbolKind::Record { .. } => {
+                    return Err(CompileError {
+                        message: format!("struct `{}` used as a value, access one of its fields like `{}.field`", name, name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
+                SymbolKind::Struct { .. } => {
+                    return Err(CompileError {
+                        message: format!("struct type `{}` used as a value", name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
+                SymbolKind::Enum { .. } => {
+                    return Err(CompileError {
+                        message: format!("enum `{}` used as a value, name one of its variants like `{}.Variant`", name, name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
+            },
+
+            Expr::Index { array, index } => {
+                if self.resolve_element(array, index)?.is_some() {
+                    let Expr::Variable(name) = &array.node else {
+                        unreachable!("the parser only indexes names");
+                    };
+
+                    return Err(CompileError {
+                        message: format!("`{}` holds structs, access a field of its elements like `{}[i].field`", name, name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
             }
 
-            Expr::Binary { left, right, .. } => {
+            Expr::Field { base, field } => {
+                let Expr::Index { array, index } = &base.node else {
+                    unreachable!("the parser only takes fields of array elements");
+                };
+
+                let Some(element) = self.resolve_element(array, index)? else {
+                    let Expr::Variable(name) = &array.node else {
+                        unreachable!("the parser only indexes names");
+                    };
+
+                    return Err(CompileError {
+                        message: format!("`{}` holds integers, which have no fields", name),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                };
+
+                let def = &self.table.structs[element];
+                if !def.fields.contains(field) {
+                    return Err(CompileError {
+                        message: format!("struct `{}` has no field `{}`", def.name, field),
+                        position: expr.position,
+                        source_id: expr.source_id,
+                    });
+                }
+            }
+
+            Expr::AddressOf { target } => self.resolve_address(target)?,
+
+            Expr::Deref { pointer } => {
+                self.resolve_expr(pointer)?;
+
+                if self.width(pointer) > self.target.word_bits {
+                    return Err(CompileError {
+                        message: format!("addresses are single {}-bit machine words", self.target.word_bits),
+                        position: pointer.position,
+                        source_id: pointer.source_id,
+                    });
+                }
+            }
+
+            // Struct literals are only parsed as values, which `resolve_struct_value` handles.
+            Expr::StructLiteral { .. } => unreachable!("struct literals are resolved as values"),
+
+            Expr::Binary { left, right, operator } => {
                 self.resolve_expr(left)?;
                 self.resolve_expr(right)?;
+
+                self.check_wide_operator(operator, left, right, expr.position, expr.source_id)?;
             }
 
             Expr::Unary { operand, .. } => {
                 self.resolve_expr(operand)?;
             }
 
-            Expr::Number(_) => {}
+            Expr::Call { callee, args } => {
+                match self.resolve(callee, expr.position, expr.source_id)? {
+                    SymbolKind::Function { arity } if arity == args.len() => {}
+                    SymbolKind::Function { arity } => {
+                        return Err(CompileError {
+                            message: format!("function `{}` takes {} arguments, but {} were given", callee, arity, args.len()),
+                            position: expr.position,
+                            source_id: expr.source_id,
+                        });
+                    }
+                    SymbolKind::Variable
+                    | SymbolKind::Constant { .. }
+                    | SymbolKind::Port { .. }
+                    | SymbolKind::Array { .. }
+                    | SymbolKind::Struct { .. }
+                    | SymbolKind::Record { .. }
+                    | SymbolKind::Enum { .. } => {
+                        return Err(CompileError {
+                            message: format!("`{}` is not a function", callee),
+                            position: expr.position,
+                            source_id: expr.source_id,
+                        });
+                    }
+                }
+
+                if let Some(caller) = self.function {
+                    let callee = self.table.id_at(expr.position, expr.source_id);
+                    self.calls.push((caller, callee, expr.position, expr.source_id));
+                }
+
+                for arg in args {
+                    self.resolve_expr(arg)?;
+                }
+            }
+
+            Expr::Number(n) => self.check_literal(*n, expr)?,
+            Expr::Bool(_) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the operand of `&`, a variable, an array or an element of one. Variables whose
+    /// address is taken are recorded, as they have to live in RAM.
+    fn resolve_address(&mut self, target: &ExprNode) -> Result<(), CompileError> {
+        let error = |message: String| CompileError {
+            message,
+            position: target.position,
+            source_id: target.source_id,
+        };
+
+        match &target.node {
+            Expr::Variable(name) => match self.resolve(name, target.position, target.source_id)? {
+                SymbolKind::Variable => {
+                    let id = self.table.id_at(target.position, target.source_id);
+
+                    if self.counters.contains(&id) {
+                        return Err(error(format!("cannot take the address of loop variable `{}`", name)));
+                    }
+
+                    if let Some(ty) = self.table.types.get(&id)
+                        && ty.bits > self.target.word_bits
+                    {
+                        return Err(error(format!(
+                            "cannot take the address of `{}`, only single {}-bit machine words have one",
+                            name, self.target.word_bits
+                        )));
+                    }
+
+                    self.table.addressed.insert(id);
+                }
+                SymbolKind::Array { .. } => self.check_in_ram(target)?,
+                SymbolKind::Port { .. } => return Err(error(format!("port `{}` is not in RAM and has no address", name))),
+                SymbolKind::Record { .. } => {
+                    return Err(error(format!("cannot take the address of struct `{}`, take that of a field like `&{}.field`", name, name)));
+                }
+                _ => return Err(error(format!("cannot take the address of `{}`", name))),
+            },
+            // The address of a struct element is that of its first field.
+            Expr::Index { array, index } => {
+                self.resolve_element(array, index)?;
+                self.check_in_ram(array)?;
+            }
+            Expr::Field { .. } => self.resolve_expr(target)?,
+            _ => return Err(error("only variables, arrays and their elements have an address".to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Rejects pointers to a constant array the target keeps in ROM, which loads can't reach.
+    fn check_in_ram(&self, array: &ExprNode) -> Result<(), CompileError> {
+        let Expr::Variable(name) = &array.node else {
+            unreachable!("the parser only indexes names");
+        };
+
+        match self.target.rom_loads && self.table.tables.contains_key(&self.table.id_at(array.position, array.source_id)) {
+            true => Err(CompileError {
+                message: format!("`{}` is kept in ROM, which pointers can't reach, index it instead", name),
+                position: array.position,
+                source_id: array.source_id,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    /// Number of a register bound in an `asm` block, which must not be bound twice in `seen`.
+    fn asm_register(register: &ExprNode, seen: &mut HashSet<u8>) -> Result<u8, CompileError> {
+        let Expr::Variable(name) = &register.node else {
+            unreachable!("the parser only binds names");
+        };
+        let error = |message: String| CompileError {
+            message,
+            position: register.position,
+            source_id: register.source_id,
+        };
+
+        let reg = asm::register(name).ok_or_else(|| error(format!("unknown register `{}`", name)))?;
+
+        match seen.insert(reg) {
+            true => Ok(reg),
+            false => Err(error(format!("register `{}` is bound more than once", name))),
+        }
+    }
+
+    /// Literals and constants may be written as signed or unsigned, as long as the bits fit
+    /// the word, or the multi-word values they are used with.
+    fn check_literal(&self, n: i64, expr: &ExprNode) -> Result<(), CompileError> {
+        let bits = self.literal_bits;
+        let signed = IntType { signed: true, bits };
+        let unsigned = IntType { signed: false, bits };
+
+        if signed.contains(n) || unsigned.contains(n) {
+            return Ok(());
+        }
+
+        let message = match bits == self.target.word_bits {
+            true => format!("`{}` does not fit in the {}-bit machine word", n, bits),
+            false => format!("`{}` does not fit in {} bits", n, bits),
+        };
+
+        Err(CompileError {
+            message,
+            position: expr.position,
+            source_id: expr.source_id,
+        })
+    }
+
+    /// Records what the names in a constant expression refer to, which `evaluate` doesn't,
+    /// for the passes that look at the expression after the resolver.
+    fn record_names(&mut self, expr: &ExprNode) -> Result<(), CompileError> {
+        match &expr.node {
+            Expr::Variable(name) => self.resolve(name, expr.position, expr.source_id).map(|_| ()),
+            Expr::Unary { operand, .. } => self.record_names(operand),
+            Expr::Binary { left, right, .. } => {
+                self.record_names(left)?;
+                self.record_names(right)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates a constant expression, made of literals, constants and operators.
+    /// Names are looked up directly, so `expr` does not need to be resolved first.
+    fn evaluate(&self, expr: &ExprNode) -> Result<i32, CompileError> {
+        let error = |message: &str| CompileError {
+            message: message.to_string(),
+            position: expr.position,
+            source_id: expr.source_id,
+        };
+
+        match &expr.node {
+            // Constants are evaluated in 32 bits, where unsigned values keep their bits.
+            Expr::Number(n) => i32::try_from(*n)
+                .or_else(|_| u32::try_from(*n).map(|n| n as i32))
+                .map_err(|_| error(&format!("`{}` does not fit in 32 bits", n))),
+            Expr::Bool(b) => Ok(*b as i32),
+            Expr::Variable(name) => match self.table.resolve(name).map_err(|msg| error(&msg))?.kind {
+                SymbolKind::Constant { value } => Ok(value),
+                _ => Err(error(&format!("`{}` is not a constant", name))),
+            },
+            Expr::Unary { operator, operand } => {
+                let value = self.evaluate(operand)?;
+
+                match operator {
+                    TokenType::MINUS => value.checked_neg(),
+                    TokenType::TILDE => Some(!value),
+                    TokenType::BANG => Some((value == 0) as i32),
+                    _ => None,
+                }
+                .ok_or_else(|| error("constant expression overflows"))
+            }
+            Expr::Binary { left, right, operator } => {
+                let (l, r) = (self.evaluate(left)?, self.evaluate(right)?);
+
+                if matches!(operator, TokenType::SLASH | TokenType::PERCENT) && r == 0 {
+                    return Err(error("division by zero in constant expression"));
+                }
+
+                match operator {
+                    TokenType::PLUS => l.checked_add(r),
+                    TokenType::MINUS => l.checked_sub(r),
+                    TokenType::ASTERISK => l.checked_mul(r),
+                    TokenType::SLASH => l.checked_div(r),
+                    TokenType::PERCENT => l.checked_rem(r),
+                    TokenType::AMPERSAND => Some(l & r),
+                    TokenType::PIPE => Some(l | r),
+                    TokenType::CARET => Some(l ^ r),
+                    TokenType::SHL => u32::try_from(r).ok().and_then(|r| l.checked_shl(r)),
+                    TokenType::SHR => u32::try_from(r).ok().and_then(|r| l.checked_shr(r)),
+                    TokenType::EQ => Some((l == r) as i32),
+                    TokenType::NEQ => Some((l != r) as i32),
+                    TokenType::LT => Some((l < r) as i32),
+                    TokenType::LTE => Some((l <= r) as i32),
+                    TokenType::GT => Some((l > r) as i32),
+                    TokenType::GTE => Some((l >= r) as i32),
+                    TokenType::AND => Some((l != 0 && r != 0) as i32),
+                    TokenType::OR => Some((l != 0 || r != 0) as i32),
+                    _ => None,
+                }
+                .ok_or_else(|| error("constant expression overflows"))
+            }
+            Expr::Index { .. }
+            | Expr::Call { .. }
+            | Expr::StructLiteral { .. }
+            | Expr::Field { .. }
+            | Expr::AddressOf { .. }
+            | Expr::Deref { .. } => {
+                Err(error("expected a constant expression"))
+            }
+        }
+    }
+
+    /// Declared width of the widest variable anywhere in `expr`, or 0 if there is none.
+    /// Looked up by name, so it also works before `expr` is resolved.
+    fn widest(&self, expr: &ExprNode) -> u32 {
+        match &expr.node {
+            Expr::Variable(name) => self
+                .table
+                .resolve(name)
+                .ok()
+                .and_then(|symbol| self.table.types.get(&symbol.id))
+                .map_or(0, |ty| ty.bits),
+            Expr::Binary { left, right, .. } => self.widest(left).max(self.widest(right)),
+            Expr::Unary { operand, .. } => self.widest(operand),
+            Expr::Index { index, .. } => self.widest(index),
+            Expr::Field { base, .. } => self.widest(base),
+            Expr::AddressOf { target } => self.widest(target),
+            Expr::Deref { pointer } => self.widest(pointer),
+            Expr::Call { args, .. } => args.iter().map(|arg| self.widest(arg)).max().unwrap_or(0),
+            Expr::Number(_) | Expr::Bool(_) | Expr::StructLiteral { .. } => 0,
+        }
+    }
+
+    /// Width of the value a resolved `expr` evaluates to. Comparisons and logic operators
+    /// produce a plain word.
+    fn width(&self, expr: &ExprNode) -> u32 {
+        let word = self.target.word_bits;
+
+        match &expr.node {
+            Expr::Variable(_) => self
+                .table
+                .types
+                .get(&self.table.id_at(expr.position, expr.source_id))
+                .map_or(word, |ty| ty.bits.max(word)),
+            Expr::Binary { left, right, operator } => match operator {
+                TokenType::SHL | TokenType::SHR => self.width(left),
+                TokenType::PLUS | TokenType::MINUS | TokenType::AMPERSAND | TokenType::PIPE | TokenType::CARET => {
+                    self.width(left).max(self.width(right))
+                }
+                _ => word,
+            },
+            Expr::Unary { operator: TokenType::MINUS | TokenType::TILDE, operand } => self.width(operand),
+            _ => word,
+        }
+    }
+
+    /// Rejects a literal value that the declared type of its variable can't hold.
+    fn check_fits(value: &ExprNode, ty: IntType) -> Result<(), CompileError> {
+        let literal = match &value.node {
+            Expr::Number(n) => *n,
+            Expr::Unary { operator: TokenType::MINUS, operand } => match operand.node {
+                Expr::Number(n) => -n,
+                _ => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+
+        if ty.contains(literal) {
+            return Ok(());
+        }
+
+        Err(CompileError {
+            message: format!("`{}` does not fit in `{}`, which holds {} to {}", literal, ty.name(), ty.min(), ty.max()),
+            position: value.position,
+            source_id: value.source_id,
+        })
+    }
+
+    /// Parameters and locals live in statically allocated frames, so a function
+    /// must never be re-entered while it is still running.
+    fn check_recursion(&self) -> Result<(), CompileError> {
+        for &(caller, callee, position, source_id) in &self.calls {
+            if self.reaches(callee, caller, &mut HashSet::new()) {
+                return Err(CompileError {
+                    message: "recursive calls are not supported, function frames are statically allocated".to_string(),
+                    position,
+                    source_id,
+                });
+            }
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn reaches(&self, from: u32, to: u32, visited: &mut HashSet<u32>) -> bool {
+        if from == to {
+            return true;
+        }
+
+        if !visited.insert(from) {
+            return false;
+        }
+
+        self.calls
+            .iter()
+            .filter(|&&(caller, ..)| caller == from)
+            .any(|&(_, callee, ..)| self.reaches(callee, to, visited))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{run, run_files};
+
+    #[test]
+    fn untyped_literals_must_fit_a_signed_word() {
+        let err = run("let x = -129;\n", "").err().unwrap();
+        assert_eq!(err, "`-129` does not fit in `i8`, which holds -128 to 127 at main.tch:1:9");
+
+        let run = run("port out @ 200;\nlet x = -128;\nout = x;\n", "").unwrap();
+        assert_eq!(run.port_writes, vec![-128]);
+    }
+
+    #[test]
+    fn parameters_are_scoped_to_their_function() {
+        let err = run("port out @ 200;\nfn f(a) { return a; }\nout = a;\n", "").err().unwrap();
+        assert_eq!(err, "use of undeclared variable `a` at main.tch:3:7");
+    }
+
+    #[test]
+    fn constants_become_immediates_and_size_arrays_and_ports() {
+        let source = "const WIDTH = 4;\nconst HEIGHT = WIDTH * 2 + 1;\nconst BASE = 190 + HEIGHT + 1;\nport out @ BASE;\n\
+            let grid[WIDTH * HEIGHT];\ngrid[WIDTH * HEIGHT - 1] = HEIGHT;\nout = grid[35];\nout = WIDTH - HEIGHT;\n";
+        let run = run(source, "").unwrap();
+        assert_eq!(run.port_writes, vec![9, -5]);
+        assert!(run.map.contains("RAM, 36 words"), "{}", run.map);
+        assert!(!format!("{:?}", run.legalized).contains("Var("), "{:?}", run.legalized);
+    }
+
+    #[test]
+    fn modules_are_shared_and_keep_their_items_private() {
+        let io = ("io.tch", "pub port output @ 200;\npub const LIMIT = 9;\nconst SECRET = 3;\n");
+        let util = ("util.tch", "import io;\npub fn double(x) { io.output = 1; return helper(x) * 2; }\nfn helper(x) { return x; }\n");
+        let main = ("main.tch", "import io;\nimport util;\nio.output = util.double(21);\nio.output = io.LIMIT;\n");
+        assert_eq!(run_files(&[main, io, util], "").unwrap().port_writes, vec![1, 42, 9]);
+
+        let err = run_files(&[("main.tch", "import io;\nio.output = io.SECRET;\n"), io], "").err().unwrap();
+        assert_eq!(err, "`SECRET` is private to module `io`, mark it `pub` to use it here at main.tch:2:13");
+        let err = run_files(&[("main.tch", "import util;\nlet x = util.helper(1);\n"), io, util], "").err().unwrap();
+        assert_eq!(err, "`helper` is private to module `util`, mark it `pub` to use it here at main.tch:2:9");
+        let err = run_files(&[("main.tch", "import io;\nlet x = io.nothing;\n"), io], "").err().unwrap();
+        assert_eq!(err, "module `io` has no item `nothing` at main.tch:2:9");
+    }
+
+    #[test]
+    fn inline_assembly_binds_registers_and_is_checked_against_the_target() {
+        let source = "port out @ 200;\nlet a = 3;\nlet b = 4;\nlet k1 = 10; let k2 = 20; let k3 = 30;\nlet c = 0;\n\
+            asm in(r1: a, r2: b) out(r0: c) clobber(r3) {\n    ldi r3, 1;\n    add r0, r1, r2;\n    add r0, r0, r3;\n}\n\
+            out = c;\nout = k1 + k2 + k3 + a + b;\n";
+        assert_eq!(run(source, "").unwrap().port_writes, vec![8, 67]);
+
+        let target = include_str!("../example.target");
+        let err = run("let a = 1;\nasm in(r1: a) {\n    mul r0, r1, r1;\n}\n", target).err().unwrap();
+        assert_eq!(err, "`mul` is not available on target `pipelined-8` at main.tch:3:5");
+        let err = run("let a = 1;\nasm in(r1: a) {\n    ldi r3, 1;\n}\n", target).err().unwrap();
+        assert_eq!(err, "`r3` is overwritten, list it in `out` or `clobber` at main.tch:3:5");
+        let err = run(source, target).err().unwrap();
+        assert_eq!(err, "`r0` is read 0 instructions after it is written, target `pipelined-8` needs 2 in between at main.tch:9:5");
+    }
+
+    #[test]
+    fn break_and_continue_need_an_enclosing_loop() {
+        let err = run("break;\n", "").err().unwrap();
+        assert_eq!(err, "`break` outside of a loop at main.tch:1:1");
+        let err = run("while true { continue nowhere; }\n", "").err().unwrap();
+        assert_eq!(err, "`continue` to unknown loop label `nowhere` at main.tch:1:23");
+    }
+}
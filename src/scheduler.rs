@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+
+use crate::{
+    instructions::{Instr, Label, Value},
+    target::Target,
+};
+
+/// Results still in flight: value -> number of instructions that have to issue before it may be read.
+type Hazards = HashMap<Value, u32>;
+
+struct Block {
+    label: Option<Label>,
+    body: Vec<Instr>,
+    terminator: Option<Instr>,
+}
+
+/// Reorders the allocated instructions of every basic block so that no result is read
+/// before the target's pipeline has written it back, and pads with `Nop` only where
+/// no independent instruction is left to fill the gap.
+///
+/// On targets with branch delay slots, instructions from before a branch that it does not
+/// depend on are moved behind it into its delay slots.
+pub struct Scheduler<'a> {
+    target: &'a Target,
+    pub nops: usize,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(target: &'a Target) -> Self {
+        Self { target, nops: 0 }
+    }
+
+    pub fn schedule(&mut self, instrs: &[Instr]) -> Vec<Instr> {
+        let blocks = Self::split_blocks(instrs);
+        let preds = Self::predecessors(&blocks);
+
+        // Hazards leaving a block depend on how its predecessors were scheduled, and loops
+        // make that circular, so iterate until the exit states no longer grow.
+        let mut exits = vec![Hazards::new(); blocks.len()];
+        let mut scheduled = Vec::new();
+
+        loop {
+            let mut changed = false;
+            scheduled.clear();
+
+            for (i, block) in blocks.iter().enumerate() {
+                let mut entry = Hazards::new();
+                for &pred in &preds[i] {
+                    Self::merge(&mut entry, &exits[pred]);
+                }
+
+                let (instrs, exit) = self.schedule_block(block, &entry);
+
+                let before = exits[i].clone();
+                Self::merge(&mut exits[i], &exit);
+                changed |= exits[i] != before;
+
+                scheduled.push(instrs);
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        let instrs: Vec<Instr> = scheduled.into_iter().flatten().collect();
+        self.nops = instrs.iter().filter(|i| matches!(i, Instr::Nop)).count();
+        instrs
+    }
+
+    fn split_blocks(instrs: &[Instr]) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut current = Block { label: None, body: Vec::new(), terminator: None };
+
+        for instr in instrs {
+            match instr {
+                Instr::Label(label) => {
+                    if current.label.is_some() || !current.body.is_empty() {
+                        blocks.push(current);
+                    }
+                    current = Block { label: Some(*label), body: Vec::new(), terminator: None };
+                }
+                instr if instr.is_terminator() => {
+                    current.terminator = Some(instr.clone());
+                    blocks.push(current);
+                    current = Block { label: None, body: Vec::new(), terminator: None };
+                }
+                instr => current.body.push(instr.clone()),
+            }
+        }
+
+        if current.label.is_some() || !current.body.is_empty() {
+            blocks.push(current);
+        }
+
+        blocks
+    }
+
+    fn predecessors(blocks: &[Block]) -> Vec<Vec<usize>> {
+        let labels: HashMap<Label, usize> = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| b.label.map(|l| (l, i)))
+            .collect();
+
+        let mut preds = vec![Vec::new(); blocks.len()];
+
+        let return_sites: Vec<usize> = blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| matches!(b.terminator, Some(Instr::Call(_))))
+            .map(|(i, _)| i + 1)
+            .filter(|&i| i < blocks.len())
+            .collect();
+
+        for (i, block) in blocks.iter().enumerate() {
+            match &block.terminator {
+                Some(Instr::Jump(target)) | Some(Instr::Call(target)) => {
+                    preds[labels[target]].push(i);
+                }
+                Some(Instr::JumpIfFalse { target, .. }) => {
+                    preds[labels[target]].push(i);
+                    if i + 1 < blocks.len() {
+                        preds[i + 1].push(i);
+                    }
+                }
+                Some(Instr::JumpTable { targets, .. }) => {
+                    for target in targets {
+                        preds[labels[target]].push(i);
+                    }
+                }
+                Some(Instr::Return) => {
+                    for &site in &return_sites {
+                        preds[site].push(i);
+                    }
+                }
+                Some(Instr::Halt | Instr::Trap) => {}
+                _ => {
+                    if i + 1 < blocks.len() {
+                        preds[i + 1].push(i);
+                    }
+                }
+            }
+        }
+
+        preds
+    }
+
+    fn merge(into: &mut Hazards, from: &Hazards) {
+        for (value, &remaining) in from {
+            let entry = into.entry(*value).or_insert(0);
+            *entry = (*entry).max(remaining);
+        }
+    }
+
+    fn schedule_block(&self, block: &Block, entry: &Hazards) -> (Vec<Instr>, Hazards) {
+        let body = &block.body;
+        let n = body.len();
+
+        // deps[j] holds (i, distance): `j` may issue no earlier than `distance` slots after `i`.
+        let mut deps: Vec<Vec<(usize, u32)>> = vec![Vec::new(); n];
+        for j in 0..n {
+            for i in 0..j {
+                if let Some(distance) = self.dependency(&body[i], &body[j]) {
+                    deps[j].push((i, distance));
+                }
+            }
+        }
+
+        // Longest path to the end of the block, so the critical chain is started first.
+        let mut priority = vec![0u32; n];
+        for j in (0..n).rev() {
+            for &(i, distance) in &deps[j] {
+                priority[i] = priority[i].max(priority[j] + distance);
+            }
+        }
+
+        let mut out = Vec::new();
+        if let Some(label) = block.label {
+            out.push(Instr::Label(label));
+        }
+
+        let mut slot_of: Vec<Option<u32>> = vec![None; n];
+        let mut written: Vec<(Value, u32)> = Vec::new();
+        let mut slot = 0u32;
+
+        let earliest_for_uses = |instr: &Instr, written: &[(Value, u32)]| -> u32 {
+            instr
+                .uses()
+                .iter()
+                .map(|value| {
+                    let in_block = written
+                        .iter()
+                        .filter(|(v, _)| v == value)
+                        .map(|&(_, ready)| ready)
+                        .max();
+                    in_block.unwrap_or_else(|| entry.get(value).copied().unwrap_or(0))
+                })
+                .max()
+                .unwrap_or(0)
+        };
+
+        let delay_slots = match &block.terminator {
+            Some(terminator) if terminator.is_branch() => self.target.delay_slots,
+            _ => 0,
+        };
+        let mut terminator_slot: Option<u32> = None;
+
+        while slot_of.iter().any(|s| s.is_none())
+            || block.terminator.is_some() && terminator_slot.is_none()
+        {
+            let remaining: Vec<usize> = (0..n).filter(|&j| slot_of[j].is_none()).collect();
+
+            // The branch is issued as soon as everything still unscheduled fits into its delay
+            // slots, so those slots are filled with work from before the branch instead of nops.
+            // Only instructions that are ready right after the branch qualify, as anything issued
+            // later than the last delay slot would no longer run on the taken path.
+            let fits_delay_slots = remaining.len() <= delay_slots as usize
+                && remaining.iter().all(|&j| {
+                    block.terminator.as_ref().is_none_or(|t| self.dependency(&body[j], t).is_none())
+                })
+                && remaining.iter().all(|&j| {
+                    deps[j].iter().all(|&(i, distance)| {
+                        slot_of[i].is_some_and(|s| s + distance <= slot + 1)
+                    }) && earliest_for_uses(&body[j], &written) <= slot + 1
+                });
+
+            if let Some(terminator) = &block.terminator
+                && terminator_slot.is_none()
+                && fits_delay_slots
+                && earliest_for_uses(terminator, &written) <= slot
+            {
+                terminator_slot = Some(slot);
+                out.push(terminator.clone());
+                slot += 1;
+                continue;
+            }
+
+            let ready = (0..n)
+                .filter(|&j| slot_of[j].is_none())
+                .filter(|&j| {
+                    deps[j].iter().all(|&(i, distance)| {
+                        slot_of[i].is_some_and(|s| s + distance <= slot)
+                    })
+                })
+                .filter(|&j| earliest_for_uses(&body[j], &written) <= slot)
+                .max_by_key(|&j| (priority[j], std::cmp::Reverse(j)));
+
+            match ready {
+                Some(j) => {
+                    slot_of[j] = Some(slot);
+                    for dst in body[j].defs() {
+                        written.push((dst, slot + self.target.latency_of(&body[j]) + 1));
+                    }
+                    out.push(body[j].clone());
+                }
+                None => out.push(Instr::Nop),
+            }
+
+            slot += 1;
+        }
+
+        if let Some(issued) = terminator_slot {
+            while slot < issued + 1 + delay_slots {
+                out.push(Instr::Nop);
+                slot += 1;
+            }
+        }
+
+        let mut exit = Hazards::new();
+        for (value, &remaining) in entry {
+            if remaining > slot && !written.iter().any(|(v, _)| v == value) {
+                exit.insert(*value, remaining - slot);
+            }
+        }
+        for &(value, ready) in &written {
+            if ready > slot {
+                exit.insert(value, ready - slot);
+            } else {
+                exit.remove(&value);
+            }
+        }
+
+        (out, exit)
+    }
+
+    /// Minimum issue distance between `first` and a later `second`, if they must stay ordered.
+    fn dependency(&self, first: &Instr, second: &Instr) -> Option<u32> {
+        let is_location = |value: &Value| !matches!(value, Value::Const(_) | Value::Ptr(_));
+
+        // Nothing moves across an asm block. It starts once every earlier register write has
+        // completed, as its body may overwrite any of those registers.
+        if let Instr::Asm { .. } = second
+            && !first.defs().is_empty()
+        {
+            return Some(self.target.latency_of(first) + 1);
+        }
+
+        if let Instr::Asm { .. } = first {
+            return match first.defs().iter().any(|dst| second.uses().contains(dst)) {
+                true => Some(self.target.latency_of(first) + 1),
+                false => Some(1),
+            };
+        }
+
+        if let Instr::Asm { .. } = second {
+            return Some(1);
+        }
+
+        if let Some(dst) = first.def() {
+            if second.uses().contains(&dst) {
+                return Some(self.target.latency_of(first) + 1);
+            }
+            if second.def() == Some(dst) {
+                return Some(1);
+            }
+        }
+
+        if let Some(dst) = second.def()
+            && is_location(&dst)
+            && first.uses().contains(&dst)
+        {
+            return Some(1);
+        }
+
+        // The carry flag is a hidden operand, so a carry chain stays in order.
+        if first.uses_carry() && second.uses_carry() {
+            return Some(1);
+        }
+
+        let is_store = |instr: &Instr| matches!(instr, Instr::Store { .. });
+        let ordered = is_store(first) || is_store(second) || first.is_volatile() || second.is_volatile();
+        if first.is_memory_access() && second.is_memory_access() && ordered {
+            return Some(1);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Scheduler;
+    use crate::{
+        instructions::{Instr, Label, Value},
+        layout::Layout,
+        simulator::Simulator,
+        target::Target,
+    };
+
+    fn target(result_latency: u32, load_latency: u32, delay_slots: u32) -> Target {
+        Target { result_latency, load_latency, delay_slots, ..Target::default() }
+    }
+
+    /// Schedules `instrs` and simulates the result, giving the schedule, the cycles it took
+    /// and the final registers.
+    fn schedule_and_run(target: &Target, instrs: &[Instr]) -> (Vec<Instr>, u64, HashMap<Value, i32>) {
+        let scheduled = Scheduler::new(target).schedule(instrs);
+        let layout = Layout::new(target.word_bits);
+        let mut simulator = Simulator::new(target, &layout, 1000);
+        simulator.run(&scheduled).unwrap();
+        (scheduled, simulator.cycles, simulator.registers)
+    }
+
+    fn nops(instrs: &[Instr]) -> usize {
+        instrs.iter().filter(|instr| matches!(instr, Instr::Nop)).count()
+    }
+
+    #[test]
+    fn reads_wait_out_the_result_latency() {
+        let (r0, r1) = (Value::Reg(0), Value::Reg(1));
+        let instrs = [
+            Instr::Immediate { dst: r0, value: Value::Const(5) },
+            Instr::Add { dst: r1, lhs: r0, rhs: r0 },
+            Instr::Halt,
+        ];
+
+        let (scheduled, cycles, registers) = schedule_and_run(&target(2, 0, 0), &instrs);
+        assert_eq!(nops(&scheduled), 2);
+        assert_eq!(cycles, 5);
+        assert_eq!(registers[&r1], 10);
+    }
+
+    #[test]
+    fn independent_instructions_fill_the_latency() {
+        let (r0, r1, r2, r3) = (Value::Reg(0), Value::Reg(1), Value::Reg(2), Value::Reg(3));
+        let instrs = [
+            Instr::Immediate { dst: r0, value: Value::Const(5) },
+            Instr::Add { dst: r1, lhs: r0, rhs: r0 },
+            Instr::Immediate { dst: r2, value: Value::Const(7) },
+            Instr::Immediate { dst: r3, value: Value::Const(9) },
+            Instr::Halt,
+        ];
+
+        let (scheduled, cycles, registers) = schedule_and_run(&target(2, 0, 0), &instrs);
+        assert_eq!(nops(&scheduled), 0);
+        assert!(matches!(scheduled[3], Instr::Add { .. }));
+        assert_eq!(cycles, 5);
+        assert_eq!((registers[&r1], registers[&r2], registers[&r3]), (10, 7, 9));
+    }
+
+    #[test]
+    fn loaded_values_wait_out_the_load_latency() {
+        let (r0, r1, r2) = (Value::Reg(0), Value::Reg(1), Value::Reg(2));
+        let instrs = [
+            Instr::Immediate { dst: r0, value: Value::Const(42) },
+            Instr::Store { dst: Value::Const(10), src: r0 },
+            Instr::Load { dst: r1, src: Value::Const(10) },
+            Instr::Add { dst: r2, lhs: r1, rhs: r1 },
+            Instr::Halt,
+        ];
+
+        // Two nops before the store can read the immediate, three before the add can read the load.
+        let (scheduled, cycles, registers) = schedule_and_run(&target(2, 3, 0), &instrs);
+        assert_eq!(nops(&scheduled), 5);
+        assert_eq!(cycles, 10);
+        assert_eq!(registers[&r2], 84);
+    }
+
+    #[test]
+    fn delay_slots_are_filled_with_earlier_work() {
+        let (r0, r1) = (Value::Reg(0), Value::Reg(1));
+        let instrs = [
+            Instr::Immediate { dst: r0, value: Value::Const(1) },
+            Instr::Immediate { dst: r1, value: Value::Const(2) },
+            Instr::Jump(Label(0)),
+            Instr::Label(Label(0)),
+            Instr::Halt,
+        ];
+
+        let (scheduled, cycles, registers) = schedule_and_run(&target(0, 0, 1), &instrs);
+        assert_eq!(nops(&scheduled), 0);
+        assert!(matches!(scheduled[1], Instr::Jump(_)));
+        assert_eq!(cycles, 4);
+        assert_eq!((registers[&r0], registers[&r1]), (1, 2));
+    }
+
+    #[test]
+    fn delay_slots_the_branch_depends_on_get_nops() {
+        let r0 = Value::Reg(0);
+        let instrs = [
+            Instr::Immediate { dst: r0, value: Value::Const(0) },
+            Instr::JumpIfFalse { cond: r0, target: Label(0) },
+            Instr::Immediate { dst: r0, value: Value::Const(3) },
+            Instr::Label(Label(0)),
+            Instr::Halt,
+        ];
+
+        // The branch waits for its condition and nothing is left to put in its delay slot.
+        let (scheduled, cycles, registers) = schedule_and_run(&target(1, 0, 1), &instrs);
+        assert_eq!(nops(&scheduled), 2);
+        assert_eq!(cycles, 5);
+        assert_eq!(registers[&r0], 0);
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use crate::{
+    instructions::{Instr, Label, Value},
+    layout::Layout,
+    target::Target,
+};
+
+/// Executes scheduled instructions the way the target's pipeline would.
+///
+/// Register writes only become visible `latency` instructions after they issue, so a
+/// schedule that ignores a hazard reads the stale value just like the hardware does.
+/// Taken branches run the following `delay_slots` instructions before transferring control.
+pub struct Simulator<'a> {
+    target: &'a Target,
+    layout: &'a Layout,
+    pub registers: HashMap<Value, i32>,
+    /// RAM holding the arrays and the memory homes of spilled values.
+    pub ram: HashMap<i32, i32>,
+    /// Every value written to an IO port as `(address, value)`, in program order.
+    pub port_writes: Vec<(i32, i32)>,
+    pending: Vec<(Value, i32, u64)>,
+    /// The carry flag, which unlike registers is updated without latency.
+    carry: bool,
+    pub cycles: u64,
+    max_cycles: u64,
+}
+
+pub type SimResult<T> = Result<T, String>;
+
+#[derive(Clone, Copy)]
+enum Transfer {
+    Jump(Label),
+    Call(Label),
+    Return,
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(target: &'a Target, layout: &'a Layout, max_cycles: u64) -> Self {
+        Self {
+            target,
+            layout,
+            registers: HashMap::new(),
+            ram: HashMap::new(),
+            port_writes: Vec::new(),
+            pending: Vec::new(),
+            carry: false,
+            cycles: 0,
+            max_cycles,
+        }
+    }
+
+    pub fn run(&mut self, instrs: &[Instr]) -> SimResult<()> {
+        let labels: HashMap<Label, usize> = instrs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instr)| match instr {
+                Instr::Label(label) => Some((*label, i)),
+                _ => None,
+            })
+            .collect();
+
+        let mut pc = 0;
+        // Target of a taken branch and the number of delay slot instructions still to run.
+        let mut branch: Option<(Transfer, u32)> = None;
+        let mut call_stack: Vec<usize> = Vec::new();
+
+        while pc < instrs.len() {
+            let instr = &instrs[pc];
+            pc += 1;
+
+            if let Instr::Label(_) = instr {
+                continue;
+            }
+
+            if self.cycles >= self.max_cycles {
+                return Err(format!("exceeded {} cycles", self.max_cycles));
+            }
+
+            self.retire();
+
+            if branch.is_some() && instr.is_terminator() {
+                return Err(format!("branch in delay slot: {:?}", instr));
+            }
+
+            if let Instr::Halt = instr {
+                self.cycles += 1;
+                break;
+            }
+
+            if let Instr::Trap = instr {
+                self.cycles += 1;
+                return Err("trapped on a failed runtime check".to_string());
+            }
+
+            let taken = self.execute(instr)?;
+
+            if let Some(transfer) = taken {
+                branch = Some((transfer, self.target.delay_slots));
+            } else if let Some((_, remaining)) = &mut branch {
+                *remaining -= 1;
+            }
+
+            self.cycles += 1;
+
+            if let Some((transfer, 0)) = branch {
+                // The return address lies behind the delay slots, which have already run.
+                pc = match transfer {
+                    Transfer::Jump(target) => labels[&target],
+                    Transfer::Call(target) => {
+                        call_stack.push(pc);
+                        labels[&target]
+                    }
+                    Transfer::Return => call_stack.pop().ok_or("return with an empty call stack")?,
+                };
+                branch = None;
+            }
+        }
+
+        self.pending.sort_by_key(|&(_, _, visible)| visible);
+        for (dst, value, _) in self.pending.drain(..) {
+            self.registers.insert(dst, value);
+        }
+
+        Ok(())
+    }
+
+    /// Commits every write whose result has made it through the pipeline by now.
+    fn retire(&mut self) {
+        let cycles = self.cycles;
+        let mut ready: Vec<_> = self.pending.iter().copied().filter(|&(_, _, visible)| visible <= cycles).collect();
+        self.pending.retain(|&(_, _, visible)| visible > cycles);
+
+        ready.sort_by_key(|&(_, _, visible)| visible);
+        for (dst, value, _) in ready {
+            self.registers.insert(dst, value);
+        }
+    }
+
+    fn read(&self, value: &Value) -> i32 {
+        match value {
+            Value::Const(c) => self.target.signed(*c),
+            Value::Ptr(id) => self.target.signed(self.layout.address_of(*id)),
+            _ => self.registers.get(value).copied().unwrap_or(0),
+        }
+    }
+
+    fn address(&self, value: &Value) -> i32 {
+        self.target.unsigned(self.read(value)) as i32
+    }
+
+    /// Address a load or store goes to. A variable or temporary there names its memory home.
+    fn memory_address(&self, value: &Value) -> i32 {
+        match value {
+            Value::Var(_) | Value::Temp(_) => self.layout.home_of(value),
+            _ => self.address(value),
+        }
+    }
+
+    /// Queues a register write. Results wrap around to the machine word like in hardware.
+    fn write(&mut self, instr: &Instr, dst: Value, value: i32) {
+        let value = self.target.signed(value);
+        let visible = self.cycles + self.target.latency_of(instr) as u64 + 1;
+        self.pending.push((dst, value, visible));
+    }
+
+    /// Executes a single instruction and returns the control transfer it starts, if any.
+    fn execute(&mut self, instr: &Instr) -> SimResult<Option<Transfer>> {
+        match instr {
+            Instr::Immediate { dst, value } | Instr::Move { dst, src: value } => {
+                let value = self.read(value);
+                self.write(instr, *dst, value);
+            }
+            Instr::Load { dst, src } => {
+                let value = self.ram.get(&self.memory_address(src)).copied().unwrap_or(0);
+                self.write(instr, *dst, value);
+            }
+            Instr::LoadRom { dst, src } => {
+                let value = self.layout.rom.get(self.address(src) as usize).copied().unwrap_or(0);
+                self.write(instr, *dst, value);
+            }
+            Instr::Store { dst, src } => {
+                let value = self.read(src);
+                if instr.is_volatile() {
+                    self.port_writes.push((self.address(dst), value));
+                }
+
+                self.ram.insert(self.memory_address(dst), value);
+            }
+            Instr::Add { dst, lhs, rhs } => {
+                let value = self.read(lhs).wrapping_add(self.read(rhs));
+                self.write(instr, *dst, value);
+            }
+            Instr::Sub { dst, lhs, rhs } => {
+                let value = self.read(lhs).wrapping_sub(self.read(rhs));
+                self.write(instr, *dst, value);
+            }
+            Instr::AddCarry { dst, lhs, rhs } => {
+                let (l, r) = (self.target.unsigned(self.read(lhs)), self.target.unsigned(self.read(rhs)));
+                let sum = l as u64 + r as u64 + self.carry as u64;
+                self.carry = sum >> self.target.word_bits != 0;
+                self.write(instr, *dst, sum as i32);
+            }
+            Instr::SubBorrow { dst, lhs, rhs } => {
+                let (l, r) = (self.target.unsigned(self.read(lhs)), self.target.unsigned(self.read(rhs)));
+                let difference = l as i64 - r as i64 - self.carry as i64;
+                self.carry = difference < 0;
+                self.write(instr, *dst, difference as i32);
+            }
+            Instr::ClearCarry => self.carry = false,
+            Instr::AddImmediate { dst, lhs, imm } => {
+                let value = self.read(lhs).wrapping_add(*imm);
+                self.write(instr, *dst, value);
+            }
+            Instr::Mul { dst, lhs, rhs } => {
+                let value = self.read(lhs).wrapping_mul(self.read(rhs));
+                self.write(instr, *dst, value);
+            }
+            Instr::Div { dst, lhs, rhs }
+            | Instr::Mod { dst, lhs, rhs }
+            | Instr::DivU { dst, lhs, rhs }
+            | Instr::ModU { dst, lhs, rhs } => {
+                let (lhs, rhs) = (self.read(lhs), self.read(rhs));
+                if self.target.unsigned(rhs) == 0 {
+                    return Err("division by zero".to_string());
+                }
+
+                let (l, r) = (self.target.unsigned(lhs), self.target.unsigned(rhs));
+                let value = match instr {
+                    Instr::Div { .. } => lhs.wrapping_div(rhs),
+                    Instr::Mod { .. } => lhs.wrapping_rem(rhs),
+                    Instr::DivU { .. } => (l / r) as i32,
+                    _ => (l % r) as i32,
+                };
+                self.write(instr, *dst, value);
+            }
+            Instr::And { dst, lhs, rhs } => {
+                let value = self.read(lhs) & self.read(rhs);
+                self.write(instr, *dst, value);
+            }
+            Instr::Or { dst, lhs, rhs } => {
+                let value = self.read(lhs) | self.read(rhs);
+                self.write(instr, *dst, value);
+            }
+            Instr::Xor { dst, lhs, rhs } => {
+                let value = self.read(lhs) ^ self.read(rhs);
+                self.write(instr, *dst, value);
+            }
+            Instr::Not { dst, src } => {
+                let value = !self.read(src);
+                self.write(instr, *dst, value);
+            }
+            Instr::Shl { dst, lhs, rhs } => {
+                let value = self.target.shl(self.read(lhs), self.read(rhs));
+                self.write(instr, *dst, value);
+            }
+            Instr::Shr { dst, lhs, rhs } => {
+                let value = self.target.shr(self.read(lhs), self.read(rhs));
+                self.write(instr, *dst, value);
+            }
+            Instr::ShrU { dst, lhs, rhs } => {
+                let value = self.target.shr_u(self.read(lhs), self.read(rhs));
+                self.write(instr, *dst, value);
+            }
+            Instr::Cmp { cond, dst, lhs, rhs } => {
+                let value = cond.holds(self.read(lhs), self.read(rhs), self.target.word_bits) as i32;
+                self.write(instr, *dst, value);
+            }
+            Instr::Jump(target) => return Ok(Some(Transfer::Jump(*target))),
+            Instr::JumpIfFalse { cond, target } => {
+                if self.read(cond) == 0 {
+                    return Ok(Some(Transfer::Jump(*target)));
+                }
+            }
+            Instr::JumpTable { index, targets } => {
+                let target = targets
+                    .get(self.target.unsigned(self.read(index)) as usize)
+                    .ok_or("jump table index out of range")?;
+                return Ok(Some(Transfer::Jump(*target)));
+            }
+            Instr::Call(target) => return Ok(Some(Transfer::Call(*target))),
+            Instr::Return => return Ok(Some(Transfer::Return)),
+            // The body runs one instruction per cycle, the last cycle is counted by `run`.
+            Instr::Asm { body, .. } => {
+                for (i, instr) in body.iter().enumerate() {
+                    if i > 0 {
+                        self.cycles += 1;
+                        self.retire();
+                    }
+
+                    self.execute(instr)?;
+                }
+            }
+            Instr::Label(_) | Instr::Nop | Instr::Halt | Instr::Trap => {}
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Simulator;
+    use crate::{
+        instructions::{Instr, Label, Value},
+        layout::Layout,
+        target::Target,
+        testing::run_files,
+    };
+
+    #[test]
+    fn reads_inside_the_result_latency_see_the_old_value() {
+        let (r0, r1) = (Value::Reg(0), Value::Reg(1));
+        let instrs = [
+            Instr::Immediate { dst: r0, value: Value::Const(5) },
+            Instr::Add { dst: r1, lhs: r0, rhs: r0 },
+            Instr::Halt,
+        ];
+        let layout = Layout::new(8);
+
+        for (result_latency, sum) in [(0, 10), (2, 0)] {
+            let target = Target { result_latency, ..Target::default() };
+            let mut simulator = Simulator::new(&target, &layout, 100);
+            simulator.run(&instrs).unwrap();
+            assert_eq!(simulator.registers[&r1], sum);
+            assert_eq!(simulator.cycles, 3);
+        }
+    }
+
+    #[test]
+    fn delay_slots_run_before_the_branch_is_taken() {
+        let (r0, r1) = (Value::Reg(0), Value::Reg(1));
+        let instrs = [
+            Instr::Jump(Label(0)),
+            Instr::Immediate { dst: r0, value: Value::Const(1) },
+            Instr::Immediate { dst: r1, value: Value::Const(2) },
+            Instr::Label(Label(0)),
+            Instr::Halt,
+        ];
+        let target = Target { delay_slots: 1, ..Target::default() };
+        let layout = Layout::new(8);
+
+        let mut simulator = Simulator::new(&target, &layout, 100);
+        simulator.run(&instrs).unwrap();
+        assert_eq!(simulator.registers.get(&r0), Some(&1));
+        assert_eq!(simulator.registers.get(&r1), None);
+        assert_eq!(simulator.cycles, 3);
+    }
+
+    #[test]
+    fn scheduled_programs_compute_the_same_on_pipelined_targets() {
+        let files = [("main.tch", include_str!("../example-fibonacci.tch")), ("io.tch", include_str!("../io.tch"))];
+        let fibonacci = vec![1, 1, 2, 3, 5, 8, 13, 21, 34, 55];
+
+        let plain = run_files(&files, "").unwrap();
+        assert_eq!(plain.port_writes, fibonacci);
+        assert_eq!(plain.nops, 0);
+
+        let pipelined = run_files(&files, include_str!("../example.target")).unwrap();
+        assert_eq!(pipelined.port_writes, fibonacci);
+        assert!(pipelined.nops > 0);
+        assert!(pipelined.cycles > plain.cycles);
+    }
+}
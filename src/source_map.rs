@@ -17,4 +17,12 @@ impl SourceMap {
         let source = Source::new(content, file_name.clone());
         Ok(self.add(source))
     }
+
+    /// Reads a file that need not be text, like an embedded image. It is added like a source
+    /// file, so errors in it can be reported, and its bytes are returned unchanged.
+    pub fn add_binary_file(&mut self, file_name: &String) -> std::io::Result<(usize, Vec<u8>)> {
+        let bytes = std::fs::read(file_name)?;
+        let source = Source::new(String::from_utf8_lossy(&bytes).into_owned(), file_name.clone());
+        Ok((self.add(source), bytes))
+    }
 }
\ No newline at end of file
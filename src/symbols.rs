@@ -1,7 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::types::IntType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Variable,
+    /// A `const`, whose uses are replaced by its value.
+    Constant { value: i32 },
+    /// A memory-mapped IO port, read and written with volatile loads and stores.
+    Port { address: i32 },
+    /// An array of `length` elements, which are structs if `element` is the index of one.
+    Array { length: u32, element: Option<usize> },
+    Function { arity: usize },
+    /// A `struct` declaration, by index into `SymbolTable::structs`.
+    Struct { index: usize },
+    /// A variable holding a struct, whose fields are variables of their own named `name.field`.
+    Record { index: usize },
+    /// An `enum` declaration, by index into `SymbolTable::enums`. Its variants are constants
+    /// named `Name.Variant`.
+    Enum { index: usize },
+}
+
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+pub struct EnumDef {
+    pub name: String,
+    /// Name and value of every variant, in declaration order.
+    pub variants: Vec<(String, i32)>,
+}
 
 pub struct Symbol {
     pub name: String,
+    pub kind: SymbolKind,
     pub position: usize,
     pub source_id: usize,
     pub id: u32,
@@ -21,10 +54,39 @@ impl Scope {
     }
 }
 
+/// What a module shares with the modules importing it.
+pub struct ModuleScope {
+    pub name: String,
+    /// Scope holding the module's top level items.
+    pub scope: usize,
+    pub exports: HashSet<String>,
+    /// Modules reachable as `name.item` from this one, by index into `SymbolTable::modules`.
+    pub imports: HashMap<String, usize>,
+}
+
 pub struct SymbolTable {
     pub scopes: Vec<Scope>,
     pub current: usize,
+    pub modules: Vec<ModuleScope>,
+    /// Index of the module being resolved.
+    pub module: usize,
     pub next_id: u32,
+    /// Symbol id each name occurrence refers to, keyed by (source_id, position).
+    pub resolved: HashMap<(usize, usize), u32>,
+    /// Declared type of every annotated variable, keyed by symbol id.
+    pub types: HashMap<u32, IntType>,
+    pub structs: Vec<StructDef>,
+    /// Ids of the field variables of every struct variable, in declaration order.
+    pub records: HashMap<u32, Vec<u32>>,
+    pub enums: Vec<EnumDef>,
+    /// Enum of every variant constant, keyed by the variant's symbol id.
+    pub variants: HashMap<u32, usize>,
+    /// Value of every `match` pattern truncated to the machine word, keyed by (source_id, position).
+    pub patterns: HashMap<(usize, usize), i32>,
+    /// Variables whose address is taken, which live in RAM rather than in registers.
+    pub addressed: HashSet<u32>,
+    /// Contents of every constant array, keyed by the array's symbol id.
+    pub tables: HashMap<u32, Vec<i32>>,
 }
 
 impl SymbolTable {
@@ -37,7 +99,18 @@ impl SymbolTable {
         SymbolTable {
             scopes: vec![root],
             current: 0,
+            modules: Vec::new(),
+            module: 0,
             next_id: 0,
+            resolved: HashMap::new(),
+            types: HashMap::new(),
+            structs: Vec::new(),
+            records: HashMap::new(),
+            enums: Vec::new(),
+            variants: HashMap::new(),
+            patterns: HashMap::new(),
+            addressed: HashSet::new(),
+            tables: HashMap::new(),
         }
     }
 
@@ -47,6 +120,26 @@ impl SymbolTable {
         id
     }
 
+    /// Starts a module, whose top level scope has no parent. The first module uses the root scope.
+    pub fn begin_module(&mut self, name: &str, exports: HashSet<String>, imports: HashMap<String, usize>) {
+        let scope = match self.modules.is_empty() {
+            true => 0,
+            false => {
+                self.scopes.push(Scope::new(None));
+                self.scopes.len() - 1
+            }
+        };
+
+        self.modules.push(ModuleScope { name: name.to_string(), scope, exports, imports });
+        self.module = self.modules.len() - 1;
+        self.current = scope;
+    }
+
+    /// Whether the current scope is the top level of a module.
+    pub fn at_top_level(&self) -> bool {
+        self.scopes[self.current].parent.is_none()
+    }
+
     pub fn begin_scope(&mut self) {
         let parent = Some(self.current);
         let idx = self.scopes.len();
@@ -58,54 +151,159 @@ impl SymbolTable {
 
     pub fn end_scope(&mut self) {
         let parent = self.scopes[self.current].parent;
-        self.current = parent.unwrap_or(0);
+        self.current = parent.unwrap_or(self.modules.get(self.module).map_or(0, |module| module.scope));
     }
 
     pub fn resolve(&self, name: &str) -> Result<&Symbol, String> {
+        if let Some((base, item)) = name.split_once('.') {
+            // `p.x` is a field if `p` is a struct variable, `E.A` a variant if `E` is an enum,
+            // and both are module items otherwise.
+            return match self.lookup(base) {
+                Some((scope, Symbol { kind: SymbolKind::Record { index }, .. })) => {
+                    self.scopes[scope].symbols.get(name).ok_or_else(|| {
+                        format!("struct `{}` has no field `{}`", self.structs[*index].name, item)
+                    })
+                }
+                Some((scope, Symbol { kind: SymbolKind::Enum { index }, .. })) => {
+                    self.scopes[scope].symbols.get(name).ok_or_else(|| {
+                        format!("enum `{}` has no variant `{}`", self.enums[*index].name, item)
+                    })
+                }
+                _ => self.resolve_in_module(base, item),
+            };
+        }
+
+        self.lookup(name)
+            .map(|(_, sym)| sym)
+            .ok_or_else(|| format!("use of undeclared variable `{}`", name))
+    }
+
+    /// Finds `name` in the current scope or the closest enclosing one, along with that scope.
+    fn lookup(&self, name: &str) -> Option<(usize, &Symbol)> {
         let mut idx = self.current;
 
         loop {
             if let Some(sym) = self.scopes[idx].symbols.get(name) {
-                return Ok(sym);
+                return Some((idx, sym));
             }
 
-            match self.scopes[idx].parent {
-                Some(p) => idx = p,
-                None => break,
-            }
+            idx = self.scopes[idx].parent?;
         }
-
-        Err(format!("use of undeclared variable `{}`", name))
     }
 
-    pub fn id_of(&self, name: &str) -> u32 {
-        for scope in self.scopes.iter().rev() {
-            if let Some(sym) = scope.symbols.get(name) {
-                return sym.id;
-            }
+    /// Resolves `module.item` through the imports of the current module.
+    fn resolve_in_module(&self, module: &str, item: &str) -> Result<&Symbol, String> {
+        let imported = self
+            .modules
+            .get(self.module)
+            .and_then(|current| current.imports.get(module))
+            .map(|&index| &self.modules[index])
+            .ok_or_else(|| format!("unknown module `{}`, add `import {};`", module, module))?;
+
+        let symbol = self.scopes[imported.scope]
+            .symbols
+            .get(item)
+            .ok_or_else(|| format!("module `{}` has no item `{}`", module, item))?;
+
+        // Fields and variants are as visible as the item they belong to.
+        let exported = item.split('.').next().unwrap_or(item);
+        if !imported.exports.contains(exported) {
+            return Err(format!("`{}` is private to module `{}`, mark it `pub` to use it here", item, module));
         }
-        
-        panic!("Could not resolve symbol");
+
+        Ok(symbol)
+    }
+
+    /// Id of the symbol the name at `pos` was resolved to.
+    pub fn id_at(&self, pos: usize, source_id: usize) -> u32 {
+        *self
+            .resolved
+            .get(&(source_id, pos))
+            .expect("Could not resolve symbol")
+    }
+
+    fn symbol(&self, id: u32) -> &Symbol {
+        self.get(id).expect("Unknown symbol id")
+    }
+
+    /// The symbol with this id, if it is one. Later passes create variables of their own.
+    pub fn get(&self, id: u32) -> Option<&Symbol> {
+        self.scopes
+            .iter()
+            .flat_map(|scope| scope.symbols.values())
+            .find(|symbol| symbol.id == id)
+    }
+
+    pub fn kind_of(&self, id: u32) -> SymbolKind {
+        self.symbol(id).kind
+    }
+
+    pub fn name_of(&self, id: u32) -> &str {
+        &self.symbol(id).name
+    }
+
+    /// Position and source id of the symbol's declaration.
+    pub fn declared_at(&self, id: u32) -> (usize, usize) {
+        let symbol = self.symbol(id);
+        (symbol.position, symbol.source_id)
     }
 
     pub fn define(&mut self, name: &str, pos: usize, source_id: usize) -> Result<(), String> {
+        self.define_kind(name, SymbolKind::Variable, pos, source_id)
+    }
+
+    pub fn define_kind(&mut self, name: &str, kind: SymbolKind, pos: usize, source_id: usize) -> Result<(), String> {
         let next_id = self.next_id();
         let scope = &mut self.scopes[self.current];
 
         if scope.symbols.contains_key(name) {
-            Err(format!("variable `{}` already declared in this scope", name))
+            match kind {
+                SymbolKind::Variable | SymbolKind::Array { .. } | SymbolKind::Record { .. } => {
+                    Err(format!("variable `{}` already declared in this scope", name))
+                }
+                SymbolKind::Constant { .. } => Err(format!("constant `{}` already declared in this scope", name)),
+                SymbolKind::Port { .. } => Err(format!("port `{}` already declared in this scope", name)),
+                SymbolKind::Function { .. } => Err(format!("function `{}` already declared", name)),
+                SymbolKind::Struct { .. } => Err(format!("struct `{}` already declared", name)),
+                SymbolKind::Enum { .. } => Err(format!("enum `{}` already declared", name)),
+            }
         } else {
             scope.symbols.insert(
                 name.to_string(),
                 Symbol {
                     name: name.to_string(),
+                    kind,
                     position: pos,
                     source_id: source_id,
                     id: next_id,
                 },
             );
+            self.resolved.insert((source_id, pos), next_id);
 
             Ok(())
         }
     }
-}
\ No newline at end of file
+
+    /// Defines a variable holding the struct `index`, along with a variable for each of its fields.
+    pub fn define_record(&mut self, name: &str, index: usize, pos: usize, source_id: usize) -> Result<(), String> {
+        self.define_kind(name, SymbolKind::Record { index }, pos, source_id)?;
+
+        let id = self.resolved[&(source_id, pos)];
+        let mut fields = Vec::new();
+
+        for field in self.structs[index].fields.clone() {
+            let field_id = self.next_id();
+            let name = format!("{}.{}", name, field);
+
+            self.scopes[self.current].symbols.insert(
+                name.clone(),
+                Symbol { name, kind: SymbolKind::Variable, position: pos, source_id, id: field_id },
+            );
+            fields.push(field_id);
+        }
+
+        self.records.insert(id, fields);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{errors::CompileError, instructions::{Cond, Instr}, source_map::SourceMap};
+
+const LOGIC_OPS: [&str; 4] = ["and", "or", "xor", "not"];
+
+/// Shift instructions a target provides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shifts {
+    /// No shifts at all. Left shifts become additions, right shifts a runtime routine.
+    None,
+    /// Shifts by exactly one bit.
+    Single,
+    /// Shifts by any amount.
+    Any,
+}
+
+/// Describes the CPU the compiler emits code for.
+///
+/// A target description is a plain text file of `key = value` lines, `#` starts a comment:
+///
+/// ```text
+/// name = pipelined-8
+/// word_bits = 8
+/// compares = gt, eq, gtu
+/// multiply = false
+/// logic = and, or, not
+/// shifts = single
+/// carry = true
+/// indirect_jumps = false
+/// rom_loads = false
+/// result_latency = 2
+/// load_latency = 3
+/// delay_slots = 1
+/// ```
+pub struct Target {
+    pub name: String,
+    pub word_bits: u32,
+    /// Comparisons the CPU implements, every other one is synthesised by the legalizer.
+    pub compares: Vec<Cond>,
+    /// Whether `Mul` exists in hardware, otherwise it becomes a shift-add routine.
+    pub multiply: bool,
+    /// Whether `Div` and `Mod` exist in hardware, otherwise they become a shift-subtract routine.
+    pub divide: bool,
+    /// Bitwise operations the CPU implements out of `and`, `or`, `xor` and `not`.
+    pub logic: Vec<String>,
+    pub shifts: Shifts,
+    /// Whether the CPU has a carry flag with add-with-carry and subtract-with-borrow,
+    /// otherwise multi-word arithmetic computes the carry with compares.
+    pub carry: bool,
+    /// Whether the CPU can jump to an address taken from a register, which jump tables need.
+    pub indirect_jumps: bool,
+    /// Whether the CPU can load words from program memory. Constant tables stay there if it
+    /// can, otherwise startup code copies them to RAM.
+    pub rom_loads: bool,
+
+    /// Number of instructions that must separate a register write from the first read of it.
+    pub result_latency: u32,
+    /// Like `result_latency`, but for the destination of a `Load`.
+    pub load_latency: u32,
+    /// Number of instructions after a `Jump` or `JumpIfFalse` that execute before the branch takes effect.
+    pub delay_slots: u32,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target {
+            name: "default".to_string(),
+            word_bits: 8,
+            compares: Cond::ALL.to_vec(),
+            multiply: false,
+            divide: false,
+            logic: LOGIC_OPS.iter().map(|op| op.to_string()).collect(),
+            shifts: Shifts::Any,
+            carry: false,
+            indirect_jumps: true,
+            rom_loads: true,
+            result_latency: 0,
+            load_latency: 0,
+            delay_slots: 0,
+        }
+    }
+}
+
+impl Target {
+    pub fn from_source(source_map: &SourceMap, source_id: usize) -> Result<Self, CompileError> {
+        let mut target = Target::default();
+        let source = &source_map.files[source_id];
+
+        for (line, start) in source.content.lines().zip(source.line_starts.iter()) {
+            let content = line.split('#').next().unwrap().trim();
+            if content.is_empty() {
+                continue;
+            }
+
+            let error = |message: String| CompileError {
+                message,
+                position: *start,
+                source_id,
+            };
+
+            let (key, value) = content
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected `key = value`, found `{}`", content)))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| error(format!("expected a number for `{}`, found `{}`", key, value)))
+            };
+
+            let flag = || match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(error(format!("expected `true` or `false` for `{}`, found `{}`", key, value))),
+            };
+
+            match key {
+                "name" => target.name = value.to_string(),
+                "word_bits" => match number()? {
+                    bits @ 1..=32 => target.word_bits = bits,
+                    bits => return Err(error(format!("word size must be between 1 and 32 bits, found {}", bits))),
+                },
+                "compares" => {
+                    target.compares = value
+                        .split(',')
+                        .map(|name| {
+                            let name = name.trim();
+                            Cond::ALL
+                                .into_iter()
+                                .find(|cond| cond.name() == name)
+                                .ok_or_else(|| error(format!("unknown comparison `{}`", name)))
+                        })
+                        .collect::<Result<_, _>>()?;
+
+                    // Any one ordering can be rewritten into all others, equality included.
+                    if !target.compares.iter().any(|cond| !matches!(cond, Cond::Eq | Cond::Ne)) {
+                        return Err(error("`compares` needs at least one ordering comparison".to_string()));
+                    }
+                }
+                "multiply" => target.multiply = flag()?,
+                "divide" => target.divide = flag()?,
+                "logic" => {
+                    target.logic = value
+                        .split(',')
+                        .map(|op| {
+                            let op = op.trim();
+                            match LOGIC_OPS.contains(&op) {
+                                true => Ok(op.to_string()),
+                                false => Err(error(format!("unknown logic operation `{}`", op))),
+                            }
+                        })
+                        .collect::<Result<_, _>>()?;
+                }
+                "shifts" => {
+                    target.shifts = match value {
+                        "none" => Shifts::None,
+                        "single" => Shifts::Single,
+                        "any" => Shifts::Any,
+                        _ => return Err(error(format!("expected `none`, `single` or `any` for `shifts`, found `{}`", value))),
+                    }
+                }
+                "carry" => target.carry = flag()?,
+                "indirect_jumps" => target.indirect_jumps = flag()?,
+                "rom_loads" => target.rom_loads = flag()?,
+                "result_latency" => target.result_latency = number()?,
+                "load_latency" => target.load_latency = number()?,
+                "delay_slots" => target.delay_slots = number()?,
+                _ => return Err(error(format!("unknown target property `{}`", key))),
+            }
+        }
+
+        Ok(target)
+    }
+
+    pub fn supports(&self, cond: Cond) -> bool {
+        self.compares.contains(&cond)
+    }
+
+    pub fn has_logic(&self, op: &str) -> bool {
+        self.logic.iter().any(|o| o == op)
+    }
+
+    /// `value` truncated to the machine word and read as signed.
+    pub fn signed(&self, value: i32) -> i32 {
+        let shift = 32 - self.word_bits;
+        (value << shift) >> shift
+    }
+
+    /// `value` truncated to the machine word and read as unsigned.
+    pub fn unsigned(&self, value: i32) -> u32 {
+        let shift = 32 - self.word_bits;
+        (value as u32) << shift >> shift
+    }
+
+    /// Shift amounts are read as unsigned words, and shifting by a word or more moves every
+    /// bit out.
+    pub fn shl(&self, value: i32, amount: i32) -> i32 {
+        match self.unsigned(amount) {
+            0 => value,
+            n if n >= self.word_bits => 0,
+            n => value << n,
+        }
+    }
+
+    pub fn shr(&self, value: i32, amount: i32) -> i32 {
+        match self.unsigned(amount) {
+            0 => value,
+            n => self.signed(value) >> n.min(self.word_bits - 1),
+        }
+    }
+
+    pub fn shr_u(&self, value: i32, amount: i32) -> i32 {
+        match self.unsigned(amount) {
+            0 => value,
+            n if n >= self.word_bits => 0,
+            n => (self.unsigned(value) >> n) as i32,
+        }
+    }
+
+    /// Estimated cycles of a taken branch, including its delay slots.
+    pub fn branch_cycles(&self) -> u32 {
+        1 + self.delay_slots
+    }
+
+    /// Estimated cycles of a compare followed by a branch on its result. Compares the CPU
+    /// lacks are synthesised by the legalizer and take a few instructions more.
+    pub fn compare_branch_cycles(&self, cond: Cond) -> u32 {
+        let compare = match self.supports(cond) {
+            true => 1,
+            false => 3,
+        };
+
+        compare + self.result_latency + self.branch_cycles()
+    }
+
+    /// Number of instructions that must separate `instr` from the first reader of its result.
+    pub fn latency_of(&self, instr: &Instr) -> u32 {
+        match instr {
+            Instr::Load { .. } | Instr::LoadRom { .. } => self.load_latency,
+            Instr::Asm { body, .. } => body.iter().map(|instr| self.latency_of(instr)).max().unwrap_or(0),
+            _ => self.result_latency,
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Runs programs through the whole compiler, for the tests of the individual passes.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    emitter::Emitter,
+    errors::CompileError,
+    instructions::Instr,
+    ir_builder::IrBuilder,
+    legalizer::Legalizer,
+    lexer::Lexer,
+    modules::{Module, ModuleLoader},
+    parser::Parser,
+    register_allocator::Allocator,
+    resolver::Resolver,
+    scheduler::Scheduler,
+    simulator::Simulator,
+    source::Source,
+    source_map::SourceMap,
+    symbols::SymbolTable,
+    target::Target,
+    type_checker::TypeChecker,
+};
+
+/// What compiling and simulating a program produced.
+pub struct Run {
+    /// Instructions after legalization, before registers are allocated.
+    pub legalized: Vec<Instr>,
+    pub assembly: String,
+    pub map: String,
+    pub port_writes: Vec<i32>,
+    pub cycles: u64,
+    /// Nops the scheduler inserted.
+    pub nops: usize,
+    /// Why each `match` didn't get a jump table, in program order.
+    pub match_rejections: Vec<Option<String>>,
+}
+
+/// Compiles and simulates `source` for the target described by `target`, the default one if
+/// that is empty. Errors are returned as `message at file:line:col`, or as just the message
+/// for those found after lowering.
+pub fn run(source: &str, target: &str) -> Result<Run, String> {
+    run_files(&[("main.tch", source)], target)
+}
+
+/// Like `run`, with more files next to the program. The first file is the program, the
+/// others can be imported or embedded by it. All of them are written to a fresh directory.
+pub fn run_files(files: &[(&str, &str)], target: &str) -> Result<Run, String> {
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+    let dir: PathBuf = std::env::temp_dir().join(format!("torch-test-{}-{}", std::process::id(), NEXT_DIR.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&dir).unwrap();
+    for (name, content) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    let mut source_map = SourceMap::new();
+    source_map.add_from_file(&dir.join(files[0].0).to_string_lossy().into_owned()).unwrap();
+
+    let result = compile(&mut source_map, target);
+    fs::remove_dir_all(&dir).unwrap();
+
+    result.map_err(|failure| {
+        let err = match failure {
+            Failure::Located(err) => err,
+            Failure::Message(message) => return message,
+        };
+        let source = &source_map.files[err.source_id];
+        let (line, col) = source.get_line_col(err.position);
+        let file_name = source.file_name.rsplit(['/', '\\']).next().unwrap().to_string();
+        format!("{} at {}:{}:{}", err.message, file_name, line, col)
+    })
+}
+
+/// Why a program failed to compile, with a position in the source where it has one.
+enum Failure {
+    Located(CompileError),
+    Message(String),
+}
+
+impl From<CompileError> for Failure {
+    fn from(err: CompileError) -> Self {
+        Failure::Located(err)
+    }
+}
+
+fn compile(source_map: &mut SourceMap, target: &str) -> Result<Run, Failure> {
+    let target = match target.is_empty() {
+        true => Target::default(),
+        false => {
+            let id = source_map.add(Source::new(target.to_string(), "test.target".to_string()));
+            Target::from_source(source_map, id)?
+        }
+    };
+
+    let tokens = Lexer::new(source_map, 0).read_all()?;
+    let mut parser = Parser::new(tokens);
+    let statements = parser.parse_program()?;
+    let modules = ModuleLoader::new(source_map).load(Module::new("main", 0, statements, parser.exports))?;
+
+    let mut symbols = SymbolTable::new();
+    Resolver::new(&mut symbols, &target).resolve_program(&modules)?;
+    TypeChecker::new(&symbols).check_program(&modules)?;
+
+    let mut ir_builder = IrBuilder::new(&symbols, &target, false);
+    let instrs = ir_builder.build(&modules)?.clone();
+
+    let mut legalizer = Legalizer::new(&instrs, &ir_builder.types, &target);
+    let legalized = legalizer.legalize();
+    let allocated = Allocator::new(legalizer.units(), ir_builder.ram_homes()).allocate(&legalized);
+
+    ir_builder.place_homes(&allocated).map_err(Failure::Message)?;
+
+    let mut scheduler = Scheduler::new(&target);
+    let scheduled = scheduler.schedule(&allocated);
+
+    let emitter = Emitter::new(&ir_builder.layout, &symbols, &target);
+    let (assembly, map) = (emitter.assembly(&scheduled), emitter.map());
+
+    let mut simulator = Simulator::new(&target, &ir_builder.layout, 100_000);
+    simulator.run(&scheduled).unwrap();
+
+    Ok(Run {
+        legalized,
+        assembly,
+        map,
+        port_writes: simulator.port_writes.iter().map(|&(_, value)| value).collect(),
+        cycles: simulator.cycles,
+        nops: scheduler.nops,
+        match_rejections: ir_builder.matches.iter().map(|dispatch| dispatch.rejection.clone()).collect(),
+    })
+}
@@ -5,6 +5,9 @@ pub enum TokenType {
 
     // Literals
     NUMBER,
+    STRING,
+    /// `!embed "file"`, carrying the data of the file.
+    EMBED,
     IDENTIFIER,
     KEYWORD,
 
@@ -16,13 +19,26 @@ pub enum TokenType {
     LBRACKET,
     RBRACKET,
     COMMA,
+    COLON,
     SEMICOLON,
+    AT,
+    DOT,
+    DOTDOT,
+    DOTDOTEQ,
+    FATARROW,
 
     // Operators
     PLUS,
     MINUS,
     ASTERISK,
     SLASH,
+    PERCENT,
+    AMPERSAND,
+    PIPE,
+    CARET,
+    TILDE,
+    SHL,
+    SHR,
     EQ,
     NEQ,
     LT,
@@ -30,12 +46,32 @@ pub enum TokenType {
     GT,
     GTE,
     ASSIGN,
+    PLUSASSIGN,
+    MINUSASSIGN,
+    ASTERISKASSIGN,
+    SLASHASSIGN,
+    PERCENTASSIGN,
+    AMPERSANDASSIGN,
+    PIPEASSIGN,
+    CARETASSIGN,
+    SHLASSIGN,
+    SHRASSIGN,
+    INCREMENT,
+    DECREMENT,
+
+    // Logical
+    AND,
+    OR,
+    BANG,
 }
 
 #[derive(Clone, Debug)]
 pub enum TokenValue {
     None,
-    Number(i32),
+    Number(i64),
+    /// The ASCII codes of a string literal's characters, escapes already replaced,
+    /// or the data of an embedded file.
+    Str(Vec<u8>),
     Identifier(String),
     Keyword(Keyword),
 }
@@ -43,8 +79,25 @@ pub enum TokenValue {
 #[derive(Debug, Clone)]
 pub enum Keyword {
     LET,
+    CONST,
+    PORT,
+    IMPORT,
+    PUB,
+    ASM,
+    STRUCT,
+    ENUM,
+    MATCH,
     IF,
+    ELSE,
     WHILE,
+    FOR,
+    IN,
+    BREAK,
+    CONTINUE,
+    FN,
+    RETURN,
+    TRUE,
+    FALSE,
 }
 
 #[derive(Clone, Debug)]
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expr, ExprNode, Stmt, StmtNode},
+    errors::CompileError,
+    modules::Module,
+    symbols::{SymbolKind, SymbolTable},
+    token::TokenType,
+    types::Type,
+};
+
+/// Checks that every value is used as the type it has. Runs after the resolver, so every
+/// name is known to refer to a symbol of the right kind.
+///
+/// Variables without an annotation take the type of their initial value. Function
+/// parameters and results, array elements, struct fields and ports are integers.
+/// A `match` on a `bool` or an enum must cover every value, one on an integer needs `_`.
+pub struct TypeChecker<'a> {
+    symbols: &'a SymbolTable,
+    /// Type of every variable and constant, keyed by symbol id.
+    types: HashMap<u32, Type>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(symbols: &'a SymbolTable) -> Self {
+        TypeChecker {
+            symbols,
+            types: symbols
+                .types
+                .iter()
+                .map(|(&id, &ty)| (id, Type::Int(Some(ty))))
+                .chain(symbols.variants.iter().map(|(&id, &index)| (id, Type::Enum(index))))
+                .collect(),
+        }
+    }
+
+    pub fn check_program(mut self, modules: &[Module]) -> Result<(), CompileError> {
+        for stmt in modules.iter().flat_map(|module| &module.stmts) {
+            self.check_stmt(stmt)?;
+        }
+
+        Ok(())
+    }
+
+    fn id_of(&self, expr: &ExprNode) -> u32 {
+        self.symbols.id_at(expr.position, expr.source_id)
+    }
+
+    fn check_stmt(&mut self, stmt: &StmtNode) -> Result<(), CompileError> {
+        match &stmt.node {
+            Stmt::Declare { target, ty, value } => {
+                if self.check_struct_value(value)? {
+                    return Ok(());
+                }
+
+                let found = self.check_expr(value)?;
+
+                let ty = match ty {
+                    Some(ty) => {
+                        self.expect(*ty, found, value)?;
+                        *ty
+                    }
+                    // Declared integer types only come from annotations.
+                    None => match found {
+                        Type::Int(_) => Type::Int(None),
+                        found => found,
+                    },
+                };
+
+                self.types.insert(self.id_of(target), ty);
+            }
+
+            Stmt::Const { target, value } => {
+                let ty = match self.check_expr(value)? {
+                    Type::Int(_) => Type::Int(None),
+                    found => found,
+                };
+
+                self.types.insert(self.id_of(target), ty);
+            }
+
+            Stmt::Import { .. }
+            | Stmt::Port { .. }
+            | Stmt::Struct { .. }
+            | Stmt::Enum { .. }
+            | Stmt::Break { .. }
+            | Stmt::Continue { .. } => {}
+
+            Stmt::DeclareArray { values, .. } => {
+                for value in values.iter().flatten() {
+                    if !self.check_struct_value(value)? {
+                        self.expect_int(value)?;
+                    }
+                }
+            }
+
+            Stmt::Assign { target, value } => {
+                if self.check_struct_value(target)? {
+                    self.check_struct_value(value)?;
+                    return Ok(());
+                }
+
+                let expected = self.check_expr(target)?;
+                let found = self.check_expr(value)?;
+
+                self.expect(expected, found, value)?;
+            }
+
+            Stmt::CompoundAssign { target, value, .. } => {
+                self.expect_plain_int(target)?;
+                self.expect_int(value)?;
+            }
+
+            Stmt::If { condition, body, else_body } => {
+                self.expect_bool(condition)?;
+
+                for stmt in body.iter().chain(else_body.iter().flatten()) {
+                    self.check_stmt(stmt)?;
+                }
+            }
+
+            Stmt::Match { value, arms } => {
+                let ty = self.check_expr(value)?;
+
+                for pattern in arms.iter().flat_map(|arm| arm.node.patterns.iter().flatten()) {
+                    let found = self.check_expr(pattern)?;
+                    self.expect(ty, found, pattern)?;
+                }
+
+                if !arms.iter().any(|arm| arm.node.patterns.is_none()) {
+                    let matched: Vec<i32> = arms
+                        .iter()
+                        .flat_map(|arm| arm.node.patterns.iter().flatten())
+                        .map(|pattern| self.symbols.patterns[&(pattern.source_id, pattern.position)])
+                        .collect();
+
+                    let missing = match ty {
+                        Type::Bool => [(false, "false"), (true, "true")]
+                            .into_iter()
+                            .find(|&(b, _)| !matched.contains(&(b as i32)))
+                            .map(|(_, name)| format!("`{}` is not covered", name)),
+                        Type::Enum(index) => {
+                            let def = &self.symbols.enums[index];
+                            def.variants
+                                .iter()
+                                .find(|(_, value)| !matched.contains(value))
+                                .map(|(variant, _)| format!("`{}.{}` is not covered", def.name, variant))
+                        }
+                        Type::Int(_) => Some(format!("`{}` has too many values to list, add a `_` arm", self.name(ty))),
+                    };
+
+                    if let Some(missing) = missing {
+                        return Err(CompileError {
+                            message: format!("non-exhaustive match, {}", missing),
+                            position: stmt.position,
+                            source_id: stmt.source_id,
+                        });
+                    }
+                }
+
+                for stmt in arms.iter().flat_map(|arm| &arm.node.body) {
+                    self.check_stmt(stmt)?;
+                }
+            }
+
+            Stmt::While { condition, body, .. } => {
+                self.expect_bool(condition)?;
+
+                for stmt in body {
+                    self.check_stmt(stmt)?;
+                }
+            }
+
+            Stmt::For { var, start, end, body, .. } => {
+                self.expect_int(start)?;
+                self.expect_int(end)?;
+
+                self.types.insert(self.id_of(var), Type::Int(None));
+
+                for stmt in body {
+                    self.check_stmt(stmt)?;
+                }
+            }
+
+            Stmt::Function { body, .. } => {
+                for stmt in body {
+                    self.check_stmt(stmt)?;
+                }
+            }
+
+            Stmt::Return { value } => {
+                if let Some(value) = value {
+                    self.expect_int(value)?;
+                }
+            }
+
+            Stmt::Expression { expr } => {
+                self.check_expr(expr)?;
+            }
+
+            // Registers hold whole words, so only an integer may receive an arbitrary one.
+            Stmt::Asm { inputs, outputs, .. } => {
+                for (_, value) in inputs {
+                    self.check_expr(value)?;
+                }
+
+                for (_, target) in outputs {
+                    self.expect_plain_int(target)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_expr(&self, expr: &ExprNode) -> Result<Type, CompileError> {
+        let error = |message: String| CompileError {
+            message,
+            position: expr.position,
+            source_id: expr.source_id,
+        };
+
+        match &expr.node {
+            Expr::Number(_) => Ok(Type::Int(None)),
+            Expr::Bool(_) => Ok(Type::Bool),
+
+            Expr::Variable(_) => Ok(self.types.get(&self.id_of(expr)).copied().unwrap_or(Type::Int(None))),
+
+            Expr::Index { index, .. } => {
+                self.expect_int(index)?;
+                Ok(Type::Int(None))
+            }
+
+            Expr::Field { base, .. } => self.check_expr(base),
+
+            Expr::StructLiteral { .. } => unreachable!("struct literals are checked as values"),
+
+            // Addresses are plain integers, and what they point at is read as one.
+            Expr::AddressOf { target } => match self.check_expr(target)? {
+                Type::Int(_) => Ok(Type::Int(None)),
+                found => Err(error(format!("`&` takes the address of an integer, found `{}`", self.name(found)))),
+            },
+
+            Expr::Deref { pointer } => {
+                self.expect_int(pointer)?;
+                Ok(Type::Int(None))
+            }
+
+            Expr::Call { args, .. } => {
+                for arg in args {
+                    self.expect_int(arg)?;
+                }
+
+                Ok(Type::Int(None))
+            }
+
+            Expr::Unary { operator, operand } => {
+                let found = self.check_expr(operand)?;
+
+                match (operator, found) {
+                    (TokenType::BANG, Type::Bool) => Ok(Type::Bool),
+                    (TokenType::BANG, found) => Err(error(format!("`!` expects a `bool`, found `{}`", self.name(found)))),
+                    (_, Type::Bool) => Err(error(format!("`{}` expects an integer, found `bool`", Self::symbol(operator)))),
+                    (_, Type::Enum(_)) => Ok(Type::Int(None)),
+                    (_, found) => Ok(found),
+                }
+            }
+
+            Expr::Binary { left, operator, right } => {
+                let (l, r) = (self.check_expr(left)?, self.check_expr(right)?);
+
+                match operator {
+                    TokenType::AND | TokenType::OR => {
+                        for (found, operand) in [(l, left), (r, right)] {
+                            self.expect(Type::Bool, found, operand)?;
+                        }
+
+                        Ok(Type::Bool)
+                    }
+                    TokenType::EQ | TokenType::NEQ => match (l, r) {
+                        (Type::Bool, Type::Bool) | (Type::Int(_) | Type::Enum(_), Type::Int(_)) | (Type::Int(_), Type::Enum(_)) => Ok(Type::Bool),
+                        (Type::Enum(a), Type::Enum(b)) if a == b => Ok(Type::Bool),
+                        _ => Err(error(format!("cannot compare `{}` with `{}`", self.name(l), self.name(r)))),
+                    },
+                    TokenType::LT | TokenType::LTE | TokenType::GT | TokenType::GTE => {
+                        self.expect_int(left)?;
+                        self.expect_int(right)?;
+
+                        Ok(Type::Bool)
+                    }
+                    // Enums take part in arithmetic as plain integers.
+                    _ => match (l, r) {
+                        (Type::Int(_), Type::Int(_)) => Ok(l),
+                        (Type::Int(_) | Type::Enum(_), Type::Int(_) | Type::Enum(_)) => Ok(Type::Int(None)),
+                        _ => Err(error(format!(
+                            "`{}` expects integers, found `{}` and `{}`",
+                            Self::symbol(operator),
+                            self.name(l),
+                            self.name(r)
+                        ))),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Checks `value` if it is a whole struct, whose fields are integers, and returns whether it is.
+    fn check_struct_value(&self, value: &ExprNode) -> Result<bool, CompileError> {
+        match &value.node {
+            Expr::StructLiteral { fields, .. } => {
+                for (_, field_value) in fields {
+                    self.expect_int(field_value)?;
+                }
+
+                Ok(true)
+            }
+            Expr::Variable(_) => Ok(matches!(self.symbols.kind_of(self.id_of(value)), SymbolKind::Record { .. })),
+            Expr::Index { array, index } => match self.symbols.kind_of(self.id_of(array)) {
+                SymbolKind::Array { element: Some(_), .. } => {
+                    self.expect_int(index)?;
+                    Ok(true)
+                }
+                _ => Ok(false),
+            },
+            _ => Ok(false),
+        }
+    }
+
+    fn expect_int(&self, expr: &ExprNode) -> Result<(), CompileError> {
+        let found = self.check_expr(expr)?;
+        self.expect(Type::Int(None), found, expr)
+    }
+
+    /// Like `expect_int`, but also rejecting enums, for targets that arithmetic writes to.
+    fn expect_plain_int(&self, expr: &ExprNode) -> Result<(), CompileError> {
+        match self.check_expr(expr)? {
+            Type::Int(_) => Ok(()),
+            found => Err(CompileError {
+                message: format!("expected `integer`, found `{}`", self.name(found)),
+                position: expr.position,
+                source_id: expr.source_id,
+            }),
+        }
+    }
+
+    fn expect_bool(&self, expr: &ExprNode) -> Result<(), CompileError> {
+        let found = self.check_expr(expr)?;
+        self.expect(Type::Bool, found, expr)
+    }
+
+    /// Integers of any width are compatible with each other, the resolver checks their ranges.
+    /// Enums are accepted where integers are expected, but not the other way around.
+    fn expect(&self, expected: Type, found: Type, expr: &ExprNode) -> Result<(), CompileError> {
+        match (expected, found) {
+            (Type::Bool, Type::Bool) | (Type::Int(_), Type::Int(_) | Type::Enum(_)) => Ok(()),
+            (Type::Enum(a), Type::Enum(b)) if a == b => Ok(()),
+            _ => Err(CompileError {
+                message: format!("expected `{}`, found `{}`", self.name(expected), self.name(found)),
+                position: expr.position,
+                source_id: expr.source_id,
+            }),
+        }
+    }
+
+    /// Name of `ty` in error messages, enums are called by their declared name.
+    fn name(&self, ty: Type) -> String {
+        match ty {
+            Type::Enum(index) => self.symbols.enums[index].name.clone(),
+            ty => ty.name(),
+        }
+    }
+
+    fn symbol(operator: &TokenType) -> &'static str {
+        match operator {
+            TokenType::PLUS => "+",
+            TokenType::MINUS => "-",
+            TokenType::ASTERISK => "*",
+            TokenType::SLASH => "/",
+            TokenType::PERCENT => "%",
+            TokenType::AMPERSAND => "&",
+            TokenType::PIPE => "|",
+            TokenType::CARET => "^",
+            TokenType::TILDE => "~",
+            TokenType::SHL => "<<",
+            TokenType::SHR => ">>",
+            _ => "operator",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::run;
+
+    #[test]
+    fn constants_may_name_constants() {
+        let run = run("port out @ 200;\nconst W = 8;\nconst H = W * 2;\nconst DONE = true;\nconst AGAIN = DONE;\nif AGAIN { out = H; }\n", "").unwrap();
+        assert_eq!(run.port_writes, vec![16]);
+    }
+
+    #[test]
+    fn constants_keep_the_type_of_the_constants_they_name() {
+        let err = run("const DONE = true;\nconst AGAIN = DONE;\nlet x = AGAIN + 1;\n", "").err().unwrap();
+        assert!(err.starts_with("`+` expects integers, found `bool` and `integer`"), "{}", err);
+    }
+}
@@ -0,0 +1,74 @@
+/// An integer type such as `u8` or `i16`. Values wrap around at its width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl IntType {
+    /// Parses names of the form `u<bits>` and `i<bits>` with 1 to 32 bits.
+    pub fn from_name(name: &str) -> Option<IntType> {
+        let signed = match name.as_bytes().first()? {
+            b'u' => false,
+            b'i' => true,
+            _ => return None,
+        };
+
+        match name[1..].parse::<u32>() {
+            Ok(bits @ 1..=32) if !name[1..].starts_with('0') => Some(IntType { signed, bits }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}{}", if self.signed { 'i' } else { 'u' }, self.bits)
+    }
+
+    pub fn min(&self) -> i64 {
+        match self.signed {
+            true => -(1i64 << (self.bits - 1)),
+            false => 0,
+        }
+    }
+
+    pub fn max(&self) -> i64 {
+        match self.signed {
+            true => (1i64 << (self.bits - 1)) - 1,
+            false => (1i64 << self.bits) - 1,
+        }
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        (self.min()..=self.max()).contains(&value)
+    }
+}
+
+/// Type of a value as seen by the type checker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    /// `true` or `false`, which comparisons produce and conditions require.
+    Bool,
+    /// An integer of the given type, or a plain machine word without one.
+    Int(Option<IntType>),
+    /// A variant of the enum with this index into `SymbolTable::enums`. Like in C, it can be
+    /// used as an integer, but integers can't be used as it.
+    Enum(usize),
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "bool" => Some(Type::Bool),
+            _ => IntType::from_name(name).map(|ty| Type::Int(Some(ty))),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Type::Bool => "bool".to_string(),
+            Type::Int(Some(ty)) => ty.name(),
+            Type::Int(None) => "integer".to_string(),
+            Type::Enum(_) => "enum".to_string(),
+        }
+    }
+}